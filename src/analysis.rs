@@ -0,0 +1,184 @@
+//! Analysis of newly captured snapshots, off the audio thread.
+//!
+//! Analyzers are registered once and then run on a worker thread for each
+//! snapshot the audio thread submits. Their latest results are kept by name for
+//! anyone interested (editors, features adapting to the material) to read.
+
+use std::collections::HashMap;
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+use vst::util::AtomicFloat;
+
+use crate::dsp;
+
+// how often an idle worker checks for a new snapshot and whether the pipeline is
+// still around, waking it from the audio thread could block there
+const WORKER_POLL: Duration = Duration::from_millis(20);
+
+/// Something worth knowing about a snapshot.
+pub trait Analyzer: Send {
+    /// name the results are published under
+    fn name(&self) -> &'static str;
+
+    /// Analyzes a snapshot, the meaning of the returned values is up to the analyzer.
+    fn analyze(&mut self, snapshot: &[f32], sample_rate: f32) -> Vec<f32>;
+}
+
+/// Root mean square level of the snapshot, as a single value.
+pub struct Rms;
+
+impl Analyzer for Rms {
+    fn name(&self) -> &'static str {
+        "rms"
+    }
+
+    fn analyze(&mut self, snapshot: &[f32], _sample_rate: f32) -> Vec<f32> {
+        if snapshot.is_empty() {
+            return vec![0.0]
+        }
+        let sum = snapshot.iter().map(|&sample| sample as f64 * sample as f64).sum::<f64>();
+        vec![(sum / snapshot.len() as f64).sqrt() as f32]
+    }
+}
+
+pub struct AnalysisPipeline {
+    analyzers: Mutex<Vec<Box<dyn Analyzer>>>,
+    // lets the audio thread skip the copy while nothing is registered
+    active: AtomicBool,
+    sample_rate: AtomicFloat,
+    input: Mutex<Vec<f32>>,
+    // whether `input` hasn't been picked up by the worker yet
+    fresh: AtomicBool,
+    results: Mutex<HashMap<&'static str, Vec<f32>>>,
+    // incremented after each analyzed snapshot
+    generation: AtomicUsize,
+}
+
+impl Default for AnalysisPipeline {
+    fn default() -> Self {
+        Self {
+            analyzers: Mutex::new(Vec::new()),
+            active: AtomicBool::new(false),
+            sample_rate: AtomicFloat::new(44_100.0),
+            input: Mutex::new(Vec::new()),
+            fresh: AtomicBool::new(false),
+            results: Mutex::new(HashMap::new()),
+            generation: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl AnalysisPipeline {
+    /// Adds an analyzer run on every snapshot captured from now on, the worker
+    /// thread starts with the first one.
+    pub fn register(self: &Arc<Self>, analyzer: Box<dyn Analyzer>) {
+        let mut analyzers = self.analyzers.lock().unwrap();
+        analyzers.push(analyzer);
+        if !self.active.swap(true, Ordering::AcqRel) {
+            self.input.lock().unwrap().reserve(self.max_snapshot_len());
+            let pipeline = Arc::downgrade(self);
+            thread::spawn(move || run_worker(pipeline));
+        }
+    }
+
+    /// The latest result of the analyzer registered under `name`.
+    pub fn result(&self, name: &str) -> Option<Vec<f32>> {
+        self.results.lock().unwrap().get(name).cloned()
+    }
+
+    /// How many snapshots have been analyzed, to tell when results are new.
+    pub fn generation(&self) -> usize {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn set_sample_rate(&self, rate: f32) {
+        self.sample_rate.set(rate);
+        if self.active.load(Ordering::Acquire) {
+            let mut input = self.input.lock().unwrap();
+            let len = input.len();
+            input.reserve(self.max_snapshot_len().saturating_sub(len));
+        }
+    }
+
+    // longest snapshot that can be submitted, the audio thread never allocates for a copy
+    fn max_snapshot_len(&self) -> usize {
+        dsp::max_snapshot_len(self.sample_rate.get())
+    }
+
+    // called on the audio thread, never blocks or allocates, a snapshot arriving
+    // while the worker is busy replaces the one waiting, `gain` brings it to full scale
+    pub(crate) fn submit(&self, snapshot: &[f32], gain: f32) {
+        if !self.active.load(Ordering::Acquire) {
+            return
+        }
+        if let Ok(mut input) = self.input.try_lock() {
+            if snapshot.len() <= input.capacity() {
+                input.clear();
+                input.extend(snapshot.iter().map(|sample| sample * gain));
+                self.fresh.store(true, Ordering::Release);
+            }
+        }
+    }
+
+    // the next submitted snapshot, swapped into `work` so both keep their capacity
+    fn wait_for_snapshot(&self, work: &mut Vec<f32>) -> bool {
+        // keeps up with the sample rate, whatever is in `work` has been analyzed already
+        work.clear();
+        work.reserve(self.max_snapshot_len());
+        if !self.fresh.load(Ordering::Acquire) {
+            thread::sleep(WORKER_POLL);
+            return false
+        }
+        let mut input = self.input.lock().unwrap();
+        self.fresh.store(false, Ordering::Release);
+        mem::swap(&mut *input, work);
+        true
+    }
+
+    fn analyze(&self, snapshot: &[f32]) {
+        let sample_rate = self.sample_rate.get();
+        let results = self.analyzers.lock().unwrap()
+            .iter_mut()
+            .map(|analyzer| (analyzer.name(), analyzer.analyze(snapshot, sample_rate)))
+            .collect::<Vec<_>>();
+        self.results.lock().unwrap().extend(results);
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
+}
+
+// keeps going as long as the plugin holds on to the pipeline
+fn run_worker(pipeline: Weak<AnalysisPipeline>) {
+    let mut work = Vec::new();
+    while let Some(pipeline) = pipeline.upgrade() {
+        if pipeline.wait_for_snapshot(&mut work) {
+            pipeline.analyze(&work);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn analyzes_submitted_snapshots() {
+        let pipeline = Arc::new(AnalysisPipeline::default());
+        // nothing registered, nothing happens
+        pipeline.submit(&[1.0; 16], 1.0);
+        assert_eq!(pipeline.result("rms"), None);
+
+        pipeline.register(Box::new(Rms));
+        pipeline.submit(&[0.25, -0.25, 0.25, -0.25], 2.0);
+        let start = Instant::now();
+        while pipeline.generation() == 0 {
+            assert!(start.elapsed() < Duration::from_secs(5), "analysis never finished");
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(pipeline.result("rms"), Some(vec![0.5]));
+    }
+}
@@ -0,0 +1,430 @@
+//! Saving the snapshot history of every channel to a bank file and loading it back.
+//!
+//! A bank is a RIFF/WAVE file with the slots (the snapshots kept for
+//! *History*, newest first) one after another, so it plays in any audio
+//! editor, and a `slot` chunk with how long each slot is in each channel. The
+//! files are written and read on a worker thread, which also prepares a loaded
+//! bank for playback. For a save the audio thread only tells how long the kept
+//! slots are, then copies them a chunk per block into buffers the worker
+//! allocated. It swaps a loaded bank in at the start of the next processed
+//! block. How it went is kept in a `FileStatus`.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use vst::util::AtomicFloat;
+
+use crate::dsp::{max_snapshot_len, ChannelState, Snapshot, HISTORY_LEN};
+use crate::import::{resample, FileStatus, Pending};
+use crate::wav::{self, Wav};
+
+// how long a save waits for the audio thread to get on with copying the history
+const HAND_OVER_TIMEOUT: Duration = Duration::from_secs(2);
+// how often the worker looks whether the audio thread got on with it
+const HAND_OVER_POLL: Duration = Duration::from_millis(10);
+// samples of the history copied for a save in each block
+const SAVE_CHUNK: usize = 1 << 16;
+const SLOT_CHUNK: [u8; 4] = *b"slot";
+
+/// the slots of each channel, newest first, at full scale
+pub(crate) type Slots = Vec<Vec<Vec<f32>>>;
+
+pub struct SnapshotBank {
+    // sample rate the bank is saved at and loaded banks are converted to
+    sample_rate: AtomicFloat,
+    // number of channels a loaded bank is mapped to
+    channel_count: AtomicUsize,
+    // the bank last saved or loaded, stored with the plugin state
+    path: Mutex<Option<PathBuf>>,
+    // set while a save needs the audio thread, so it doesn't go for the lock otherwise
+    saving: AtomicBool,
+    save: Mutex<Save>,
+    // a loaded bank waiting for the audio thread, the slots of each channel newest first
+    pending: Pending<Vec<Vec<Snapshot>>>,
+    status: Mutex<FileStatus>,
+}
+
+impl Default for SnapshotBank {
+    fn default() -> Self {
+        Self {
+            sample_rate: AtomicFloat::new(44_100.0),
+            channel_count: AtomicUsize::new(2),
+            path: Mutex::new(None),
+            saving: AtomicBool::new(false),
+            save: Mutex::new(Save::Idle),
+            pending: Pending::default(),
+            status: Mutex::new(FileStatus::Idle),
+        }
+    }
+}
+
+impl SnapshotBank {
+    /// Writes the snapshot history of every channel to a bank file on a worker
+    /// thread, once the audio thread has copied it over the next few blocks.
+    /// The result is also kept for `status`.
+    pub fn save(self: &Arc<Self>, path: PathBuf) -> thread::JoinHandle<io::Result<()>> {
+        *self.path.lock().unwrap() = Some(path.clone());
+        *self.save.lock().unwrap() = Save::Requested;
+        *self.status.lock().unwrap() = FileStatus::Busy;
+        self.saving.store(true, Ordering::Release);
+        let this = Arc::clone(self);
+        thread::spawn(move || {
+            let result = match this.wait_for_history() {
+                Some(slots) => File::create(&path)
+                    .and_then(|file| encode(BufWriter::new(file), &slots, this.sample_rate.get() as u32)),
+                None => Err(io::Error::new(io::ErrorKind::TimedOut, "no audio is being processed")),
+            };
+            *this.status.lock().unwrap() = FileStatus::of(&result);
+            result
+        })
+    }
+
+    /// Reads a bank file on a worker thread, once done its slots replace the
+    /// history of every channel, the newest one playing. The result is also
+    /// kept for `status`.
+    pub fn load(self: &Arc<Self>, path: PathBuf) -> thread::JoinHandle<io::Result<()>> {
+        *self.path.lock().unwrap() = Some(path.clone());
+        *self.status.lock().unwrap() = FileStatus::Busy;
+        let this = Arc::clone(self);
+        thread::spawn(move || {
+            let result = this.read(&path).map(|slots| this.pending.put(slots));
+            *this.status.lock().unwrap() = FileStatus::of(&result);
+            result
+        })
+    }
+
+    /// The bank last saved or loaded.
+    pub fn path(&self) -> Option<PathBuf> {
+        self.path.lock().unwrap().clone()
+    }
+
+    /// How the last save or load went.
+    pub fn status(&self) -> FileStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    pub(crate) fn set_sample_rate(&self, rate: f32) {
+        self.sample_rate.set(rate);
+    }
+
+    pub(crate) fn set_channel_count(&self, count: usize) {
+        self.channel_count.store(count, Ordering::Relaxed);
+    }
+
+    // called on the audio thread at the start of a block, measures the history
+    // for a save or copies the next chunk of it, never blocks or allocates, if
+    // the worker has the lock it carries on next time
+    pub(crate) fn copy_history(&self, states: &[ChannelState]) {
+        if !self.saving.load(Ordering::Acquire) {
+            return
+        }
+        let mut save = match self.save.try_lock() {
+            Ok(save) => save,
+            Err(_) => return,
+        };
+        match &mut *save {
+            Save::Requested => *save = Save::Measured(Layout::measure(states)),
+            Save::Copying(copy) => if copy.step(states) {
+                let slots = mem::take(&mut copy.slots);
+                *save = Save::Copied(slots);
+            },
+            _ => (),
+        }
+    }
+
+    // called on the audio thread with the slots of a loaded bank, if there are any
+    pub(crate) fn take_pending(&self, load: impl FnOnce(&mut Vec<Vec<Snapshot>>)) {
+        self.pending.take(load)
+    }
+
+    // allocates the buffers once the history is measured and waits for them to
+    // be filled, gives up if the audio thread doesn't get on with it
+    fn wait_for_history(&self) -> Option<Slots> {
+        let mut progress = None;
+        let mut waited = Duration::from_secs(0);
+        let slots = loop {
+            let mut save = self.save.lock().unwrap();
+            match &*save {
+                &Save::Measured(layout) => {
+                    drop(save);
+                    let copy = HistoryCopy::new(layout);
+                    save = self.save.lock().unwrap();
+                    *save = Save::Copying(copy);
+                },
+                Save::Copied(_) => if let Save::Copied(slots) = mem::replace(&mut *save, Save::Idle) {
+                    break Some(slots)
+                },
+                _ => (),
+            }
+            if progress != Some(save.progress()) {
+                progress = Some(save.progress());
+                waited = Duration::from_secs(0);
+            } else if waited >= HAND_OVER_TIMEOUT {
+                *save = Save::Idle;
+                break None
+            }
+            drop(save);
+            thread::sleep(HAND_OVER_POLL);
+            waited += HAND_OVER_POLL;
+        };
+        self.saving.store(false, Ordering::Release);
+        slots
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<Vec<Snapshot>>> {
+        let (sample_rate, mut slots) = decode(BufReader::new(File::open(path)?))?;
+        let ratio = self.sample_rate.get() as f64 / sample_rate as f64;
+
+        // a narrower bank repeats its last channel
+        let last = slots.last().cloned().unwrap_or_default();
+        slots.resize(self.channel_count.load(Ordering::Relaxed), last);
+
+        let max_len = max_snapshot_len(self.sample_rate.get());
+        Ok(slots.iter()
+            .map(|chan| chan.iter().map(|slot| Snapshot::prepare(&resample(slot, ratio), max_len)).collect())
+            .collect())
+    }
+}
+
+enum Save {
+    Idle,
+    // the audio thread measures the history at the start of the next block
+    Requested,
+    // waiting for the worker to allocate the buffers
+    Measured(Layout),
+    // the audio thread copies a chunk per block
+    Copying(HistoryCopy),
+    // waiting for the worker to write them
+    Copied(Slots),
+}
+
+impl Save {
+    // changes whenever the audio thread got on with it
+    fn progress(&self) -> usize {
+        match self {
+            Save::Idle | Save::Requested => 0,
+            Save::Measured(_) => 1,
+            Save::Copying(copy) => 2 + copy.copied,
+            Save::Copied(_) => usize::MAX,
+        }
+    }
+}
+
+// which slots each channel kept and how long they are, taken on the audio thread
+#[derive(Clone, Copy, Default)]
+struct Layout {
+    channels: usize,
+    // how many snapshots the channel had pushed, to tell whether a slot is still kept
+    pushed: [usize; crate::MAX_CHANNELS],
+    // slot lengths, newest first, zero past the ones kept
+    lens: [[usize; HISTORY_LEN]; crate::MAX_CHANNELS],
+}
+
+impl Layout {
+    fn measure(states: &[ChannelState]) -> Self {
+        let mut layout = Self { channels: states.len().min(crate::MAX_CHANNELS), ..Self::default() };
+        for (chan, state) in states.iter().take(layout.channels).enumerate() {
+            layout.pushed[chan] = state.pushed();
+            layout.lens[chan].iter_mut().zip(state.history_lens()).for_each(|(len, slot)| *len = slot);
+        }
+        layout
+    }
+}
+
+// the history being copied over, the slots are allocated by the worker so the
+// audio thread only fills them
+struct HistoryCopy {
+    layout: Layout,
+    slots: Slots,
+    // the slot being copied
+    chan: usize,
+    back: usize,
+    // samples copied so far
+    copied: usize,
+}
+
+impl HistoryCopy {
+    fn new(layout: Layout) -> Self {
+        let slots = layout.lens[..layout.channels].iter()
+            .map(|lens| lens.iter().take_while(|&&len| len > 0).map(|&len| Vec::with_capacity(len)).collect())
+            .collect();
+        Self { layout, slots, chan: 0, back: 0, copied: 0 }
+    }
+
+    // copies up to `SAVE_CHUNK` samples, true once all are, a slot replaced
+    // since it was measured is left empty
+    fn step(&mut self, states: &[ChannelState]) -> bool {
+        let mut budget = SAVE_CHUNK;
+        while budget > 0 && self.chan < self.layout.channels {
+            let slots = &mut self.slots[self.chan];
+            let slot = match slots.get_mut(self.back) {
+                Some(slot) => slot,
+                None => {
+                    self.chan += 1;
+                    self.back = 0;
+                    continue
+                },
+            };
+            let (pushed, len) = (self.layout.pushed[self.chan], self.layout.lens[self.chan][self.back]);
+            let before = slot.len();
+            let count = usize::min(budget, len - before);
+            let back = self.back;
+            let kept = states.get(self.chan).map_or(false, |state| state.copy_history(pushed, back, slot, count));
+            let copied = slot.len() - before;
+            if !kept {
+                // cleared without freeing, the worker drops it
+                slot.clear();
+            }
+            if !kept || copied == 0 || slot.len() >= len {
+                self.back += 1;
+            }
+            budget -= copied;
+            self.copied += copied;
+        }
+        self.chan >= self.layout.channels
+    }
+}
+
+// the slots of each channel one after another, each slot as long as its longest channel
+fn encode(writer: impl Write, slots: &Slots, sample_rate: u32) -> io::Result<()> {
+    let slot_count = slots.iter().map(Vec::len).max().unwrap_or(0);
+    let mut channels = vec![Vec::new(); slots.len()];
+    let mut lengths = Vec::new();
+    lengths.extend_from_slice(&(slot_count as u32).to_le_bytes());
+    lengths.extend_from_slice(&(slots.len() as u32).to_le_bytes());
+    for slot in 0..slot_count {
+        let slot_len = slots.iter().map(|chan| chan.get(slot).map_or(0, Vec::len)).max().unwrap_or(0);
+        for (chan, samples) in slots.iter().zip(&mut channels) {
+            let snapshot = chan.get(slot).map_or(&[][..], Vec::as_slice);
+            lengths.extend_from_slice(&(snapshot.len() as u32).to_le_bytes());
+            samples.extend_from_slice(snapshot);
+            samples.resize(samples.len() + slot_len - snapshot.len(), 0.0);
+        }
+    }
+    wav::write_with_chunks(writer, &Wav { sample_rate, channels }, &[(SLOT_CHUNK, lengths)])
+}
+
+// (sample rate, slots of each channel), a plain WAV file is a single slot
+fn decode(reader: impl Read) -> io::Result<(u32, Slots)> {
+    let (wav, chunks) = wav::read_with_chunks(reader)?;
+    let lengths = match chunks.iter().find(|(id, _)| *id == SLOT_CHUNK) {
+        Some((_, body)) => body.chunks_exact(4)
+            .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+            .collect::<Vec<_>>(),
+        None => return Ok((wav.sample_rate, wav.channels.into_iter().map(|chan| vec![chan]).collect())),
+    };
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "broken slot chunk");
+    let (slot_count, channel_count) = match lengths.as_slice() {
+        [slot_count, channel_count, ..] if *channel_count == wav.channels.len() => (*slot_count, *channel_count),
+        _ => return Err(invalid()),
+    };
+    let lengths = &lengths[2..];
+    if lengths.len() != slot_count * channel_count {
+        return Err(invalid())
+    }
+
+    let mut slots = vec![Vec::new(); channel_count];
+    let mut start = 0;
+    for slot_lengths in lengths.chunks_exact(channel_count) {
+        let slot_len = slot_lengths.iter().copied().max().unwrap_or(0);
+        for ((chan, samples), &len) in slots.iter_mut().zip(&wav.channels).zip(slot_lengths) {
+            let snapshot = samples.get(start..start + len).ok_or_else(invalid)?;
+            chan.push(snapshot.to_vec());
+        }
+        start += slot_len;
+    }
+    Ok((wav.sample_rate, slots))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slots_survive_the_file() {
+        let slots = vec![
+            vec![vec![0.5; 10], vec![0.25; 4]],
+            // channels decide on their own, so the slots can differ
+            vec![vec![-0.5; 6]],
+        ];
+        let mut file = Vec::new();
+        encode(&mut file, &slots, 48_000).unwrap();
+        let (sample_rate, decoded) = decode(file.as_slice()).unwrap();
+        assert_eq!(sample_rate, 48_000);
+        assert_eq!(decoded, vec![slots[0].clone(), vec![vec![-0.5; 6], vec![]]]);
+
+        // a plain WAV file is a single slot
+        let wav = Wav { sample_rate: 44_100, channels: vec![vec![0.5; 8]] };
+        let mut file = Vec::new();
+        wav::write(&mut file, &wav).unwrap();
+        assert_eq!(decode(file.as_slice()).unwrap(), (44_100, vec![vec![vec![0.5; 8]]]));
+    }
+
+    #[test]
+    fn history_is_copied_a_chunk_per_block() {
+        let long = SAVE_CHUNK + 10;
+        let mut states = vec![ChannelState::default(), ChannelState::default()];
+        for (chan, state) in states.iter_mut().enumerate() {
+            for len in [4, 6].iter().chain(&[long; HISTORY_LEN - 2]) {
+                state.load_snapshot(&mut Snapshot::prepare(&vec![0.5 / (chan + 1) as f32; *len], long), *len);
+            }
+        }
+        let mut copy = HistoryCopy::new(Layout::measure(&states));
+        assert!(!copy.step(&states));
+        assert_eq!(copy.copied, SAVE_CHUNK);
+
+        // the oldest slot of the first channel gets replaced halfway
+        states[0].load_snapshot(&mut Snapshot::prepare(&[1.0; 8], long), 8);
+        let mut blocks = 1;
+        while !copy.step(&states) {
+            blocks += 1;
+        }
+        assert_eq!(blocks, (2 * (HISTORY_LEN - 2) * long + 20) / SAVE_CHUNK);
+        let lens: Vec<Vec<usize>> = copy.slots.iter().map(|chan| chan.iter().map(Vec::len).collect()).collect();
+        let mut expected = vec![long; HISTORY_LEN - 2];
+        expected.extend_from_slice(&[6, 4]);
+        assert_eq!(lens[1], expected);
+        let expected_full = expected.clone();
+        expected[HISTORY_LEN - 1] = 0;
+        assert_eq!(lens[0], expected);
+        assert!(copy.slots[1].iter().flatten().all(|&sample| sample == 0.25));
+
+        // the worker writes what the audio thread copied
+        let bank = Arc::new(SnapshotBank::default());
+        let path = std::env::temp_dir().join(format!("snapshot_repeat_save_{}.wav", std::process::id()));
+        let save = bank.save(path.clone());
+        while !save.is_finished() {
+            bank.copy_history(&states);
+            thread::sleep(Duration::from_millis(1));
+        }
+        save.join().unwrap().unwrap();
+        let (_, slots) = decode(BufReader::new(File::open(&path).unwrap())).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(slots[0][0], [1.0; 8]);
+        assert_eq!(slots[1].iter().map(Vec::len).collect::<Vec<_>>(), expected_full);
+    }
+
+    #[test]
+    fn loads_prepared_or_reported() {
+        let bank = Arc::new(SnapshotBank::default());
+        bank.set_channel_count(1);
+        let path = std::env::temp_dir().join(format!("snapshot_repeat_bank_{}.wav", std::process::id()));
+        encode(File::create(&path).unwrap(), &vec![vec![vec![0.5; 10], vec![0.25; 4]]], 44_100).unwrap();
+        bank.load(path.clone()).join().unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(bank.status(), FileStatus::Done);
+        let mut lengths = Vec::new();
+        bank.take_pending(|slots| lengths = slots[0].iter().map(Snapshot::len).collect());
+        assert_eq!(lengths, [10, 4]);
+
+        let err = bank.load(path).join().unwrap().unwrap_err();
+        assert_eq!(bank.status(), FileStatus::Failed(err.to_string()));
+        bank.take_pending(|_| panic!("nothing to load"));
+    }
+}
@@ -0,0 +1,14 @@
+//! Helpers shared by the binaries hosting the plugin outside of a DAW.
+
+use vst::plugin::PluginParameters;
+
+/// Finds a parameter either by its index or by its (case insensitive) name
+/// with spaces replaced by `_`.
+pub fn find_parameter(params: &dyn PluginParameters, count: i32, query: &str) -> Option<i32> {
+    if let Ok(index) = query.parse::<i32>() {
+        return Some(index).filter(|index| (0..count).contains(index));
+    }
+    (0..count).find(|&index| {
+        params.get_parameter_name(index).replace(' ', "_").eq_ignore_ascii_case(query)
+    })
+}
@@ -0,0 +1,239 @@
+//! Offline renderer processing a WAV file through the plugin.
+//!
+//! ```text
+//! snapshot_repeat_render <input.wav> <output.wav> [options]
+//!
+//!     --set <param>=<value>   set a parameter (name or index) to a value in 0 ..= 1
+//!     --automation <file>     apply parameter changes from a CSV file with
+//!                             `seconds,param,value` lines
+//!     --import <file>         start with a WAV file loaded as the snapshot
+//!     --groove <file>         apply a groove template to the synced periods
+//!     --block-size <samples>  size of the processed blocks (default 512)
+//!     --tail <seconds>        silence appended to the input (default 0)
+//! ```
+//!
+//! `--help` also lists the parameters with what each of them does.
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::process;
+
+use snapshot_repeat::wav::{self, Wav};
+use snapshot_repeat::{SnapshotRepeatPlugin, Trigger};
+use vst::host::HostBuffer;
+use vst::plugin::{Plugin, PluginParameters};
+
+mod common;
+use common::find_parameter;
+
+struct Options {
+    input: String,
+    output: String,
+    // (param, value) set before rendering
+    params: Vec<(String, f32)>,
+    automation: Option<String>,
+    import: Option<String>,
+    groove: Option<String>,
+    block_size: usize,
+    tail: f32,
+}
+
+struct Automation {
+    sample: usize,
+    index: i32,
+    value: f32,
+}
+
+fn main() {
+    if let Err(err) = parse_args().and_then(|options| render(&options)) {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn parse_args() -> Result<Options, Box<dyn Error>> {
+    let mut args = std::env::args().skip(1);
+    let mut positional = Vec::new();
+    let mut options = Options {
+        input: String::new(),
+        output: String::new(),
+        params: Vec::new(),
+        automation: None,
+        import: None,
+        groove: None,
+        block_size: 512,
+        tail: 0.0,
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("missing value for `{}`", arg));
+        match arg.as_str() {
+            "--set" => {
+                let value = value()?;
+                let (param, val) = split_assignment(&value)
+                    .ok_or_else(|| format!("expected `<param>=<value>`, got `{}`", value))?;
+                options.params.push((param.to_string(), val.parse()?));
+            }
+            "--automation" => options.automation = Some(value()?),
+            "--import" => options.import = Some(value()?),
+            "--groove" => options.groove = Some(value()?),
+            "--block-size" => options.block_size = value()?.parse()?,
+            "--tail" => options.tail = value()?.parse()?,
+            "-h" | "--help" => {
+                eprintln!("usage: snapshot_repeat_render <input.wav> <output.wav> [--set <param>=<value>]... [--automation <file.csv>] [--import <file.wav>] [--groove <file>] [--block-size <samples>] [--tail <seconds>]");
+                print_parameters();
+                process::exit(0);
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown option `{}`", arg).into()),
+            _ => positional.push(arg),
+        }
+    }
+
+    if positional.len() != 2 {
+        return Err("expected an input and an output file".into());
+    }
+    options.output = positional.pop().unwrap();
+    options.input = positional.pop().unwrap();
+    if options.block_size == 0 {
+        return Err("block size must be positive".into());
+    }
+    Ok(options)
+}
+
+// the names `--set` and the automation accept, with what they do
+fn print_parameters() {
+    let mut plugin = SnapshotRepeatPlugin::default();
+    let params = plugin.get_parameter_object();
+    eprintln!();
+    eprintln!("parameters:");
+    for index in 0..plugin.get_info().parameters {
+        eprintln!(
+            "    {:<22}{}",
+            params.get_parameter_name(index).replace(' ', "_").to_lowercase(),
+            SnapshotRepeatPlugin::parameter_description(index).unwrap_or(""),
+        );
+    }
+}
+
+fn split_assignment(text: &str) -> Option<(&str, &str)> {
+    let eq = text.find('=')?;
+    Some((text[..eq].trim(), text[eq + 1..].trim()))
+}
+
+fn parameter(params: &dyn PluginParameters, count: i32, name: &str, value: f32) -> Result<(i32, f32), Box<dyn Error>> {
+    let index = find_parameter(params, count, name)
+        .ok_or_else(|| format!("unknown parameter `{}`", name))?;
+    if !(0.0..=1.0).contains(&value) {
+        return Err(format!("value {} of `{}` is out of the 0 ..= 1 range", value, name).into());
+    }
+    Ok((index, value))
+}
+
+fn read_automation(
+    path: &str,
+    params: &dyn PluginParameters,
+    count: i32,
+    sample_rate: u32,
+) -> Result<Vec<Automation>, Box<dyn Error>> {
+    let mut automation = Vec::new();
+    for (line_idx, line) in fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields = line.split(',').map(str::trim).collect::<Vec<_>>();
+        let time = match fields.as_slice() {
+            [time, _, _] => time.parse::<f32>(),
+            _ => return Err(format!("{}:{}: expected `seconds,param,value`", path, line_idx + 1).into()),
+        };
+        let time = match time {
+            Ok(time) => time,
+            // allow a header line
+            Err(_) if automation.is_empty() && line_idx == 0 => continue,
+            Err(err) => return Err(format!("{}:{}: {}", path, line_idx + 1, err).into()),
+        };
+        let (index, value) = parameter(params, count, fields[1], fields[2].parse()?)?;
+        automation.push(Automation {
+            sample: (time.max(0.0) * sample_rate as f32).round() as usize,
+            index,
+            value,
+        });
+    }
+    // stable, so changes at the same time are applied in file order
+    automation.sort_by_key(|point| point.sample);
+    Ok(automation)
+}
+
+fn render(options: &Options) -> Result<(), Box<dyn Error>> {
+    let mut input = wav::read(BufReader::new(File::open(&options.input)?))?;
+
+    let mut plugin = SnapshotRepeatPlugin::default();
+    plugin.set_sample_rate(input.sample_rate as f32);
+    let info = plugin.get_info();
+    let params = plugin.get_parameter_object();
+    // the plugin adapts to the number of channels it's given, up to its maximum
+    let channels = usize::min(input.channels.len(), info.outputs as usize);
+
+    for (name, value) in &options.params {
+        let (index, value) = parameter(&*params, info.parameters, name, *value)?;
+        params.set_parameter(index, value);
+    }
+    if let Some(path) = &options.import {
+        // the import runs on a worker thread, wait for it so it's there from the first sample
+        plugin.snapshot_import().import(path.into())
+            .join()
+            .map_err(|_| "snapshot import panicked")?
+            .map_err(|err| format!("failed to import {}: {}", path, err))?;
+    }
+    if let Some(path) = &options.groove {
+        plugin.groove().load(path.as_ref())?;
+    }
+    let automation = match &options.automation {
+        Some(path) => read_automation(path, &*params, info.parameters, input.sample_rate)?,
+        None => Vec::new(),
+    };
+
+    if input.channels.len() > channels {
+        eprintln!("warning: only the first {} of {} channels are processed", channels, input.channels.len());
+    }
+    input.channels.truncate(channels);
+    let len = input.len() + (options.tail * input.sample_rate as f32) as usize;
+    input.channels.iter_mut().for_each(|chan| chan.resize(len, 0.0));
+
+    let mut output = Wav {
+        sample_rate: input.sample_rate,
+        channels: vec![vec![0.0; len]; channels],
+    };
+
+    let mut host_buffer = HostBuffer::new(channels, channels);
+    let mut automation = automation.iter().peekable();
+    let mut pos = 0;
+    while pos < len {
+        while automation.peek().map_or(false, |point| point.sample <= pos) {
+            let point = automation.next().unwrap();
+            params.set_parameter(point.index, point.value);
+        }
+
+        // changes inside the block are queued to land on their sample, the
+        // block ends early at the first one that doesn't fit in the queue
+        let mut end = usize::min(pos + options.block_size, len);
+        while let Some(&point) = automation.peek().filter(|point| point.sample < end) {
+            if !plugin.trigger(point.sample - pos, Trigger::Parameter(point.index, point.value)) {
+                end = point.sample;
+                break
+            }
+            automation.next();
+        }
+
+        let inputs = input.channels.iter().map(|chan| &chan[pos..end]).collect::<Vec<_>>();
+        let mut outputs = output.channels.iter_mut().map(|chan| &mut chan[pos..end]).collect::<Vec<_>>();
+        let mut buffer = host_buffer.bind(&inputs, &mut outputs);
+        plugin.process(&mut buffer);
+
+        pos = end;
+    }
+
+    wav::write(BufWriter::new(File::create(&options.output)?), &output)?;
+    Ok(())
+}
@@ -0,0 +1,231 @@
+//! Standalone host running the effect on live audio without a DAW.
+//!
+//! Audio is read from the default input device, processed by the plugin and
+//! written to the default output device. Parameters are controlled with a tiny
+//! line based interface on stdin, type `help` to list the commands.
+
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+use std::sync::Arc;
+use std::thread;
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use ringbuf::RingBuffer;
+use snapshot_repeat::{GrooveTemplate, SnapshotBank, SnapshotImport, SnapshotRepeatPlugin};
+use vst::host::HostBuffer;
+use vst::plugin::{Plugin, PluginParameters};
+
+mod common;
+use common::find_parameter;
+
+// how much audio is buffered between the input and output streams
+const LATENCY_MS: f32 = 50.0;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let host = audio_host()?;
+    let input_device = host.default_input_device().ok_or("no input device available")?;
+    let output_device = host.default_output_device().ok_or("no output device available")?;
+
+    let supported = input_device.default_input_config()?;
+    if supported.sample_format() != cpal::SampleFormat::F32 {
+        return Err(format!("unsupported sample format {:?}", supported.sample_format()).into());
+    }
+    let input_config: cpal::StreamConfig = supported.into();
+    let output_config = output_config(&output_device, &input_config)?;
+    let sample_rate = input_config.sample_rate.0;
+    let input_channels = input_config.channels as usize;
+    let output_channels = output_config.channels as usize;
+
+    let mut plugin = SnapshotRepeatPlugin::default();
+    plugin.set_sample_rate(sample_rate as f32);
+    plugin.init();
+    plugin.resume();
+    let info = plugin.get_info();
+    let params = plugin.get_parameter_object();
+    let import = plugin.snapshot_import();
+    let groove = plugin.groove();
+    let bank = plugin.snapshot_bank();
+    // the plugin adapts to the number of channels it's given, up to its maximum,
+    // fewer inputs than outputs repeat across them
+    let outputs_used = usize::min(output_channels, info.outputs as usize);
+    let inputs_used = usize::min(input_channels, outputs_used);
+
+    let latency_frames = (LATENCY_MS / 1000.0 * sample_rate as f32) as usize;
+    let latency_samples = latency_frames * input_channels;
+    let ring = RingBuffer::<f32>::new(latency_samples * 2);
+    let (mut producer, mut consumer) = ring.split();
+    for _ in 0..latency_samples {
+        // the ring buffer has twice the needed capacity, this can't fail
+        producer.push(0.0).unwrap();
+    }
+
+    let input_stream = input_device.build_input_stream(
+        &input_config,
+        move |data: &[f32], _: &cpal::InputCallbackInfo| {
+            for &sample in data {
+                // drop samples when the output can't keep up
+                let _ = producer.push(sample);
+            }
+        },
+        |err| eprintln!("input stream error: {}", err),
+    )?;
+
+    let mut host_buffer = HostBuffer::new(inputs_used, outputs_used);
+    let mut inputs = vec![Vec::new(); inputs_used];
+    let mut outputs = vec![Vec::new(); outputs_used];
+    let output_stream = output_device.build_output_stream(
+        &output_config,
+        move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+            let frames = data.len() / output_channels;
+            inputs.iter_mut().chain(&mut outputs).for_each(|buf| buf.resize(frames, 0.0));
+
+            // deinterleave, input channels beyond the ones used are dropped
+            for frame in 0..frames {
+                for chan in 0..input_channels {
+                    let sample = consumer.pop().unwrap_or(0.0);
+                    if chan < inputs_used {
+                        inputs[chan][frame] = sample;
+                    }
+                }
+            }
+
+            let mut buffer = host_buffer.bind(&inputs, &mut outputs);
+            plugin.process(&mut buffer);
+
+            // interleave, extra device channels are kept silent
+            for (frame, out) in data.chunks_mut(output_channels).enumerate() {
+                for (chan, out) in out.iter_mut().enumerate() {
+                    *out = outputs.get(chan).map_or(0.0, |buf| buf[frame]);
+                }
+            }
+        },
+        |err| eprintln!("output stream error: {}", err),
+    )?;
+
+    input_stream.play()?;
+    output_stream.play()?;
+
+    eprintln!(
+        "{} running at {} Hz, {} in and {} out, type `help` for commands",
+        info.name, sample_rate, input_channels, output_channels,
+    );
+    command_loop(&*params, info.parameters, &import, &bank, &groove)?;
+    Ok(())
+}
+
+// an output config taking 32-bit floats at the rate of the input, with as many
+// channels as the input if the device can do that
+fn output_config(device: &cpal::Device, input: &cpal::StreamConfig) -> Result<cpal::StreamConfig, Box<dyn Error>> {
+    let rate = input.sample_rate;
+    let usable = device.supported_output_configs()?
+        .filter(|range| range.sample_format() == cpal::SampleFormat::F32)
+        .filter(|range| range.min_sample_rate() <= rate && rate <= range.max_sample_rate())
+        .collect::<Vec<_>>();
+    let range = usable.iter()
+        .find(|range| range.channels() == input.channels)
+        .or_else(|| usable.first())
+        .ok_or_else(|| format!("the output device can't play 32-bit floats at {} Hz", rate.0))?;
+    Ok(range.clone().with_sample_rate(rate).into())
+}
+
+#[cfg(feature = "jack")]
+fn audio_host() -> Result<cpal::Host, Box<dyn Error>> {
+    Ok(cpal::host_from_id(cpal::HostId::Jack)?)
+}
+
+#[cfg(not(feature = "jack"))]
+fn audio_host() -> Result<cpal::Host, Box<dyn Error>> {
+    Ok(cpal::default_host())
+}
+
+fn command_loop(
+    params: &dyn PluginParameters,
+    count: i32,
+    import: &Arc<SnapshotImport>,
+    bank: &Arc<SnapshotBank>,
+    groove: &GrooveTemplate,
+) -> io::Result<()> {
+    let stdin = io::stdin();
+    prompt()?;
+    for line in stdin.lock().lines() {
+        let line = line?;
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        match words.as_slice() {
+            [] => {}
+            ["help"] => {
+                println!("list               show all parameters");
+                println!("set <param> <val>  set a parameter (name or index) to a value in 0 ..= 1");
+                println!("describe <param>   explain what a parameter does");
+                println!("import <file>      load a WAV file as the snapshot");
+                println!("save-bank <file>   save the snapshot history of every channel");
+                println!("load-bank <file>   load a saved snapshot history");
+                println!("groove <file>      load a groove template for the synced periods");
+                println!("quit               stop processing and exit");
+            }
+            ["list"] => {
+                for index in 0..count {
+                    println!(
+                        "{:>3} {:<20} {:.3} ({})",
+                        index,
+                        params.get_parameter_name(index),
+                        params.get_parameter(index),
+                        params.get_parameter_text(index),
+                    );
+                }
+            }
+            ["set", param, value] => match (find_parameter(params, count, param), value.parse::<f32>()) {
+                (Some(index), Ok(value)) if (0.0..=1.0).contains(&value) => {
+                    params.set_parameter(index, value);
+                    println!("{} = {}", params.get_parameter_name(index), params.get_parameter_text(index));
+                }
+                (None, _) => println!("unknown parameter `{}`", param),
+                _ => println!("invalid value `{}`, expected a number in 0 ..= 1", value),
+            },
+            ["describe", param] => match find_parameter(params, count, param) {
+                Some(index) => println!(
+                    "{}: {}",
+                    params.get_parameter_name(index),
+                    SnapshotRepeatPlugin::parameter_description(index).unwrap_or(""),
+                ),
+                None => println!("unknown parameter `{}`", param),
+            },
+            ["import", ..] => {
+                // the rest of the line, so paths can contain spaces
+                let path = line.trim_start()["import".len()..].trim();
+                report("import", path, import.import(path.into()));
+            }
+            ["save-bank", ..] => {
+                let path = line.trim_start()["save-bank".len()..].trim();
+                report("save", path, bank.save(path.into()));
+            }
+            ["load-bank", ..] => {
+                let path = line.trim_start()["load-bank".len()..].trim();
+                report("load", path, bank.load(path.into()));
+            }
+            ["groove", ..] => {
+                let path = line.trim_start()["groove".len()..].trim();
+                if let Err(err) = groove.load(path.as_ref()) {
+                    println!("failed to load {}: {}", path, err);
+                }
+            }
+            ["quit"] | ["exit"] => break,
+            _ => println!("unknown command, type `help` for a list of commands"),
+        }
+        prompt()?;
+    }
+    Ok(())
+}
+
+// waits for a file to be done with on its worker, the audio keeps running meanwhile
+fn report(action: &str, path: &str, worker: thread::JoinHandle<io::Result<()>>) {
+    match worker.join() {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => println!("failed to {} {}: {}", action, path, err),
+        Err(_) => println!("failed to {} {}: the worker panicked", action, path),
+    }
+}
+
+fn prompt() -> io::Result<()> {
+    print!("> ");
+    io::stdout().flush()
+}
@@ -0,0 +1,72 @@
+//! Keeps denormal floats out of the processing.
+//!
+//! Decaying feedback and fades end up in the denormal range where every
+//! operation on x86 gets many times slower, so processing runs with the SSE
+//! flush-to-zero and denormals-are-zero modes on.
+
+// newer toolchains deprecate these in favour of inline assembly
+#[cfg(target_arch = "x86")]
+#[allow(deprecated)]
+use std::arch::x86::{_mm_getcsr, _mm_setcsr};
+#[cfg(target_arch = "x86_64")]
+#[allow(deprecated)]
+use std::arch::x86_64::{_mm_getcsr, _mm_setcsr};
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+const FLUSH_TO_ZERO: u32 = 0x8000;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+const DENORMALS_ARE_ZERO: u32 = 0x0040;
+
+/// Switches the denormal modes on for as long as it lives, restoring what the
+/// host had set when dropped.
+pub(crate) struct DenormalGuard {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    mxcsr: u32,
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+impl DenormalGuard {
+    #[allow(deprecated)]
+    pub(crate) fn new() -> Self {
+        // SSE is always there on x86_64 and required by any host we run in on x86
+        unsafe {
+            let mxcsr = _mm_getcsr();
+            _mm_setcsr(mxcsr | FLUSH_TO_ZERO | DENORMALS_ARE_ZERO);
+            Self { mxcsr }
+        }
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+impl Drop for DenormalGuard {
+    #[allow(deprecated)]
+    fn drop(&mut self) {
+        unsafe { _mm_setcsr(self.mxcsr) }
+    }
+}
+
+// other architectures either flush by default or have no cheap way to switch it
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+impl DenormalGuard {
+    pub(crate) fn new() -> Self {
+        Self {}
+    }
+}
+
+#[cfg(all(test, any(target_arch = "x86", target_arch = "x86_64")))]
+mod tests {
+    use super::*;
+    use std::ptr;
+
+    #[test]
+    fn flushes_while_alive() {
+        let tiny = f32::MIN_POSITIVE;
+        // volatile so the division isn't done at compile time
+        let quarter = || unsafe { ptr::read_volatile(&tiny) } / 4.0;
+        {
+            let _guard = DenormalGuard::new();
+            assert_eq!(quarter(), 0.0);
+        }
+        assert!(quarter() > 0.0);
+    }
+}
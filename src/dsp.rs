@@ -0,0 +1,4024 @@
+//! The repeats themselves, free of any plugin API.
+//!
+//! `SnapshotRepeat` runs the capture and playback of a channel on plain
+//! buffers, for other wrappers, tools and tests. The plugin drives the same
+//! parts and adds what only makes sense inside a host: the dry mix, ducking,
+//! stereo rotation, bypass, MIDI and following the host transport.
+
+use std::f32::consts::{FRAC_PI_2, TAU};
+use std::mem;
+use std::ops::{Add, Mul, Range, RangeInclusive};
+use std::slice;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use vst::util::AtomicFloat;
+
+use crate::bank::SnapshotBank;
+use crate::denormals::DenormalGuard;
+use crate::granular::{GrainCloud, GrainSettings};
+use crate::groove::GrooveTemplate;
+use crate::import::SnapshotImport;
+use crate::learn::MidiLearn;
+use crate::modulation::{self, Destination, Route, Source, DESTINATIONS, ROUTES};
+use crate::random::Random;
+use crate::scenes::Scenes;
+use crate::stretch::{Stretcher, STRETCH_WINDOW_MS};
+
+// snapshots and everything mixed into them are kept this far (about -12 dB) below full
+// scale, the output makes it up, a power of two so the scaling is exact
+pub(crate) const HEADROOM: f32 = 0.25;
+// peak level the feedback limiter keeps the snapshots under, at full scale
+const FEEDBACK_CEILING: f32 = 1.0;
+// how much the limiter lets go each period once the snapshots are back under the ceiling
+const FEEDBACK_RECOVERY: f32 = 1.25;
+// share of the ceiling the soft clipper starts bending the output at
+const CLIP_KNEE: f32 = 0.5;
+// how far ahead of the output the limiter sees, it's down by the time a peak comes out
+const LIMITER_LOOKAHEAD_MS: f32 = 1.5;
+// feedback above the knee is bent towards the maximum so the loop gain never reaches unity
+const FEEDBACK_KNEE: f32 = 0.8;
+const FEEDBACK_MAX: f32 = 0.98;
+// band-limited copies of each snapshot, for playback at 2x, 4x, 8x and 16x the rate and above
+const MIP_LEVELS: usize = 4;
+// mipmap samples filtered for each sample processed, a new snapshot's are built
+// over the blocks after it starts playing instead of all at the swap
+const MIPMAP_PACE: usize = 32;
+// taps of the half-band low-pass each mipmap level is decimated with, at odd
+// distances from the center tap of 0.5, the even ones are zero (Kaiser window, beta 6)
+const HALF_BAND: [f32; 5] = [0.307_912_3, -0.077_708_95, 0.025_554_74, -0.006_284_507, 0.000_526_392];
+// fraction of a sample the scan offset may fall short by and still read that sample
+const INDEX_TOLERANCE: f32 = 1e-3;
+// longest the capture waits for the input to cross zero after a period boundary
+const ZERO_CROSSING_TIMEOUT_MS: f32 = 5.0;
+// how far in from each end of a capture the loop points are searched for
+const LOOP_SEARCH_MS: f32 = 20.0;
+// ends and samples the first pass of the best match search steps over
+const LOOP_SEARCH_STRIDE: usize = 8;
+// period and capture length are kept as samples at this rate so the same setting
+// is the same time at any sample rate
+const LENGTH_RATE: f32 = 44_100.0;
+// longest period and capture length
+pub(crate) const MAX_LENGTH_SECONDS: f32 = 10.0;
+// how long switching the capture or playback on and off takes
+pub(crate) const ENABLE_FADE_MS: f32 = 5.0;
+// input below this level (-60 dBFS) counts as silence
+pub(crate) const SILENCE_LEVEL: f32 = 0.001;
+// cutoff of the high-pass taking DC offset out of the captured input
+const DC_FILTER_HZ: f32 = 10.0;
+// most a quiet snapshot is brought up by normalizing
+pub(crate) const MAX_MAKEUP_DB: f32 = 24.0;
+// how far apart the outer channels play when a mono input is spread over them
+const SPREAD_SEMITONES: f32 = 0.2;
+const SPREAD_OFFSET: f32 = 0.125;
+// extra play heads besides the main one
+const MAX_TAPS: usize = 4;
+// the taps and the octave down, octave up and fifth layers
+const HEADS: usize = MAX_TAPS + 3;
+/// snapshots each channel keeps to go back to, the playing one included
+pub(crate) const HISTORY_LEN: usize = 8;
+// longest the repeats of one channel can be delayed against the other
+const MAX_CHANNEL_DELAY_MS: f32 = 40.0;
+// samples the channel delay moves by each sample when changed, a slight bend in
+// pitch while it moves rather than a jump
+const DELAY_GLIDE: f32 = 0.1;
+// longest the outgoing snapshot is crossfaded under the new one
+const MAX_TAIL_MS: f32 = 2000.0;
+/// steps in the gate pattern, each an equal part of the period
+pub(crate) const GATE_STEPS: usize = 16;
+/// note values the period steps through in sync, shortest first, as (label,
+/// quarter notes, bars) with the bars following the host's time signature
+pub(crate) const NOTE_VALUES: [(&str, f64, f64); 18] = [
+    ("1/32T", 1.0 / 12.0, 0.0),
+    ("1/32", 0.125, 0.0),
+    ("1/16T", 1.0 / 6.0, 0.0),
+    ("1/32D", 0.1875, 0.0),
+    ("1/16", 0.25, 0.0),
+    ("1/8T", 1.0 / 3.0, 0.0),
+    ("1/16D", 0.375, 0.0),
+    ("1/8", 0.5, 0.0),
+    ("1/4T", 2.0 / 3.0, 0.0),
+    ("1/8D", 0.75, 0.0),
+    ("1/4", 1.0, 0.0),
+    ("1/2T", 4.0 / 3.0, 0.0),
+    ("1/4D", 1.5, 0.0),
+    ("1/2", 2.0, 0.0),
+    ("1/2D", 3.0, 0.0),
+    ("1 bar", 0.0, 1.0),
+    ("2 bars", 0.0, 2.0),
+    ("4 bars", 0.0, 4.0),
+];
+// indices of the parameters whose effective values are published for editors
+pub(crate) const PLAYBACK_RATE_PARAM: i32 = 2;
+pub(crate) const ROTATE_DEPTH_PARAM: i32 = 5;
+pub(crate) const FREEZE_TRANSPOSE_PARAM: i32 = 9;
+pub(crate) const FEEDBACK_PARAM: i32 = 12;
+// index of the read-only parameter showing the feedback limiter
+pub(crate) const FEEDBACK_LIMITER_PARAM: i32 = 13;
+// indices of the toggles keeping parameter groups when a preset is loaded
+pub(crate) const LOCK_SYNC_PARAM: i32 = 53;
+pub(crate) const LOCK_LEVELS_PARAM: i32 = 54;
+// index of the read-only parameter showing whether safe mode is on
+pub(crate) const SAFE_MODE_PARAM: i32 = 34;
+// indices of the scene switch and the action copying to the other scene
+pub(crate) const SCENE_PARAM: i32 = 112;
+pub(crate) const COPY_SCENE_PARAM: i32 = 113;
+
+/// host transport state at the start of a processed block
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Transport {
+    pub(crate) playing: bool,
+    /// the host reports its transport as stopped, without a host it's neither
+    pub(crate) stopped: bool,
+    /// position in quarter notes, if the host provides it along with the tempo
+    pub(crate) ppq_pos: Option<f64>,
+    /// how many quarter notes pass each sample
+    pub(crate) ppq_per_sample: f64,
+    /// quarter notes in a bar, if the host provides the time signature
+    pub(crate) ppq_per_bar: Option<f64>,
+}
+
+impl Transport {
+    // the transport `samples` into the block
+    pub(crate) fn advanced(&self, samples: usize) -> Transport {
+        Transport {
+            ppq_pos: self.ppq_pos.map(|ppq| ppq + samples as f64 * self.ppq_per_sample),
+            ..*self
+        }
+    }
+
+    // quarter notes in the entry of `NOTE_VALUES` at `index`
+    pub(crate) fn note_value_beats(&self, index: usize) -> f64 {
+        let (_, quarters, bars) = NOTE_VALUES[index];
+        quarters + bars * self.ppq_per_bar.unwrap_or(4.0)
+    }
+}
+
+/// what happens when the host transport jumps, e.g. when looping a region
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum LoopMode {
+    /// ignore the jump and keep going
+    FreeRun,
+    /// start a new period at the jump
+    Reset,
+    /// continue from where the period would be had it been running since the song start
+    Align,
+}
+
+/// what happens while the host transport is stopped
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum WhenStopped {
+    /// keep repeating as if nothing happened
+    Run,
+    /// hold everything where it is and pass the dry input through
+    Dry,
+    /// hold everything where it is and output silence
+    Silence,
+}
+
+impl WhenStopped {
+    pub(crate) fn from_param(val: f32) -> Self {
+        match val as usize {
+            1 => WhenStopped::Dry,
+            2 => WhenStopped::Silence,
+            _ => WhenStopped::Run,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            WhenStopped::Run => "Keep running",
+            WhenStopped::Dry => "Dry",
+            WhenStopped::Silence => "Silence",
+        }
+    }
+}
+
+/// what the sidechain inputs are used for
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum SidechainMode {
+    /// capture the main input, output only the repeats
+    Off,
+    /// capture the sidechain, output only the repeats
+    Replace,
+    /// capture the sidechain, output the repeats on top of the main input
+    Mix,
+}
+
+/// where the captured input goes in the snapshot
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum CapturePosition {
+    /// the capture becomes the snapshot as it is
+    Start,
+    /// the capture becomes the snapshot, starting at the capture offset and wrapping around
+    Offset,
+    /// the capture replaces a window of the snapshot, the window moves along with each period
+    Window,
+    /// the capture replaces one of the equal segments of the snapshot, taken in turn or at random
+    Mosaic,
+}
+
+/// which grid the free running period boundaries snap to when the host has a tempo
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Quantize {
+    Off,
+    Beat,
+    Bar,
+}
+
+/// how the snapshot is played back
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Engine {
+    /// a single pass through the snapshot like a wavetable
+    Scan,
+    /// overlapping short grains around the scan position
+    Granular,
+}
+
+impl Engine {
+    pub(crate) fn from_param(val: f32) -> Self {
+        match val as usize {
+            1 => Engine::Granular,
+            _ => Engine::Scan,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Engine::Scan => "Scan",
+            Engine::Granular => "Granular",
+        }
+    }
+}
+
+impl CapturePosition {
+    pub(crate) fn from_param(val: f32) -> Self {
+        match val as usize {
+            1 => CapturePosition::Offset,
+            2 => CapturePosition::Window,
+            3 => CapturePosition::Mosaic,
+            _ => CapturePosition::Start,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            CapturePosition::Start => "Start",
+            CapturePosition::Offset => "Offset",
+            CapturePosition::Window => "Window",
+            CapturePosition::Mosaic => "Mosaic",
+        }
+    }
+}
+
+impl Quantize {
+    pub(crate) fn from_param(val: f32) -> Self {
+        match val as usize {
+            1 => Quantize::Beat,
+            2 => Quantize::Bar,
+            _ => Quantize::Off,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Quantize::Off => "Off",
+            Quantize::Beat => "Beat",
+            Quantize::Bar => "Bar",
+        }
+    }
+}
+
+impl SidechainMode {
+    pub(crate) fn from_param(val: f32) -> Self {
+        match val as usize {
+            1 => SidechainMode::Replace,
+            2 => SidechainMode::Mix,
+            _ => SidechainMode::Off,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            SidechainMode::Off => "Off",
+            SidechainMode::Replace => "Replace",
+            SidechainMode::Mix => "Mix",
+        }
+    }
+}
+
+/// the level each new snapshot is normalized by
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Normalize {
+    Off,
+    Peak,
+    Rms,
+}
+
+impl Normalize {
+    pub(crate) fn from_param(val: f32) -> Self {
+        match val as usize {
+            1 => Normalize::Peak,
+            2 => Normalize::Rms,
+            _ => Normalize::Off,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Normalize::Off => "Off",
+            Normalize::Peak => "Peak",
+            Normalize::Rms => "RMS",
+        }
+    }
+}
+
+/// what keeps the plugin output under the ceiling
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum OutputClip {
+    Off,
+    /// bends the peaks down towards the ceiling, each sample on its own
+    SoftClip,
+    /// turns the whole output down while it would go past the ceiling
+    Limiter,
+}
+
+impl OutputClip {
+    pub(crate) fn from_param(val: f32) -> Self {
+        match val as usize {
+            1 => OutputClip::SoftClip,
+            2 => OutputClip::Limiter,
+            _ => OutputClip::Off,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            OutputClip::Off => "Off",
+            OutputClip::SoftClip => "Soft clip",
+            OutputClip::Limiter => "Limiter",
+        }
+    }
+}
+
+/// shape of the fades, the same for fading in and out
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum FadeCurve {
+    Linear,
+    /// constant power while crossfading uncorrelated material
+    EqualPower,
+    /// linear in decibels, over 60 dB
+    Exponential,
+    /// eases in and out of both ends
+    SCurve,
+}
+
+impl FadeCurve {
+    pub(crate) fn from_param(val: f32) -> Self {
+        match val as usize {
+            1 => FadeCurve::EqualPower,
+            2 => FadeCurve::Exponential,
+            3 => FadeCurve::SCurve,
+            _ => FadeCurve::Linear,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            FadeCurve::Linear => "Linear",
+            FadeCurve::EqualPower => "Equal power",
+            FadeCurve::Exponential => "Exponential",
+            FadeCurve::SCurve => "S-curve",
+        }
+    }
+
+    /// Gain of a fade in `pos` (0.0 ..= 1.0) of the way through, a fade out is
+    /// the same from the other end.
+    pub(crate) fn gain(self, pos: f32) -> f32 {
+        let pos = pos.clamp(0.0, 1.0);
+        match self {
+            FadeCurve::Linear => pos,
+            FadeCurve::EqualPower => (pos * FRAC_PI_2).sin(),
+            FadeCurve::Exponential if pos <= 0.0 => 0.0,
+            FadeCurve::Exponential => db_to_gain(60.0 * (pos - 1.0)),
+            FadeCurve::SCurve => pos * pos * (3.0 - 2.0 * pos),
+        }
+    }
+}
+
+/// where each capture is trimmed so its end joins up with its start
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum LoopPoints {
+    Off,
+    /// from the first to the last rising zero crossing
+    ZeroCrossings,
+    /// the end where what follows it looks the most like the start
+    BestMatch,
+}
+
+impl LoopPoints {
+    pub(crate) fn from_param(val: f32) -> Self {
+        match val as usize {
+            1 => LoopPoints::ZeroCrossings,
+            2 => LoopPoints::BestMatch,
+            _ => LoopPoints::Off,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            LoopPoints::Off => "Off",
+            LoopPoints::ZeroCrossings => "Zero crossings",
+            LoopPoints::BestMatch => "Best match",
+        }
+    }
+}
+
+/// shape of the tremolo over each cycle
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum TremoloShape {
+    Sine,
+    /// on for the first half and off for the second, a trance gate
+    Square,
+    /// falls from full level to the depth over the cycle
+    Saw,
+}
+
+impl TremoloShape {
+    pub(crate) fn from_param(val: f32) -> Self {
+        match val as usize {
+            1 => TremoloShape::Square,
+            2 => TremoloShape::Saw,
+            _ => TremoloShape::Sine,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            TremoloShape::Sine => "Sine",
+            TremoloShape::Square => "Square",
+            TremoloShape::Saw => "Saw",
+        }
+    }
+
+    /// Gain of the tremolo `phase` (0.0 .. 1.0) of the way through a cycle at
+    /// full depth, the cycles start at full level.
+    pub(crate) fn gain(self, phase: f32) -> f32 {
+        match self {
+            TremoloShape::Sine => 0.5 + 0.5 * (phase * TAU).cos(),
+            TremoloShape::Square if phase < 0.5 => 1.0,
+            TremoloShape::Square => 0.0,
+            TremoloShape::Saw => 1.0 - phase,
+        }
+    }
+}
+
+impl LoopMode {
+    pub(crate) fn from_param(val: f32) -> Self {
+        match val as usize {
+            1 => LoopMode::Reset,
+            2 => LoopMode::Align,
+            _ => LoopMode::FreeRun,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            LoopMode::FreeRun => "Free run",
+            LoopMode::Reset => "Reset",
+            LoopMode::Align => "Align",
+        }
+    }
+}
+
+/// how the normalized values the host sees map to the values of a parameter
+#[derive(Clone, Copy)]
+pub(crate) enum Scale {
+    /// linear from `low` to `high`
+    Lin { low: f32, high: f32 },
+    /// decibels, linear from `low` to `high` except for the very bottom which is silence
+    Db { low: f32, high: f32 },
+    /// hertz, logarithmic from `low` to `high`
+    Freq { low: f32, high: f32 },
+    /// whole numbers `0 ..= steps - 1` for switches and choices
+    Step { steps: usize },
+    /// milliseconds from zero to `high`, the cube of the normalized value so the
+    /// short times that need the finer steps get most of the range
+    Time { high: f32 },
+}
+
+impl Scale {
+    pub(crate) fn to_norm(self, scaled: f32) -> f32 {
+        match self {
+            Scale::Lin { low, high } => {
+                debug_assert!(low <= scaled && scaled <= high);
+                (scaled - low) / (high - low)
+            }
+            Scale::Db { low, high } => {
+                if scaled == f32::NEG_INFINITY {
+                    return 0.0
+                }
+                debug_assert!(low <= scaled && scaled <= high);
+                (scaled - low) / (high - low)
+            }
+            Scale::Freq { low, high } => {
+                debug_assert!(low <= scaled && scaled <= high);
+                (scaled / low).ln() / (high / low).ln()
+            }
+            Scale::Step { steps } => {
+                debug_assert!((0.0..=(steps - 1) as f32).contains(&scaled));
+                scaled / (steps - 1) as f32
+            }
+            Scale::Time { high } => {
+                debug_assert!((0.0..=high).contains(&scaled));
+                (scaled / high).cbrt()
+            }
+        }
+    }
+
+    pub(crate) fn to_scaled(self, norm: f32) -> f32 {
+        debug_assert!((0.0..=1.0).contains(&norm));
+        match self {
+            Scale::Lin { low, high } => low + norm * (high - low),
+            Scale::Db { low, high } => {
+                if norm <= 0.0 {
+                    return f32::NEG_INFINITY
+                }
+                low + norm * (high - low)
+            }
+            Scale::Freq { low, high } => low * (high / low).powf(norm),
+            Scale::Step { steps } => (norm * (steps - 1) as f32).round(),
+            Scale::Time { high } => high * norm * norm * norm,
+        }
+    }
+}
+
+pub(crate) fn db_to_gain(db: f32) -> f32 {
+    10.0f32.powf(db / 20.0)
+}
+
+/// sample types the plugin can process, the snapshots themselves are always stored as `f32`
+pub(crate) trait Sample: Copy + Default + Add<Output = Self> + Mul<Output = Self> {
+    fn from_f32(val: f32) -> Self;
+    fn to_f32(self) -> f32;
+    fn from_f64(val: f64) -> Self;
+    fn to_f64(self) -> f64;
+
+    /// the sample times `gain`, worked out at the precision of the sample
+    fn scale(self, gain: f32) -> Self {
+        self * Self::from_f32(gain)
+    }
+}
+
+impl Sample for f32 {
+    fn from_f32(val: f32) -> Self {
+        val
+    }
+
+    fn to_f32(self) -> f32 {
+        self
+    }
+
+    fn from_f64(val: f64) -> Self {
+        val as f32
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl Sample for f64 {
+    fn from_f32(val: f32) -> Self {
+        val as f64
+    }
+
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+
+    fn from_f64(val: f64) -> Self {
+        val
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+}
+
+pub(crate) struct ScaledParameter {
+    inner: AtomicFloat,
+    // normalized offset the modulation matrix moves the value by, the host never sees it
+    modulation: AtomicFloat,
+    scale: Scale,
+}
+
+impl ScaledParameter {
+    pub(crate) fn new(scale: Scale, init: f32) -> Self {
+        Self {
+            inner: AtomicFloat::new(scale.to_norm(init)),
+            modulation: AtomicFloat::new(0.0),
+            scale,
+        }
+    }
+
+    pub(crate) fn lin(low: f32, high: f32, init: f32) -> Self {
+        Self::new(Scale::Lin { low, high }, init)
+    }
+
+    pub(crate) fn db(low: f32, high: f32, init: f32) -> Self {
+        Self::new(Scale::Db { low, high }, init)
+    }
+
+    pub(crate) fn freq(low: f32, high: f32, init: f32) -> Self {
+        Self::new(Scale::Freq { low, high }, init)
+    }
+
+    pub(crate) fn step(steps: usize, init: f32) -> Self {
+        Self::new(Scale::Step { steps }, init)
+    }
+
+    pub(crate) fn time(high: f32, init: f32) -> Self {
+        Self::new(Scale::Time { high }, init)
+    }
+
+    pub(crate) fn get_raw(&self) -> f32 {
+        self.inner.get()
+    }
+
+    pub(crate) fn set_raw(&self, val: f32) {
+        self.inner.set(val)
+    }
+
+    pub(crate) fn get(&self) -> f32 {
+        self.scale.to_scaled(self.get_raw())
+    }
+
+    /// How many values a switch or choice has, `None` for the continuous ones.
+    pub(crate) fn steps(&self) -> Option<usize> {
+        match self.scale {
+            Scale::Step { steps } => Some(steps),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn set_modulation(&self, offset: f32) {
+        self.modulation.set(offset)
+    }
+
+    // the normalized value moved by the modulation, still within the range
+    pub(crate) fn modulated_raw(&self) -> f32 {
+        (self.get_raw() + self.modulation.get()).clamp(0.0, 1.0)
+    }
+
+    pub(crate) fn modulated(&self) -> f32 {
+        self.scale.to_scaled(self.modulated_raw())
+    }
+
+    // where `val` would be in the normalized range, clamped to it
+    pub(crate) fn normalize(&self, val: f32) -> f32 {
+        self.scale.to_norm(val.clamp(self.scale.to_scaled(0.0), self.scale.to_scaled(1.0)))
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set(&self, val: f32) {
+        self.set_raw(self.scale.to_norm(val))
+    }
+}
+
+pub(crate) struct Params {
+    /// period between recapturing
+    pub(crate) period: ScaledParameter,
+    /// size of the captured buffer
+    pub(crate) capture_len: ScaledParameter,
+    /// playback rate
+    pub(crate) playback_rate: ScaledParameter,
+    /// how much the pump envelope ducks the start of each beat
+    pub(crate) pump_depth: ScaledParameter,
+    /// exponent of the pump envelope recovery, higher recovers faster
+    pub(crate) pump_shape: ScaledParameter,
+    /// how far the stereo field of the repeats is rotated back and forth
+    pub(crate) rotate_depth: ScaledParameter,
+    /// frequency of the stereo rotation in Hz
+    pub(crate) rotate_rate: ScaledParameter,
+    /// reaction to jumps of the host transport, see `LoopMode`
+    pub(crate) loop_mode: ScaledParameter,
+    /// keeps repeating the current snapshot, transposed by `freeze_transpose`
+    pub(crate) freeze: ScaledParameter,
+    /// semitones the playback glides to while frozen
+    pub(crate) freeze_transpose: ScaledParameter,
+    /// milliseconds the glide to (and from) the frozen transposition takes
+    pub(crate) freeze_glide: ScaledParameter,
+    /// whether the sidechain is captured instead of the main input, see `SidechainMode`
+    pub(crate) sidechain: ScaledParameter,
+    /// how much of the playing snapshot is mixed into the next capture
+    pub(crate) feedback: ScaledParameter,
+    /// gain the stability limiter applies to the feedback, only written by the plugin
+    pub(crate) feedback_limit: AtomicFloat,
+    /// sums each capture into the playing snapshot instead of replacing it
+    pub(crate) overdub: ScaledParameter,
+    /// drops the snapshot when switched on, meant to be used as a button
+    pub(crate) clear: ScaledParameter,
+    /// gain of the captured input in dB
+    pub(crate) input_trim: ScaledParameter,
+    /// gain of the repeats in dB
+    pub(crate) output_gain: ScaledParameter,
+    /// replaces the period with `sync_numerator / sync_denominator` beats when the host has a tempo
+    pub(crate) sync: ScaledParameter,
+    /// beats per period in sync, minus one
+    pub(crate) sync_numerator: ScaledParameter,
+    /// what the beats per period in sync are divided by, minus one
+    pub(crate) sync_denominator: ScaledParameter,
+    /// crossfades to the unprocessed input
+    pub(crate) bypass: ScaledParameter,
+    /// whether the host bypasses the plugin through its own switch, crossfades like `bypass`
+    pub(crate) host_bypass: AtomicBool,
+    /// each MIDI note starts playing the captured audio
+    pub(crate) midi_trigger: ScaledParameter,
+    /// each MIDI note also latches a random variation until it ends
+    pub(crate) latch_random: ScaledParameter,
+    /// the same seed gives the same sequence of variations
+    pub(crate) seed: ScaledParameter,
+    /// whether new snapshots are captured, the last one keeps playing otherwise
+    pub(crate) capture_enable: ScaledParameter,
+    /// whether the repeats are heard
+    pub(crate) playback_enable: ScaledParameter,
+    /// balance between the dry input and the repeats
+    pub(crate) mix: ScaledParameter,
+    /// how much the dry input is turned down while the repeats are loud
+    pub(crate) duck: ScaledParameter,
+    /// playback engine, see `Engine`
+    pub(crate) engine: ScaledParameter,
+    /// length of each grain in milliseconds
+    pub(crate) grain_size: ScaledParameter,
+    /// grains started each second
+    pub(crate) grain_density: ScaledParameter,
+    /// how far from the play head grains start, as a fraction of the snapshot
+    pub(crate) position_spray: ScaledParameter,
+    /// semitones the pitch of each grain is scattered by both ways
+    pub(crate) pitch_spray: ScaledParameter,
+    /// whether processing fell back to passing the input through, only written by the plugin
+    pub(crate) safe_mode: AtomicBool,
+    /// the snapshot restarts `2 ^ repeat_division` times each period
+    pub(crate) repeat_division: ScaledParameter,
+    /// grid the period boundaries snap to outside of sync, see `Quantize`
+    pub(crate) quantize: ScaledParameter,
+    /// fraction of a slice every other restart of the repeat division is delayed by
+    pub(crate) division_swing: ScaledParameter,
+    /// milliseconds each captured snapshot fades in and out over
+    pub(crate) edge_fade: ScaledParameter,
+    /// chance of each period being repeated, the dry input passes through otherwise
+    pub(crate) repeat_probability: ScaledParameter,
+    /// where the captured input goes in the snapshot, see `CapturePosition`
+    pub(crate) capture_position: ScaledParameter,
+    /// where in the snapshot the capture starts in the offset position, as a fraction of its length
+    pub(crate) capture_offset: ScaledParameter,
+    /// chance of each period playing the snapshot backwards
+    pub(crate) reverse_probability: ScaledParameter,
+    /// segments the snapshot is split into in the mosaic position, minus two
+    pub(crate) mosaic_segments: ScaledParameter,
+    /// chance of a segment being refreshed each period in the mosaic position
+    pub(crate) mosaic_probability: ScaledParameter,
+    /// whether the refreshed segment is picked at random instead of in turn
+    pub(crate) mosaic_random: ScaledParameter,
+    /// each division plays one slice `2 ^ -slice_length` of the snapshot long, unless zero
+    pub(crate) slice_length: ScaledParameter,
+    /// chance of a division playing a random slice instead of the one in turn
+    pub(crate) slice_random: ScaledParameter,
+    /// milliseconds of silent input after which capturing stops until the input returns, unless zero
+    pub(crate) silence_hold: ScaledParameter,
+    /// playback rate multiplier at the start of each period
+    pub(crate) rate_start: ScaledParameter,
+    /// playback rate multiplier at the end of each period, it moves linearly from the start one
+    pub(crate) rate_end: ScaledParameter,
+    /// milliseconds the repeats fade in over after the transport starts or jumps
+    pub(crate) start_fade: ScaledParameter,
+    /// each capture starts where the input crosses zero
+    pub(crate) zero_crossing: ScaledParameter,
+    /// keeps the sync settings and the groove when a preset is loaded
+    pub(crate) lock_sync: ScaledParameter,
+    /// keeps the input trim, output gain and mix when a preset is loaded
+    pub(crate) lock_levels: ScaledParameter,
+    /// cutoff of the high-pass on the repeats, off at the bottom
+    pub(crate) wet_highpass: ScaledParameter,
+    /// cutoff of the low-pass on the repeats, off at the top
+    pub(crate) wet_lowpass: ScaledParameter,
+    /// bit depth the repeats are reduced to, off at the top
+    pub(crate) crush_bits: ScaledParameter,
+    /// factor the sample rate of the repeats is reduced by, off at one
+    pub(crate) downsample: ScaledParameter,
+    /// how differently each channel plays its input, when not zero
+    pub(crate) spread: ScaledParameter,
+    /// beats per minute used when the host has no tempo, unless zero
+    pub(crate) manual_bpm: ScaledParameter,
+    /// milliseconds the outgoing snapshot is crossfaded under the new one for
+    pub(crate) tail_crossfade: ScaledParameter,
+    /// whether the capture length is a fraction of the period rather than samples
+    pub(crate) capture_ratio: ScaledParameter,
+    /// percent each period's length is randomly moved by, at most
+    pub(crate) period_jitter: ScaledParameter,
+    /// milliseconds each repeat fades in over from the start of its period
+    pub(crate) attack: ScaledParameter,
+    /// milliseconds each repeat fades out over after the attack, no fade if zero
+    pub(crate) decay: ScaledParameter,
+    /// shape of the snapshot edge, tail and bypass fades, see `FadeCurve`
+    pub(crate) fade_curve: ScaledParameter,
+    /// whether the playback rate is shown as an interval rather than a multiplier
+    pub(crate) rate_display: ScaledParameter,
+    /// captures the mono sum of the inputs into the same snapshot for all channels
+    pub(crate) mono_capture: ScaledParameter,
+    /// what the level of each new snapshot is brought to the target by, see `Normalize`
+    pub(crate) normalize: ScaledParameter,
+    /// level in dBFS normalized snapshots are brought to
+    pub(crate) normalize_target: ScaledParameter,
+    /// high-passes the input as it's captured so offsets don't thump at every loop
+    pub(crate) dc_filter: ScaledParameter,
+    /// what keeps the output under the ceiling, see `OutputClip`
+    pub(crate) output_clip: ScaledParameter,
+    /// level in dBFS the output clipper or limiter never lets the output past
+    pub(crate) clip_ceiling: ScaledParameter,
+    /// start of the part of the snapshot playback loops in, as a share of the snapshot
+    pub(crate) loop_start: ScaledParameter,
+    /// length of the part of the snapshot playback loops in, as a share of the snapshot
+    pub(crate) loop_length: ScaledParameter,
+    /// playback follows `position` instead of running through the snapshot on its own
+    pub(crate) scrub: ScaledParameter,
+    /// where in the looped part playback is while scrubbing, as a share of it
+    pub(crate) position: ScaledParameter,
+    /// starts over from a fresh capture whenever the host transport starts playing
+    pub(crate) reset_on_start: ScaledParameter,
+    /// what the plugin does while the host transport is stopped, see `WhenStopped`
+    pub(crate) when_stopped: ScaledParameter,
+    /// how many extra heads play the snapshot besides the main one, up to `MAX_TAPS`
+    pub(crate) taps: ScaledParameter,
+    /// playback rate of tap 1, as a multiple of the main head's
+    pub(crate) tap_1_rate: ScaledParameter,
+    /// level of tap 1 in decibels, relative to the main head
+    pub(crate) tap_1_level: ScaledParameter,
+    /// playback rate of tap 2, as a multiple of the main head's
+    pub(crate) tap_2_rate: ScaledParameter,
+    /// level of tap 2 in decibels, relative to the main head
+    pub(crate) tap_2_level: ScaledParameter,
+    /// playback rate of tap 3, as a multiple of the main head's
+    pub(crate) tap_3_rate: ScaledParameter,
+    /// level of tap 3 in decibels, relative to the main head
+    pub(crate) tap_3_level: ScaledParameter,
+    /// playback rate of tap 4, as a multiple of the main head's
+    pub(crate) tap_4_rate: ScaledParameter,
+    /// level of tap 4 in decibels, relative to the main head
+    pub(crate) tap_4_level: ScaledParameter,
+    /// level of the layer an octave below the main head in decibels, off at the bottom
+    pub(crate) octave_down_level: ScaledParameter,
+    /// level of the layer an octave above the main head in decibels, off at the bottom
+    pub(crate) octave_up_level: ScaledParameter,
+    /// level of the layer a fifth above the main head in decibels, off at the bottom
+    pub(crate) fifth_level: ScaledParameter,
+    /// the scan engine changes the speed without the pitch, see `Stretcher`
+    pub(crate) time_stretch: ScaledParameter,
+    /// level in dBFS the input has to reach during a capture for it to replace the snapshot, off at the bottom
+    pub(crate) capture_threshold: ScaledParameter,
+    /// captures that came out silent don't replace the snapshot
+    pub(crate) skip_silence: ScaledParameter,
+    /// the repeats are brought to the level of the input
+    pub(crate) auto_gain: ScaledParameter,
+    /// rate of modulation LFO 1 in hertz
+    pub(crate) lfo_1_rate: ScaledParameter,
+    /// rate of modulation LFO 2 in hertz
+    pub(crate) lfo_2_rate: ScaledParameter,
+    /// MIDI controller number the controller modulation source follows
+    pub(crate) mod_controller: ScaledParameter,
+    /// source of modulation route 1, see `Source`
+    pub(crate) route_1_source: ScaledParameter,
+    /// what modulation route 1 modulates, see `Destination`
+    pub(crate) route_1_destination: ScaledParameter,
+    /// how far modulation route 1 moves its destination, as a share of its range, negative inverts
+    pub(crate) route_1_depth: ScaledParameter,
+    /// source of modulation route 2, see `Source`
+    pub(crate) route_2_source: ScaledParameter,
+    /// what modulation route 2 modulates, see `Destination`
+    pub(crate) route_2_destination: ScaledParameter,
+    /// how far modulation route 2 moves its destination, as a share of its range, negative inverts
+    pub(crate) route_2_depth: ScaledParameter,
+    /// source of modulation route 3, see `Source`
+    pub(crate) route_3_source: ScaledParameter,
+    /// what modulation route 3 modulates, see `Destination`
+    pub(crate) route_3_destination: ScaledParameter,
+    /// how far modulation route 3 moves its destination, as a share of its range, negative inverts
+    pub(crate) route_3_depth: ScaledParameter,
+    /// source of modulation route 4, see `Source`
+    pub(crate) route_4_source: ScaledParameter,
+    /// what modulation route 4 modulates, see `Destination`
+    pub(crate) route_4_destination: ScaledParameter,
+    /// how far modulation route 4 moves its destination, as a share of its range, negative inverts
+    pub(crate) route_4_depth: ScaledParameter,
+    /// in sync the period steps through `NOTE_VALUES` instead of the beat ratio
+    pub(crate) note_values: ScaledParameter,
+    /// which of the two scenes the parameters hold, see `Scenes`
+    pub(crate) scene: ScaledParameter,
+    /// switching it on copies the scene being edited to the other one
+    pub(crate) copy_scene: ScaledParameter,
+    /// how many captures back the playing snapshot is, up to `HISTORY_LEN - 1`, captures are held while it isn't zero
+    pub(crate) history: ScaledParameter,
+    /// how the ends of each capture are trimmed, see `LoopPoints`
+    pub(crate) loop_points: ScaledParameter,
+    /// how far into the snapshot the last channel starts playing ahead of the first, the ones
+    /// between evenly spread, as a fraction of the snapshot
+    pub(crate) phase_offset: ScaledParameter,
+    /// milliseconds the repeats of the right channel are delayed by, the left one when negative
+    pub(crate) channel_delay: ScaledParameter,
+    /// hertz the vibrato swings the playback pitch at
+    pub(crate) vibrato_rate: ScaledParameter,
+    /// cents the vibrato swings the playback pitch by either way, off at zero
+    pub(crate) vibrato_depth: ScaledParameter,
+    /// how far the tremolo takes the level of the repeats down, off at zero
+    pub(crate) tremolo_depth: ScaledParameter,
+    /// hertz the tremolo goes at, the note value it lasts (see `NOTE_VALUES`) when synced
+    pub(crate) tremolo_rate: ScaledParameter,
+    /// the shape of the tremolo, see `TremoloShape`
+    pub(crate) tremolo_shape: ScaledParameter,
+    /// the tremolo lasts a note value and follows the host position, when it gives a tempo
+    pub(crate) tremolo_sync: ScaledParameter,
+    /// whether the repeats play through each of the `GATE_STEPS` steps of the period
+    pub(crate) gate_steps: [ScaledParameter; GATE_STEPS],
+
+    /// audio file loaded in place of a captured snapshot
+    pub(crate) import: Arc<SnapshotImport>,
+    /// file the snapshot history is saved to and loaded from
+    pub(crate) bank: Arc<SnapshotBank>,
+    /// timing offsets of the synced period boundaries
+    pub(crate) groove: Arc<GrooveTemplate>,
+    /// MIDI controllers driving the parameters
+    pub(crate) midi_learn: Arc<MidiLearn>,
+    /// the other of the A/B scenes
+    pub(crate) scenes: Scenes,
+    /// the timing the lengths are shown in, cached by the audio thread for the
+    /// text callbacks, quarter notes per sample are zero while there's no tempo
+    pub(crate) display_ppq_per_sample: AtomicFloat,
+    pub(crate) display_ppq_per_bar: AtomicFloat,
+    /// sample rate set by the host
+    pub(crate) sample_rate: AtomicFloat,
+}
+
+impl Params {
+    // `length` (of the period or the capture) in samples at the current rate
+    pub(crate) fn length(&self, length: f32) -> f32 {
+        length / LENGTH_RATE * self.sample_rate.get()
+    }
+
+    // the modulation routes as set, the ones switched off included
+    pub(crate) fn routes(&self) -> [Route; ROUTES] {
+        [
+            (&self.route_1_source, &self.route_1_destination, &self.route_1_depth),
+            (&self.route_2_source, &self.route_2_destination, &self.route_2_depth),
+            (&self.route_3_source, &self.route_3_destination, &self.route_3_depth),
+            (&self.route_4_source, &self.route_4_destination, &self.route_4_depth),
+        ].map(|(source, destination, depth)| {
+            (Source::from_param(source.get()), Destination::from_param(destination.get()), depth.get())
+        })
+    }
+
+    // moves the parameters the routes can modulate by `offsets`, in `Destination` order
+    pub(crate) fn set_modulation(&self, offsets: [f32; DESTINATIONS]) {
+        let destinations = [
+            &self.playback_rate,
+            &self.period,
+            &self.capture_len,
+            &self.mix,
+            &self.loop_start,
+            &self.pump_depth,
+            &self.rotate_depth,
+        ];
+        for (param, offset) in destinations.iter().zip(offsets) {
+            param.set_modulation(offset);
+        }
+    }
+
+    /// The parameter at `index`, `None` for the read-only ones and indices past the last.
+    pub(crate) fn parameter(&self, index: i32) -> Option<&ScaledParameter> {
+        let param = match index {
+            0 => &self.period,
+            1 => &self.capture_len,
+            2 => &self.playback_rate,
+            3 => &self.pump_depth,
+            4 => &self.pump_shape,
+            5 => &self.rotate_depth,
+            6 => &self.rotate_rate,
+            7 => &self.loop_mode,
+            8 => &self.freeze,
+            9 => &self.freeze_transpose,
+            10 => &self.freeze_glide,
+            11 => &self.sidechain,
+            12 => &self.feedback,
+            14 => &self.overdub,
+            15 => &self.clear,
+            16 => &self.input_trim,
+            17 => &self.output_gain,
+            18 => &self.sync,
+            19 => &self.sync_numerator,
+            20 => &self.sync_denominator,
+            21 => &self.bypass,
+            22 => &self.midi_trigger,
+            23 => &self.latch_random,
+            24 => &self.seed,
+            25 => &self.capture_enable,
+            26 => &self.playback_enable,
+            27 => &self.mix,
+            28 => &self.duck,
+            29 => &self.engine,
+            30 => &self.grain_size,
+            31 => &self.grain_density,
+            32 => &self.position_spray,
+            33 => &self.pitch_spray,
+            35 => &self.repeat_division,
+            36 => &self.quantize,
+            37 => &self.division_swing,
+            38 => &self.edge_fade,
+            39 => &self.repeat_probability,
+            40 => &self.capture_position,
+            41 => &self.capture_offset,
+            42 => &self.reverse_probability,
+            43 => &self.mosaic_segments,
+            44 => &self.mosaic_probability,
+            45 => &self.mosaic_random,
+            46 => &self.slice_length,
+            47 => &self.slice_random,
+            48 => &self.silence_hold,
+            49 => &self.rate_start,
+            50 => &self.rate_end,
+            51 => &self.start_fade,
+            52 => &self.zero_crossing,
+            LOCK_SYNC_PARAM => &self.lock_sync,
+            LOCK_LEVELS_PARAM => &self.lock_levels,
+            55 => &self.wet_highpass,
+            56 => &self.wet_lowpass,
+            57 => &self.crush_bits,
+            58 => &self.downsample,
+            59 => &self.spread,
+            60 => &self.manual_bpm,
+            61 => &self.tail_crossfade,
+            62 => &self.capture_ratio,
+            63 => &self.period_jitter,
+            64 => &self.attack,
+            65 => &self.decay,
+            66 => &self.fade_curve,
+            67 => &self.rate_display,
+            68 => &self.mono_capture,
+            69 => &self.normalize,
+            70 => &self.normalize_target,
+            71 => &self.dc_filter,
+            72 => &self.output_clip,
+            73 => &self.clip_ceiling,
+            74 => &self.loop_start,
+            75 => &self.loop_length,
+            76 => &self.scrub,
+            77 => &self.position,
+            78 => &self.reset_on_start,
+            79 => &self.when_stopped,
+            80 => &self.taps,
+            81 => &self.tap_1_rate,
+            82 => &self.tap_1_level,
+            83 => &self.tap_2_rate,
+            84 => &self.tap_2_level,
+            85 => &self.tap_3_rate,
+            86 => &self.tap_3_level,
+            87 => &self.tap_4_rate,
+            88 => &self.tap_4_level,
+            89 => &self.octave_down_level,
+            90 => &self.octave_up_level,
+            91 => &self.fifth_level,
+            92 => &self.time_stretch,
+            93 => &self.capture_threshold,
+            94 => &self.skip_silence,
+            95 => &self.auto_gain,
+            96 => &self.lfo_1_rate,
+            97 => &self.lfo_2_rate,
+            98 => &self.mod_controller,
+            99 => &self.route_1_source,
+            100 => &self.route_1_destination,
+            101 => &self.route_1_depth,
+            102 => &self.route_2_source,
+            103 => &self.route_2_destination,
+            104 => &self.route_2_depth,
+            105 => &self.route_3_source,
+            106 => &self.route_3_destination,
+            107 => &self.route_3_depth,
+            108 => &self.route_4_source,
+            109 => &self.route_4_destination,
+            110 => &self.route_4_depth,
+            111 => &self.note_values,
+            SCENE_PARAM => &self.scene,
+            COPY_SCENE_PARAM => &self.copy_scene,
+            114 => &self.history,
+            115 => &self.loop_points,
+            116 => &self.phase_offset,
+            117 => &self.channel_delay,
+            118 => &self.vibrato_rate,
+            119 => &self.vibrato_depth,
+            120 => &self.tremolo_depth,
+            121 => &self.tremolo_rate,
+            122 => &self.tremolo_shape,
+            123 => &self.tremolo_sync,
+            124..=139 => &self.gate_steps[index as usize - 124],
+            _ => return None,
+        };
+        Some(param)
+    }
+
+    // the value of a parameter by index, normalized (0.0 .. 1.0)
+    pub(crate) fn get_normalized(&self, index: i32) -> f32 {
+        match index {
+            FEEDBACK_LIMITER_PARAM => 1.0 - self.feedback_limit.get(),
+            SAFE_MODE_PARAM => self.safe_mode.load(Ordering::Acquire) as u8 as f32,
+            _ => self.parameter(index).map_or(0.0, ScaledParameter::get_raw),
+        }
+    }
+
+    // read-only parameters and indices past the last are ignored
+    pub(crate) fn set_normalized(&self, index: i32, val: f32) {
+        if let Some(param) = self.parameter(index) {
+            param.set_raw(val)
+        }
+    }
+}
+
+impl Default for Params {
+    fn default() -> Params {
+        Params {
+            period: ScaledParameter::lin(1.0, LENGTH_RATE * MAX_LENGTH_SECONDS, LENGTH_RATE),
+            capture_len: ScaledParameter::lin(1.0, LENGTH_RATE * MAX_LENGTH_SECONDS, LENGTH_RATE),
+            playback_rate: ScaledParameter::lin(0.01, 100.0, 1.0),
+            pump_depth: ScaledParameter::lin(0.0, 1.0, 0.0),
+            pump_shape: ScaledParameter::lin(0.5, 8.0, 2.0),
+            rotate_depth: ScaledParameter::lin(0.0, 1.0, 0.0),
+            rotate_rate: ScaledParameter::lin(0.01, 10.0, 0.25),
+            loop_mode: ScaledParameter::step(3, 0.0),
+            freeze: ScaledParameter::step(2, 0.0),
+            freeze_transpose: ScaledParameter::lin(-24.0, 24.0, -12.0),
+            freeze_glide: ScaledParameter::lin(0.0, 2000.0, 200.0),
+            sidechain: ScaledParameter::step(3, 0.0),
+            feedback: ScaledParameter::lin(0.0, 1.2, 0.0),
+            feedback_limit: AtomicFloat::new(1.0),
+            overdub: ScaledParameter::step(2, 0.0),
+            clear: ScaledParameter::step(2, 0.0),
+            input_trim: ScaledParameter::db(-24.0, 24.0, 0.0),
+            output_gain: ScaledParameter::db(-48.0, 12.0, 0.0),
+            sync: ScaledParameter::step(2, 0.0),
+            sync_numerator: ScaledParameter::step(16, 0.0),
+            sync_denominator: ScaledParameter::step(16, 0.0),
+            bypass: ScaledParameter::step(2, 0.0),
+            host_bypass: AtomicBool::new(false),
+            midi_trigger: ScaledParameter::step(2, 0.0),
+            latch_random: ScaledParameter::step(2, 0.0),
+            seed: ScaledParameter::step(128, 0.0),
+            capture_enable: ScaledParameter::step(2, 1.0),
+            playback_enable: ScaledParameter::step(2, 1.0),
+            mix: ScaledParameter::lin(0.0, 1.0, 1.0),
+            duck: ScaledParameter::lin(0.0, 1.0, 0.0),
+            engine: ScaledParameter::step(2, 0.0),
+            grain_size: ScaledParameter::lin(5.0, 500.0, 80.0),
+            grain_density: ScaledParameter::lin(1.0, 100.0, 20.0),
+            position_spray: ScaledParameter::lin(0.0, 1.0, 0.0),
+            pitch_spray: ScaledParameter::lin(0.0, 12.0, 0.0),
+            safe_mode: AtomicBool::new(false),
+            repeat_division: ScaledParameter::step(5, 0.0),
+            quantize: ScaledParameter::step(3, 0.0),
+            division_swing: ScaledParameter::lin(0.0, 0.5, 0.0),
+            edge_fade: ScaledParameter::time(500.0, 2.0),
+            repeat_probability: ScaledParameter::lin(0.0, 1.0, 1.0),
+            capture_position: ScaledParameter::step(4, 0.0),
+            capture_offset: ScaledParameter::lin(0.0, 1.0, 0.0),
+            reverse_probability: ScaledParameter::lin(0.0, 1.0, 0.0),
+            mosaic_segments: ScaledParameter::step(15, 2.0),
+            mosaic_probability: ScaledParameter::lin(0.0, 1.0, 1.0),
+            mosaic_random: ScaledParameter::step(2, 0.0),
+            slice_length: ScaledParameter::step(5, 0.0),
+            slice_random: ScaledParameter::lin(0.0, 1.0, 0.0),
+            silence_hold: ScaledParameter::lin(0.0, 5000.0, 0.0),
+            rate_start: ScaledParameter::lin(0.0, 2.0, 1.0),
+            rate_end: ScaledParameter::lin(0.0, 2.0, 1.0),
+            start_fade: ScaledParameter::lin(0.0, 2000.0, 0.0),
+            zero_crossing: ScaledParameter::step(2, 0.0),
+            lock_sync: ScaledParameter::step(2, 0.0),
+            lock_levels: ScaledParameter::step(2, 0.0),
+            wet_highpass: ScaledParameter::freq(20.0, 20000.0, 20.0),
+            wet_lowpass: ScaledParameter::freq(20.0, 20000.0, 20000.0),
+            crush_bits: ScaledParameter::lin(1.0, 16.0, 16.0),
+            downsample: ScaledParameter::lin(1.0, 32.0, 1.0),
+            spread: ScaledParameter::lin(0.0, 1.0, 0.0),
+            manual_bpm: ScaledParameter::lin(0.0, 300.0, 0.0),
+            tail_crossfade: ScaledParameter::time(MAX_TAIL_MS, 0.0),
+            capture_ratio: ScaledParameter::step(2, 0.0),
+            period_jitter: ScaledParameter::lin(0.0, 50.0, 0.0),
+            attack: ScaledParameter::lin(0.0, 2000.0, 0.0),
+            decay: ScaledParameter::lin(0.0, 5000.0, 0.0),
+            fade_curve: ScaledParameter::step(4, 0.0),
+            rate_display: ScaledParameter::step(2, 0.0),
+            mono_capture: ScaledParameter::step(2, 0.0),
+            normalize: ScaledParameter::step(3, 0.0),
+            normalize_target: ScaledParameter::lin(-40.0, 0.0, -12.0),
+            dc_filter: ScaledParameter::step(2, 0.0),
+            output_clip: ScaledParameter::step(3, 0.0),
+            clip_ceiling: ScaledParameter::lin(-24.0, 0.0, -1.0),
+            loop_start: ScaledParameter::lin(0.0, 1.0, 0.0),
+            loop_length: ScaledParameter::lin(0.0, 1.0, 1.0),
+            scrub: ScaledParameter::step(2, 0.0),
+            position: ScaledParameter::lin(0.0, 1.0, 0.0),
+            reset_on_start: ScaledParameter::step(2, 0.0),
+            when_stopped: ScaledParameter::step(3, 0.0),
+            taps: ScaledParameter::step(MAX_TAPS + 1, 0.0),
+            tap_1_rate: ScaledParameter::freq(0.25, 4.0, 2.0),
+            tap_1_level: ScaledParameter::db(-48.0, 0.0, -6.0),
+            tap_2_rate: ScaledParameter::freq(0.25, 4.0, 0.5),
+            tap_2_level: ScaledParameter::db(-48.0, 0.0, -6.0),
+            tap_3_rate: ScaledParameter::freq(0.25, 4.0, 1.5),
+            tap_3_level: ScaledParameter::db(-48.0, 0.0, -6.0),
+            tap_4_rate: ScaledParameter::freq(0.25, 4.0, 0.75),
+            tap_4_level: ScaledParameter::db(-48.0, 0.0, -6.0),
+            octave_down_level: ScaledParameter::db(-48.0, 0.0, f32::NEG_INFINITY),
+            octave_up_level: ScaledParameter::db(-48.0, 0.0, f32::NEG_INFINITY),
+            fifth_level: ScaledParameter::db(-48.0, 0.0, f32::NEG_INFINITY),
+            time_stretch: ScaledParameter::step(2, 0.0),
+            capture_threshold: ScaledParameter::db(-60.0, 0.0, f32::NEG_INFINITY),
+            skip_silence: ScaledParameter::step(2, 0.0),
+            auto_gain: ScaledParameter::step(2, 0.0),
+            lfo_1_rate: ScaledParameter::freq(0.01, 20.0, 1.0),
+            lfo_2_rate: ScaledParameter::freq(0.01, 20.0, 0.2),
+            mod_controller: ScaledParameter::step(128, 1.0),
+            route_1_source: ScaledParameter::step(6, 0.0),
+            route_1_destination: ScaledParameter::step(DESTINATIONS, 0.0),
+            route_1_depth: ScaledParameter::lin(-1.0, 1.0, 0.0),
+            route_2_source: ScaledParameter::step(6, 0.0),
+            route_2_destination: ScaledParameter::step(DESTINATIONS, 0.0),
+            route_2_depth: ScaledParameter::lin(-1.0, 1.0, 0.0),
+            route_3_source: ScaledParameter::step(6, 0.0),
+            route_3_destination: ScaledParameter::step(DESTINATIONS, 0.0),
+            route_3_depth: ScaledParameter::lin(-1.0, 1.0, 0.0),
+            route_4_source: ScaledParameter::step(6, 0.0),
+            route_4_destination: ScaledParameter::step(DESTINATIONS, 0.0),
+            route_4_depth: ScaledParameter::lin(-1.0, 1.0, 0.0),
+            note_values: ScaledParameter::step(2, 0.0),
+            scene: ScaledParameter::step(2, 0.0),
+            copy_scene: ScaledParameter::step(2, 0.0),
+            history: ScaledParameter::step(HISTORY_LEN, 0.0),
+            loop_points: ScaledParameter::step(3, 0.0),
+            phase_offset: ScaledParameter::lin(0.0, 1.0, 0.0),
+            channel_delay: ScaledParameter::lin(-MAX_CHANNEL_DELAY_MS, MAX_CHANNEL_DELAY_MS, 0.0),
+            vibrato_rate: ScaledParameter::freq(0.1, 20.0, 5.0),
+            vibrato_depth: ScaledParameter::lin(0.0, 100.0, 0.0),
+            tremolo_depth: ScaledParameter::lin(0.0, 1.0, 0.0),
+            tremolo_rate: ScaledParameter::freq(0.1, 20.0, 4.0),
+            tremolo_shape: ScaledParameter::step(3, 0.0),
+            tremolo_sync: ScaledParameter::step(2, 0.0),
+            gate_steps: [(); GATE_STEPS].map(|_| ScaledParameter::step(2, 1.0)),
+            import: Default::default(),
+            bank: Default::default(),
+            groove: Default::default(),
+            midi_learn: Default::default(),
+            scenes: Default::default(),
+            display_ppq_per_sample: AtomicFloat::new(0.0),
+            display_ppq_per_bar: AtomicFloat::new(4.0),
+            sample_rate: AtomicFloat::new(44_100.0),
+        }
+    }
+}
+
+// longest snapshot at `sample_rate`
+pub(crate) fn max_snapshot_len(sample_rate: f32) -> usize {
+    (MAX_LENGTH_SECONDS * sample_rate).ceil() as usize
+}
+
+// snapshot length the buffers are allocated for up front, the period and capture
+// as set, a longer one later grows them the first time it comes along
+pub(crate) fn reserved_len(params: &Params) -> usize {
+    let period = (params.length(params.period.get()).round() as usize).max(1);
+    let len = usize::max(period, capture_samples(params, period));
+    usize::min(len, max_snapshot_len(params.sample_rate.get()))
+}
+
+// a snapshot and copies of it low-passed and decimated for fast playback, each
+// for twice the rate of the one before
+#[derive(Default)]
+pub(crate) struct Snapshot {
+    samples: Vec<f32>,
+    mipmaps: [Vec<f32>; MIP_LEVELS],
+}
+
+impl Snapshot {
+    // room for `max_len` samples so a snapshot up to that long never allocates
+    fn allocate(&mut self, max_len: usize) {
+        reserve(&mut self.samples, max_len);
+        for (level, mipmap) in self.mipmaps.iter_mut().enumerate() {
+            reserve(mipmap, decimated_len(max_len, level));
+        }
+    }
+
+    // `samples` at full scale brought down to the internal level and cut to
+    // `max_len`, made ready to play off the audio thread
+    pub(crate) fn prepare(samples: &[f32], max_len: usize) -> Self {
+        let mut snapshot = Self::default();
+        let samples = &samples[..usize::min(samples.len(), max_len)];
+        snapshot.allocate(samples.len());
+        snapshot.samples.extend(samples.iter().map(|sample| sample * HEADROOM));
+        snapshot.build_mipmaps(usize::MAX);
+        snapshot
+    }
+
+    // filters up to `budget` more samples into the mipmaps, each level from the
+    // one before once that's done, within the room they already have
+    fn build_mipmaps(&mut self, mut budget: usize) {
+        for level in 0..MIP_LEVELS {
+            let (finer, coarser) = self.mipmaps.split_at_mut(level);
+            let source = finer.last().unwrap_or(&self.samples);
+            let mipmap = &mut coarser[0];
+            let missing = decimated_len(self.samples.len(), level) - mipmap.len();
+            let count = usize::min(missing, budget);
+            half_band(source, mipmap, count);
+            budget -= count;
+            if count < missing {
+                return
+            }
+        }
+    }
+
+    // how many of the mipmaps are done, they're read in order
+    fn mipmaps_built(&self) -> usize {
+        let len = self.samples.len();
+        self.mipmaps.iter()
+            .enumerate()
+            .take_while(|(level, mipmap)| mipmap.len() == decimated_len(len, *level))
+            .count()
+    }
+
+    fn clear(&mut self) {
+        self.samples.clear();
+        self.mipmaps.iter_mut().for_each(Vec::clear);
+    }
+
+    pub(crate) fn samples(&self) -> &[f32] {
+        &self.samples
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+}
+
+// samples of a mipmap level for `len` samples of the snapshot
+fn decimated_len(len: usize, level: usize) -> usize {
+    let stride = 2 << level;
+    (len + stride - 1) / stride
+}
+
+// grows the capacity of `buffer` to at least `len`
+fn reserve(buffer: &mut Vec<f32>, len: usize) {
+    buffer.reserve_exact(len.saturating_sub(buffer.len()));
+}
+
+pub(crate) struct ChannelState {
+    // the latest snapshots to go back to, a ring with the newest at `newest` and
+    // older ones after it
+    snapshots: [Snapshot; HISTORY_LEN],
+    newest: usize,
+    // how many of them have been captured
+    kept: usize,
+    // how many snapshots were ever pushed, a save copying the history over
+    // several blocks tells from it which of the slots it measured are still kept
+    pushed: usize,
+    // which one is playing
+    playing: usize,
+    // normalized (0.0 .. 1.0) offset into the current buffer
+    current_offset_norm: f32,
+    // the same for each of the taps and harmony layers, they start with the main
+    // head and drift off at their own rates
+    head_offsets: [f32; HEADS],
+
+    // how many samples used the current buffer
+    current_offset_total: usize,
+    // how many samples should the current buffer be used for in total
+    current_period: usize,
+
+    // the buffer to be used next (if any)
+    next_buffer: Vec<f32>,
+    // how many of the samples in the next buffer have been written
+    next_buffer_len: usize,
+    // where the next capture goes into the snapshot in the window position, the
+    // next segment in turn in the mosaic position
+    window_pos: usize,
+
+    // the current buffer isn't replaced while frozen
+    frozen: bool,
+    // semitones the playback is currently transposed by
+    pub(crate) transpose: f32,
+    // semitones the transposition glides towards
+    transpose_target: f32,
+    // how many semitones the transposition moves each sample
+    transpose_step: f32,
+
+    // playback rate at the end of the last block, the next one glides from there
+    last_rate: Option<f32>,
+    // the same for the input and output gains
+    last_input_gain: Option<f32>,
+    last_output_gain: Option<f32>,
+    // and for the scrubbed position
+    last_position: Option<f32>,
+    // playback rate multiplier latched by a MIDI note
+    pub(crate) rate_variation: f32,
+    // normalized offset each snapshot starts playing from, latched by a MIDI note
+    pub(crate) start_offset: f32,
+    // rate ratio and start offset of this channel when a mono input is spread, the
+    // start offset includes the phase offset
+    spread_rate: f32,
+    spread_offset: f32,
+    // normalized phase of the vibrato, how far it moves each sample and its depth in cents
+    vibrato_phase: f32,
+    vibrato_step: f32,
+    vibrato_depth: f32,
+    // normalized phase of the tremolo when it isn't synced and how far it moves each sample
+    tremolo_phase: f32,
+    tremolo_step: f32,
+    // gain of the tremolo at full depth, fading as fast as the enable gains so the
+    // edges of the square and saw don't click
+    tremolo_gain: f32,
+
+    // gain fading the input in and out of the capture
+    capture_gain: f32,
+    capture_target: f32,
+    // gain fading the repeats in and out
+    playback_gain: f32,
+    playback_target: f32,
+    // gain of the gate pattern, fading as fast as the enable gains
+    gate_gain: f32,
+    // how much the enable gains move each sample
+    enable_step: f32,
+    // samples the edges of each captured snapshot are faded over
+    edge_fade: usize,
+    // samples each repeat rises over and then falls to silence over, no fall if zero
+    attack: usize,
+    decay: usize,
+    // how many samples the input has been silent for
+    silent_samples: usize,
+    // peak level and sum of squares of the input captured since the period started
+    capture_peak: f32,
+    capture_energy: f64,
+    // longest a capture waits for the input to cross zero, no waiting if zero
+    crossing_timeout: usize,
+    // samples the capture keeps waiting for the input to cross zero
+    awaiting_crossing: usize,
+    // how far in from each end of a capture its loop points are searched for
+    loop_search: usize,
+    // the input sample before the current block
+    last_input: f32,
+    // takes DC offset out of the captured input
+    dc_filter: OnePole,
+    // tucking the repeats into the mix
+    highpass: OnePole,
+    lowpass: OnePole,
+    // lo-fi repeats
+    crusher: Crusher,
+    // delays the repeats of this channel against the other one
+    delay: DelayLine,
+    // samples the outgoing snapshot is crossfaded under the new one for
+    tail_len: usize,
+    // the outgoing snapshot's continuation and how much of it has been played
+    tail: Vec<f32>,
+    tail_pos: usize,
+
+    // set whenever the playing snapshot is replaced, until it's been submitted for analysis
+    pub(crate) fresh_snapshot: bool,
+    // set whenever a period starts, until the modulation has drawn a new random value
+    pub(crate) new_period: bool,
+
+    // decides whether each period is repeated, seeded the same for all channels so they decide in lockstep
+    random: Random,
+    // the current period passes the dry input through instead of repeating
+    pub(crate) skipped: bool,
+    // how far the next period is moved off its length, as a fraction of the jitter
+    next_jitter: f32,
+    // the current period plays the snapshot backwards
+    reversed: bool,
+    // normalized start of the slice playing in slice mode
+    slice_start: f32,
+
+    // plays the snapshot in the granular engine
+    grains: GrainCloud,
+    // plays it in the scan engine when time stretching
+    stretcher: Stretcher,
+
+    // how many captures back the playing snapshot is, nothing is captured while it isn't the latest
+    recalled: usize,
+
+    // peak level of the last captured snapshot
+    snapshot_peak: f32,
+    // gain the stability limiter applies to the feedback
+    pub(crate) feedback_gain: f32,
+}
+
+impl ChannelState {
+    // allocates the buffers for `sample_rate` and snapshots `len` long up front so
+    // the audio thread only has to for longer ones
+    pub(crate) fn allocate(&mut self, sample_rate: f32, len: usize) {
+        self.delay.allocate((MAX_CHANNEL_DELAY_MS / 1000.0 * sample_rate).ceil() as usize);
+        self.snapshots.iter_mut().for_each(|snapshot| snapshot.allocate(len));
+        reserve(&mut self.next_buffer, len);
+        reserve(&mut self.tail, (MAX_TAIL_MS / 1000.0 * sample_rate).ceil() as usize);
+    }
+
+    // back to how a new channel starts, the buffers stay allocated so the audio
+    // thread can do it
+    pub(crate) fn reset(&mut self) {
+        let mut snapshots = mem::take(&mut self.snapshots);
+        let mut next_buffer = mem::take(&mut self.next_buffer);
+        let mut tail = mem::take(&mut self.tail);
+        let mut delay = mem::take(&mut self.delay);
+        let pushed = self.pushed + HISTORY_LEN;
+        *self = Self::default();
+        self.pushed = pushed;
+        snapshots.iter_mut().for_each(Snapshot::clear);
+        next_buffer.clear();
+        tail.clear();
+        delay.reset();
+        self.snapshots = snapshots;
+        self.next_buffer = next_buffer;
+        self.tail = tail;
+        self.delay = delay;
+    }
+
+    // replaces the playing snapshot with a prepared one and starts a new period
+    // with it, `snapshot` is left with the buffers it was swapped with
+    pub(crate) fn load_snapshot(&mut self, snapshot: &mut Snapshot, period: usize) {
+        let slot = self.next_slot();
+        mem::swap(&mut self.snapshots[slot], snapshot);
+        self.current_offset_norm = 0.0;
+        self.head_offsets = [0.0; HEADS];
+        self.current_offset_total = 0;
+        self.current_period = period;
+        self.next_buffer_len = 0;
+        self.window_pos = 0;
+        self.push_snapshot(slot);
+    }
+
+    // the slot the next snapshot goes into, the oldest one once they're all kept
+    fn next_slot(&self) -> usize {
+        (self.newest + HISTORY_LEN - 1) % HISTORY_LEN
+    }
+
+    // makes the snapshot in `slot` the newest one and plays it
+    fn push_snapshot(&mut self, slot: usize) {
+        self.newest = slot;
+        self.playing = slot;
+        self.kept = usize::min(self.kept + 1, HISTORY_LEN);
+        self.pushed += 1;
+        self.fresh_snapshot = true;
+    }
+
+    fn current(&self) -> &Snapshot {
+        &self.snapshots[self.playing]
+    }
+
+    // the kept snapshots, newest first
+    fn history(&self) -> impl Iterator<Item = &Snapshot> {
+        (0..self.kept).map(move |back| &self.snapshots[(self.newest + back) % HISTORY_LEN])
+    }
+
+    pub(crate) fn pushed(&self) -> usize {
+        self.pushed
+    }
+
+    // how long the snapshots in the history are, newest first
+    pub(crate) fn history_lens(&self) -> impl Iterator<Item = usize> + '_ {
+        self.history().map(Snapshot::len)
+    }
+
+    // appends up to `count` more samples at full scale of the snapshot that was
+    // `back` captures before the latest after `pushed` of them, false if it isn't
+    // kept anymore
+    pub(crate) fn copy_history(&self, pushed: usize, back: usize, into: &mut Vec<f32>, count: usize) -> bool {
+        let back = match self.pushed.checked_sub(pushed) {
+            Some(since) if back + since < self.kept => back + since,
+            _ => return false,
+        };
+        let samples = &self.snapshots[(self.newest + back) % HISTORY_LEN].samples;
+        into.extend(samples.iter().skip(into.len()).take(count).map(|sample| sample / HEADROOM));
+        true
+    }
+
+    // replaces the history with prepared `slots` (newest first), the newest one
+    // plays unless going back through the history, `slots` are left with the
+    // buffers they were swapped with
+    pub(crate) fn load_history(&mut self, slots: &mut [Snapshot], period: usize) {
+        self.clear_history();
+        for slot in slots.iter_mut().take(HISTORY_LEN).rev().filter(|slot| !slot.is_empty()) {
+            self.load_snapshot(slot, period);
+        }
+        if self.kept > 0 {
+            self.play_recalled();
+        }
+    }
+
+    // plays the snapshot `back` captures before the latest, or the oldest one
+    // kept if there aren't that many, fading out the one playing under it
+    pub(crate) fn recall(&mut self, params: &Params, back: usize) {
+        if back == self.recalled {
+            return
+        }
+        self.recalled = back;
+        if self.kept == 0 {
+            return
+        }
+        let rate = self.last_rate.unwrap_or_else(|| params.playback_rate.modulated());
+        self.render_tail(params, rate);
+        self.play_recalled();
+    }
+
+    fn play_recalled(&mut self) {
+        self.playing = (self.newest + self.recalled.min(self.kept - 1)) % HISTORY_LEN;
+        self.fresh_snapshot = true;
+    }
+
+    // where playback is in the playing snapshot, `0 ..= 1`
+    pub(crate) fn playhead(&self) -> f32 {
+        if self.reversed { 1.0 - self.current_offset_norm } else { self.current_offset_norm }
+    }
+
+    // the playing snapshot, at the internal level
+    pub(crate) fn playing_snapshot(&self) -> &[f32] {
+        self.current().samples()
+    }
+
+    // the same settings are applied to all channels at the start of a block so
+    // they glide in lockstep
+    pub(crate) fn set_freeze(&mut self, frozen: bool, transpose: f32, glide_samples: f32) {
+        self.frozen = frozen;
+        let target = if frozen { transpose } else { 0.0 };
+        if target != self.transpose_target {
+            self.transpose_target = target;
+            // the glide takes the same time however far it goes
+            self.transpose_step = (target - self.transpose).abs() / glide_samples.max(1.0);
+        }
+    }
+
+    // measures the loop gain from the peaks of consecutive snapshots and turns the
+    // feedback down while it keeps pushing them over the ceiling
+    pub(crate) fn limit_feedback(&mut self, snapshot: &[f32], feedback: f32) {
+        let peak = snapshot.iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        let loop_gain = peak / self.snapshot_peak.max(f32::EPSILON);
+        self.feedback_gain = if feedback <= 0.0 {
+            1.0
+        } else if peak > FEEDBACK_CEILING * HEADROOM {
+            // hold while the level is already on its way down
+            if loop_gain >= 1.0 {
+                self.feedback_gain * FEEDBACK_CEILING * HEADROOM / peak
+            } else {
+                self.feedback_gain
+            }
+        } else {
+            (self.feedback_gain * FEEDBACK_RECOVERY).min(1.0)
+        };
+        self.snapshot_peak = peak;
+    }
+
+    pub(crate) fn set_enabled(&mut self, capture: bool, playback: bool, fade_samples: f32) {
+        self.capture_target = if capture { 1.0 } else { 0.0 };
+        self.playback_target = if playback { 1.0 } else { 0.0 };
+        self.enable_step = 1.0 / fade_samples.max(1.0);
+    }
+
+    pub(crate) fn set_edge_fade(&mut self, samples: usize) {
+        self.edge_fade = samples;
+    }
+
+    pub(crate) fn set_envelope(&mut self, attack: usize, decay: usize) {
+        self.attack = attack;
+        self.decay = decay;
+    }
+
+    pub(crate) fn set_crossing_timeout(&mut self, samples: usize) {
+        self.crossing_timeout = samples;
+    }
+
+    pub(crate) fn set_tail(&mut self, samples: usize) {
+        self.tail_len = samples;
+    }
+
+    // what the outgoing snapshot would have played next, at `rate` under the rate
+    // envelope, so it can be faded out under the new one however different the two
+    // are, read the same way the playback reads it
+    pub(crate) fn render_tail(&mut self, params: &Params, rate: f32) {
+        self.tail.clear();
+        self.tail_pos = 0;
+        let snapshot = &self.snapshots[self.playing];
+        if snapshot.is_empty() {
+            return
+        }
+        let increment = 1.0 / snapshot.len() as f32;
+        let slices = slice_count(params);
+        let (loop_start, loop_length) =
+            loop_bounds(params.loop_start.modulated(), params.loop_length.get(), increment, self.reversed);
+        let rate_start = params.rate_start.get();
+        let rate_slope = (params.rate_end.get() - rate_start) / self.current_period.max(1) as f32;
+        let ratio = semitones_to_ratio(self.transpose) * self.rate_variation * self.spread_rate;
+        let mut offset = self.current_offset_norm;
+        for i in 0..self.tail_len {
+            let envelope = rate_start + rate_slope * (self.current_offset_total + i) as f32;
+            let step = rate * envelope * ratio;
+            offset = wrap_loop(offset, loop_start, loop_length);
+            self.tail.push(scan(snapshot, offset, step, self.reversed));
+            offset = wrap_slice((offset + increment * step) % 1.0, self.slice_start, slices, increment);
+        }
+    }
+
+    pub(crate) fn set_wet_filter(&mut self, highpass: f32, lowpass: f32) {
+        self.highpass.set_coef(highpass);
+        self.lowpass.set_coef(lowpass);
+    }
+
+    pub(crate) fn set_crush(&mut self, bits: f32, downsample: f32) {
+        self.crusher.set_settings(bits, downsample);
+    }
+
+    pub(crate) fn set_delay(&mut self, samples: f32) {
+        self.delay.set_delay(samples);
+    }
+
+    pub(crate) fn set_vibrato(&mut self, step: f32, depth: f32) {
+        self.vibrato_step = step;
+        self.vibrato_depth = depth;
+    }
+
+    pub(crate) fn set_tremolo(&mut self, step: f32) {
+        self.tremolo_step = step;
+    }
+
+    pub(crate) fn set_variation(&mut self, rate: f32, offset: f32) {
+        self.rate_variation = rate;
+        self.start_offset = offset;
+    }
+
+    pub(crate) fn set_spread(&mut self, rate: f32, offset: f32) {
+        self.spread_rate = rate;
+        self.spread_offset = offset;
+    }
+
+    // normalized offset the snapshot starts playing from
+    pub(crate) fn start_position(&self) -> f32 {
+        (self.start_offset + self.spread_offset) % 1.0
+    }
+
+    // ends the period early, what has been captured so far plays next
+    pub(crate) fn end_cycle(&mut self) {
+        self.next_buffer.truncate(self.next_buffer_len);
+        self.current_offset_total = self.current_period;
+    }
+
+    // starts the random decisions over, the same seed on all channels keeps them in lockstep
+    pub(crate) fn reseed(&mut self, seed: u32) {
+        self.random = Random::new(seed);
+        self.grains.reseed(seed);
+    }
+
+    // silent until a new snapshot is captured
+    pub(crate) fn clear(&mut self) {
+        self.clear_history();
+        self.window_pos = 0;
+        self.snapshot_peak = 0.0;
+        self.feedback_gain = 1.0;
+        self.restart_cycle();
+    }
+
+    fn clear_history(&mut self) {
+        self.snapshots.iter_mut().for_each(Snapshot::clear);
+        self.newest = 0;
+        self.kept = 0;
+        // none of the slots measured before are kept
+        self.pushed += HISTORY_LEN;
+        self.playing = 0;
+    }
+
+    // starts the current period over, keeping the playing snapshot
+    pub(crate) fn restart_cycle(&mut self) {
+        self.current_offset_norm = 0.0;
+        self.capture_peak = 0.0;
+        self.capture_energy = 0.0;
+        self.head_offsets = [0.0; HEADS];
+        self.current_offset_total = 0;
+        self.next_buffer_len = 0;
+    }
+
+    // moves to `pos` samples since the start of the song, as if the cycles had been
+    // running from there without interruption
+    pub(crate) fn align_cycle(&mut self, pos: f64, playback_rate: f32) {
+        if self.current_period == 0 {
+            return
+        }
+        self.current_offset_total = pos.rem_euclid(self.current_period as f64) as usize;
+        self.next_buffer_len = usize::min(self.current_offset_total, self.next_buffer.len());
+        if !self.current().is_empty() {
+            let scanned = self.current_offset_total as f32 * playback_rate / self.current().len() as f32;
+            self.current_offset_norm = scanned.fract();
+            self.head_offsets = [self.current_offset_norm; HEADS];
+        }
+    }
+}
+
+#[cfg(test)]
+impl ChannelState {
+    // mid-cycle, playing `buffer` while capturing a full period
+    pub(crate) fn with_snapshot(buffer: &[f32], period: usize) -> Self {
+        let mut state = Self::default();
+        state.load_snapshot(&mut Snapshot::prepare(buffer, buffer.len()), period);
+        state.next_buffer = vec![0.0; period];
+        state
+    }
+
+    pub(crate) fn current_len(&self) -> usize {
+        self.current().len()
+    }
+
+    // the part of the next buffer captured so far, at full scale
+    pub(crate) fn captured(&self) -> Vec<f32> {
+        self.next_buffer[..self.next_buffer_len].iter().map(|sample| sample / HEADROOM).collect()
+    }
+
+    // the playing snapshot at full scale
+    pub(crate) fn snapshot(&self) -> Vec<f32> {
+        self.current().samples.iter().map(|sample| sample / HEADROOM).collect()
+    }
+
+    // (samples into the current period, current period)
+    pub(crate) fn cycle(&self) -> (usize, usize) {
+        (self.current_offset_total, self.current_period)
+    }
+
+    pub(crate) fn assert_invariants(&self) {
+        assert!(self.next_buffer_len <= self.next_buffer.len());
+        assert!((0.0..1.0).contains(&self.current_offset_norm));
+        assert!(self.head_offsets.iter().all(|offset| (0.0..1.0).contains(offset)));
+        assert!(self.current_period == 0 || self.current_len() <= self.current_period);
+    }
+}
+
+impl Default for ChannelState {
+    fn default() -> Self {
+        Self {
+            snapshots: Default::default(),
+            newest: 0,
+            kept: 0,
+            pushed: 0,
+            playing: 0,
+            current_offset_norm: 0.0,
+            head_offsets: [0.0; HEADS],
+            current_offset_total: 0,
+            current_period: 0,
+            next_buffer: Vec::new(),
+            next_buffer_len: 0,
+            window_pos: 0,
+            frozen: false,
+            transpose: 0.0,
+            transpose_target: 0.0,
+            transpose_step: 0.0,
+            last_rate: None,
+            last_position: None,
+            last_input_gain: None,
+            last_output_gain: None,
+            rate_variation: 1.0,
+            start_offset: 0.0,
+            spread_rate: 1.0,
+            spread_offset: 0.0,
+            vibrato_phase: 0.0,
+            vibrato_step: 0.0,
+            vibrato_depth: 0.0,
+            tremolo_phase: 0.0,
+            tremolo_step: 0.0,
+            tremolo_gain: 1.0,
+            capture_gain: 1.0,
+            capture_target: 1.0,
+            playback_gain: 1.0,
+            gate_gain: 1.0,
+            playback_target: 1.0,
+            enable_step: 1.0,
+            edge_fade: 0,
+            attack: 0,
+            decay: 0,
+            silent_samples: 0,
+            capture_peak: 0.0,
+            capture_energy: 0.0,
+            crossing_timeout: 0,
+            awaiting_crossing: 0,
+            loop_search: 0,
+            last_input: 0.0,
+            dc_filter: OnePole::default(),
+            highpass: OnePole::default(),
+            lowpass: OnePole::new(1.0),
+            crusher: Crusher::default(),
+            delay: DelayLine::default(),
+            tail_len: 0,
+            tail: Vec::new(),
+            tail_pos: 0,
+            fresh_snapshot: false,
+            new_period: false,
+            random: Random::new(0),
+            skipped: false,
+            next_jitter: 0.0,
+            reversed: false,
+            slice_start: 0.0,
+            grains: Default::default(),
+            stretcher: Default::default(),
+            recalled: 0,
+            snapshot_peak: 0.0,
+            feedback_gain: 1.0,
+        }
+    }
+}
+
+/// One channel of the repeats without a host around it, for other wrappers and
+/// tools. Parameters are set by the plugin's indices, synced periods follow the
+/// manual tempo and only the repeats come out, mixing in the dry signal is up
+/// to the caller.
+pub struct SnapshotRepeat {
+    params: Params,
+    state: ChannelState,
+    sample_rate: f32,
+    // quarter notes played so far at the manual tempo
+    ppq_pos: f64,
+    // the clear parameter acts once each time it's switched on
+    clear_param: bool,
+    // seed the random decisions were last reset with
+    seed: Option<u32>,
+}
+
+impl Default for SnapshotRepeat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SnapshotRepeat {
+    pub fn new() -> Self {
+        let params = Params::default();
+        let mut state = ChannelState::default();
+        state.allocate(44_100.0, reserved_len(&params));
+        Self {
+            params,
+            state,
+            sample_rate: 44_100.0,
+            ppq_pos: 0.0,
+            clear_param: false,
+            seed: None,
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, rate: f32) {
+        self.sample_rate = rate;
+        self.params.sample_rate.set(rate);
+        self.state.allocate(rate, reserved_len(&self.params));
+    }
+
+    /// Sets the parameter at `index` to a normalized (0.0 .. 1.0) value.
+    pub fn set_param(&mut self, index: usize, value: f32) {
+        self.params.set_normalized(index as i32, value);
+    }
+
+    /// The normalized value of the parameter at `index`.
+    pub fn param(&self, index: usize) -> f32 {
+        self.params.get_normalized(index as i32)
+    }
+
+    /// Replaces `output` with the repeats of `input`, both have to be the same length.
+    pub fn process(&mut self, input: &[f32], output: &mut [f32]) {
+        assert_eq!(input.len(), output.len(), "input and output lengths differ");
+        let _denormals = DenormalGuard::new();
+
+        let clear_param = self.params.clear.get() > 0.5;
+        if clear_param && !self.clear_param {
+            self.state.clear();
+        }
+        self.clear_param = clear_param;
+
+        let seed = self.params.seed.get() as u32;
+        if self.seed != Some(seed) {
+            self.seed = Some(seed);
+            self.state.reseed(seed);
+        }
+
+        let manual_bpm = self.params.manual_bpm.get();
+        let transport = Transport {
+            ppq_pos: (manual_bpm > 0.0).then_some(self.ppq_pos),
+            ppq_per_sample: manual_bpm as f64 / 60.0 / self.sample_rate as f64,
+            ..Default::default()
+        };
+        let settings = ChannelSettings::new(&self.params, self.sample_rate, slice::from_ref(&self.state));
+        self.state.apply(&settings, self.params.freeze.get() > 0.5, 0, 1);
+        process_channel(&self.params, &transport, &mut self.state, input, output);
+        self.ppq_pos = transport.advanced(input.len()).ppq_pos.unwrap_or(0.0);
+        self.params.feedback_limit.set(self.state.feedback_gain);
+    }
+}
+
+/// what the parameters set on every channel, worked out once per block
+pub(crate) struct ChannelSettings {
+    transpose: f32,
+    glide_samples: f32,
+    capture_enable: bool,
+    playback_enable: bool,
+    enable_fade: f32,
+    edge_fade: usize,
+    attack: usize,
+    decay: usize,
+    crossing_timeout: usize,
+    loop_search: usize,
+    dc_filter: f32,
+    highpass: f32,
+    lowpass: f32,
+    tail: usize,
+    crush_bits: f32,
+    downsample: f32,
+    spread: f32,
+    phase_offset: f32,
+    // samples the right channel is delayed by, the left one when negative
+    channel_delay: f32,
+    // vibrato phase per sample and depth in cents
+    vibrato_step: f32,
+    vibrato_depth: f32,
+    // tremolo phase per sample when it isn't synced
+    tremolo_step: f32,
+    grains: GrainSettings,
+    stretch_window: f32,
+}
+
+impl ChannelSettings {
+    pub(crate) fn new(params: &Params, sample_rate: f32, channel_states: &[ChannelState]) -> Self {
+        let ms_to_samples = |ms: f32| ms / 1000.0 * sample_rate;
+        // breaks in the input don't replace the snapshot with silence, all channels have to be quiet
+        let hold_samples = ms_to_samples(params.silence_hold.get()) as usize;
+        let held = hold_samples > 0 && channel_states.iter().all(|chan_state| chan_state.silent_samples >= hold_samples);
+        Self {
+            transpose: params.freeze_transpose.get(),
+            glide_samples: ms_to_samples(params.freeze_glide.get()),
+            capture_enable: params.capture_enable.get() > 0.5 && !held,
+            playback_enable: params.playback_enable.get() > 0.5,
+            enable_fade: ms_to_samples(ENABLE_FADE_MS),
+            edge_fade: ms_to_samples(params.edge_fade.get()).round() as usize,
+            attack: ms_to_samples(params.attack.get()).round() as usize,
+            decay: ms_to_samples(params.decay.get()).round() as usize,
+            crossing_timeout: if params.zero_crossing.get() > 0.5 {
+                ms_to_samples(ZERO_CROSSING_TIMEOUT_MS) as usize
+            } else {
+                0
+            },
+            loop_search: ms_to_samples(LOOP_SEARCH_MS) as usize,
+            dc_filter: if params.dc_filter.get() > 0.5 { one_pole_coef(DC_FILTER_HZ, sample_rate) } else { 0.0 },
+            highpass: match params.wet_highpass.get() {
+                hz if hz > 20.0 => one_pole_coef(hz, sample_rate),
+                _ => 0.0,
+            },
+            lowpass: match params.wet_lowpass.get() {
+                hz if hz < 20000.0 => one_pole_coef(hz, sample_rate),
+                _ => 1.0,
+            },
+            tail: ms_to_samples(params.tail_crossfade.get()).round() as usize,
+            crush_bits: params.crush_bits.get(),
+            downsample: params.downsample.get(),
+            spread: params.spread.get(),
+            phase_offset: params.phase_offset.get(),
+            channel_delay: ms_to_samples(params.channel_delay.get()),
+            vibrato_step: params.vibrato_rate.get() / sample_rate,
+            vibrato_depth: params.vibrato_depth.get(),
+            tremolo_step: params.tremolo_rate.get() / sample_rate,
+            grains: GrainSettings {
+                size: ms_to_samples(params.grain_size.get()),
+                interval: sample_rate / params.grain_density.get(),
+                position_spray: params.position_spray.get(),
+                pitch_spray: params.pitch_spray.get(),
+            },
+            stretch_window: ms_to_samples(STRETCH_WINDOW_MS),
+        }
+    }
+}
+
+impl ChannelState {
+    // `frozen` is passed on its own, triggers can change it in the middle of a block
+    pub(crate) fn apply(&mut self, settings: &ChannelSettings, frozen: bool, chan: usize, channel_count: usize) {
+        self.set_freeze(frozen, settings.transpose, settings.glide_samples);
+        self.set_enabled(settings.capture_enable, settings.playback_enable, settings.enable_fade);
+        self.set_edge_fade(settings.edge_fade);
+        self.set_envelope(settings.attack, settings.decay);
+        self.set_crossing_timeout(settings.crossing_timeout);
+        self.loop_search = settings.loop_search;
+        self.set_tail(settings.tail);
+        self.dc_filter.set_coef(settings.dc_filter);
+        self.set_wet_filter(settings.highpass, settings.lowpass);
+        self.set_crush(settings.crush_bits, settings.downsample);
+        // like the other stereo effects only between the first two channels
+        let delay = match chan {
+            0 if channel_count > 1 => -settings.channel_delay,
+            1 => settings.channel_delay,
+            _ => 0.0,
+        };
+        self.set_delay(delay.max(0.0));
+        self.set_vibrato(settings.vibrato_step, settings.vibrato_depth);
+        self.set_tremolo(settings.tremolo_step);
+        let (spread_rate, spread_offset) =
+            spread_variation(chan, channel_count, settings.spread, settings.phase_offset);
+        self.set_spread(spread_rate, spread_offset);
+        self.grains.set_settings(settings.grains);
+        self.stretcher.set_window(settings.stretch_window);
+    }
+}
+
+// all the actual DSP logic is here, blocks running past the end of a period
+// are split there so each swap lands on its sample however long the blocks are
+pub(crate) fn process_channel<I: Sample, T: Sample>(
+    params: &Params,
+    transport: &Transport,
+    state: &mut ChannelState,
+    inp: &[I],
+    out: &mut [T],
+) {
+    // going back through the history takes effect right away
+    state.recall(params, params.history.get() as usize);
+    let mut start = 0;
+    while start < inp.len() {
+        start += process_period(params, &transport.advanced(start), state, &inp[start..], &mut out[start..]);
+    }
+}
+
+// processes as much of the block as fits in the current period, starting the next
+// one first if it's due, returns how many samples that was
+fn process_period<I: Sample, T: Sample>(
+    params: &Params,
+    transport: &Transport,
+    state: &mut ChannelState,
+    inp: &[I],
+    out: &mut [T],
+) -> usize {
+    let period = period_samples(params, transport);
+    // dbg!(period);
+    let capture_len = capture_samples(params, period);
+    // dbg!(capture_len);
+    let playback_rate = params.playback_rate.modulated();
+    // dbg!(playback_rate);
+    let pump_depth = params.pump_depth.modulated();
+    let pump_shape = params.pump_shape.get();
+    let tremolo_depth = params.tremolo_depth.get();
+    let tremolo_shape = TremoloShape::from_param(params.tremolo_shape.get());
+    let mut gate = [true; GATE_STEPS];
+    for (open, step) in gate.iter_mut().zip(&params.gate_steps) {
+        *open = step.get() > 0.5;
+    }
+    // quarter notes in a tremolo cycle when it follows the host
+    let tremolo_beats = match transport.ppq_pos {
+        Some(_) if params.tremolo_sync.get() > 0.5 => {
+            Some(transport.note_value_beats(note_value(params.tremolo_rate.get_raw())))
+        }
+        _ => None,
+    };
+    let feedback = params.feedback.get();
+    let overdub = params.overdub.get() > 0.5;
+    let engine = Engine::from_param(params.engine.get());
+    let time_stretch = params.time_stretch.get() > 0.5;
+    let fade_curve = FadeCurve::from_param(params.fade_curve.get());
+    let divisions = 1 << params.repeat_division.get() as usize;
+    let slices = slice_count(params);
+    let slice_random = params.slice_random.get();
+    let loop_start = params.loop_start.modulated();
+    let loop_length = params.loop_length.get();
+    let scrub = params.scrub.get() > 0.5;
+    let position = params.position.get();
+    let input_gain = db_to_gain(params.input_trim.get());
+    let output_gain = db_to_gain(params.output_gain.get());
+    // (rate multiple, gain) of each extra head, silent past the number of taps
+    let tap_count = params.taps.get() as usize;
+    let heads = [
+        (params.tap_1_rate.get(), &params.tap_1_level, tap_count >= 1),
+        (params.tap_2_rate.get(), &params.tap_2_level, tap_count >= 2),
+        (params.tap_3_rate.get(), &params.tap_3_level, tap_count >= 3),
+        (params.tap_4_rate.get(), &params.tap_4_level, tap_count >= 4),
+        (0.5, &params.octave_down_level, true),
+        (2.0, &params.octave_up_level, true),
+        (1.5, &params.fifth_level, true),
+    ].map(|(rate, level, playing)| (rate, if playing { db_to_gain(level.get()) } else { 0.0 }));
+    // eprintln!("");
+
+    // finished one period, swap buffers and update parameters
+    if state.current_offset_total >= state.current_period {
+        let jitter = params.period_jitter.get() / 100.0;
+        let jittered = |offset: f32| ((period as f32 * (1.0 + offset * jitter)).round() as usize).max(1);
+        let period = jittered(state.next_jitter);
+        // drawn a period ahead so the capture fits the period it's played in, and
+        // only when jittering so the other random decisions of a seed stay the same
+        state.next_jitter = if jitter > 0.0 { state.random.next_f32() * 2.0 - 1.0 } else { 0.0 };
+        state.current_period = period;
+        state.current_offset_total = 0;
+        state.new_period = true;
+
+        // takes the minimum because we can't manage to capture more than `period` samples
+        let next_buffer_size = capture_len.min(period).min(jittered(state.next_jitter));
+        let filled = mem::take(&mut state.next_buffer_len);
+        state.awaiting_crossing = state.crossing_timeout;
+
+        let mut captured = mem::take(&mut state.next_buffer);
+        let loop_points = LoopPoints::from_param(params.loop_points.get());
+        if loop_points != LoopPoints::Off && filled > 0 {
+            let range = find_loop_points(&captured[..filled], loop_points, state.loop_search);
+            captured.truncate(range.end);
+            captured.drain(..range.start);
+        }
+        let normalize = Normalize::from_param(params.normalize.get());
+        normalize_snapshot(&mut captured, normalize, params.normalize_target.get());
+        state.limit_feedback(&captured, feedback);
+        fade_edges(&mut captured, state.edge_fade, fade_curve);
+        // when frozen, going back through the history, not capturing, nothing was
+        // captured, or the input stayed under the threshold or silent (if skipped)
+        // the snapshot keeps looping uninterrupted, each channel decides on its own
+        let loud_enough = mem::take(&mut state.capture_peak) >= db_to_gain(params.capture_threshold.get());
+        let rms = (mem::take(&mut state.capture_energy) / captured.len().max(1) as f64).sqrt() as f32;
+        let silent = params.skip_silence.get() > 0.5 && rms < SILENCE_LEVEL;
+        let promote = !state.frozen && state.recalled == 0 && state.capture_target > 0.0 && loud_enough && !silent
+            && !captured.is_empty();
+        if promote {
+            state.render_tail(params, state.last_rate.unwrap_or(playback_rate));
+        }
+        // decided once for the whole period so each repeat stays coherent
+        state.skipped = state.random.next_f32() >= params.repeat_probability.get();
+        state.reversed = state.random.next_f32() < params.reverse_probability.get();
+        if promote {
+            state.current_offset_norm = state.start_position();
+            state.head_offsets = [state.current_offset_norm; HEADS];
+            let position = CapturePosition::from_param(params.capture_position.get());
+            if position == CapturePosition::Offset {
+                // the same as writing from the offset on and wrapping around at the end
+                let shift = (params.capture_offset.get() * captured.len() as f32) as usize;
+                captured.rotate_right(shift);
+            }
+            let slot = state.next_slot();
+            let layered = position == CapturePosition::Window || position == CapturePosition::Mosaic || overdub;
+            if layered && !state.current().is_empty() {
+                // written over a copy of the playing snapshot so the history keeps it as it was
+                let (playing, next) = slot_pair(&mut state.snapshots, state.playing, slot);
+                next.samples.clear();
+                next.samples.extend_from_slice(&playing.samples);
+                let next = &mut next.samples;
+                if position == CapturePosition::Window {
+                    state.window_pos = write_window(next, &captured, state.window_pos, overdub);
+                } else if position == CapturePosition::Mosaic {
+                    if state.random.next_f32() < params.mosaic_probability.get() {
+                        let segments = params.mosaic_segments.get() as usize + 2;
+                        let segment = if params.mosaic_random.get() > 0.5 {
+                            (state.random.next_f32() * segments as f32) as usize
+                        } else {
+                            state.window_pos % segments
+                        };
+                        state.window_pos = segment + 1;
+                        let segment_len = (next.len() + segments - 1) / segments;
+                        if let Some(segment) = next.chunks_mut(segment_len).nth(segment) {
+                            write_window(segment, &captured, 0, overdub);
+                        }
+                    }
+                } else {
+                    // the new layer goes on top, the loop keeps the length of the first take
+                    next.iter_mut()
+                        .zip(captured.iter())
+                        .for_each(|(layered, captured)| *layered += captured);
+                }
+            } else {
+                // the oldest snapshot's buffer is captured into next, neither is reallocated
+                mem::swap(&mut state.snapshots[slot].samples, &mut captured);
+            }
+            // built while it plays
+            state.snapshots[slot].mipmaps.iter_mut().for_each(Vec::clear);
+            state.push_snapshot(slot);
+        }
+        captured.clear();
+        captured.resize(next_buffer_size, 0.0);
+        state.next_buffer = captured;
+    }
+    let len = usize::min(inp.len(), state.current_period - state.current_offset_total);
+    let (inp, out) = (&inp[..len], &mut out[..len]);
+    let playing = state.playing;
+    state.snapshots[playing].build_mipmaps(len * MIPMAP_PACE);
+    let cycle_pos = state.current_offset_total;
+    state.current_offset_total += len;
+    let start_position = state.start_position();
+
+    for inp in inp {
+        state.silent_samples = if inp.to_f32().abs() < SILENCE_LEVEL { state.silent_samples + 1 } else { 0 };
+    }
+
+    // the capture waits for the input to cross zero so the snapshot doesn't start with a step
+    let mut capture_inp = inp;
+    while state.awaiting_crossing > 0 {
+        let sample = match capture_inp.first() {
+            Some(sample) => sample.to_f32(),
+            None => break,
+        };
+        if (sample < 0.0) != (state.last_input < 0.0) {
+            state.awaiting_crossing = 0;
+            break
+        }
+        state.last_input = sample;
+        state.awaiting_crossing -= 1;
+        capture_inp = &capture_inp[1..];
+    }
+    if let Some(last) = inp.last() {
+        state.last_input = last.to_f32();
+    }
+
+    // gain changes would step at every block boundary, they move linearly across the block instead
+    let start_input_gain = state.last_input_gain.replace(input_gain).unwrap_or(input_gain);
+    let input_gain_step = (input_gain - start_input_gain) / inp.len().max(1) as f32;
+    let start_output_gain = state.last_output_gain.replace(output_gain).unwrap_or(output_gain);
+    let output_gain_step = (output_gain - start_output_gain) / out.len().max(1) as f32;
+
+    // if the next buffer is not full write to it from the input, the samples
+    // skipped waiting for a zero crossing still count for the gain
+    let capture_start = state.next_buffer_len;
+    let skipped = inp.len() - capture_inp.len();
+    if state.next_buffer.len() > state.next_buffer_len {
+        for (i, (inp, out)) in capture_inp.iter().zip(&mut state.next_buffer[state.next_buffer_len..]).enumerate() {
+            state.capture_gain = ramp(state.capture_gain, state.capture_target, state.enable_step);
+            let input_gain = start_input_gain + input_gain_step * (skipped + i + 1) as f32;
+            let sample = state.dc_filter.highpass(inp.to_f32()) * input_gain;
+            state.capture_peak = state.capture_peak.max(sample.abs());
+            state.capture_energy += sample as f64 * sample as f64;
+            *out = sample * state.capture_gain * HEADROOM;
+        }
+        // the capture stops once the next buffer is full, when the capture length is
+        // shorter than the period the rest of the input isn't taken
+        state.next_buffer_len = usize::min(state.next_buffer_len + capture_inp.len(), state.next_buffer.len());
+    }
+    // the part of the next buffer written this block, the feedback goes there
+    // lined up with the input it was captured from
+    let captured = &mut state.next_buffer[capture_start..state.next_buffer_len];
+    let feedback = soft_feedback(feedback) * state.feedback_gain;
+    // rate steps are the most audible zipper noise, the rate moves linearly across the block
+    let start_rate = state.last_rate.replace(playback_rate).unwrap_or(playback_rate);
+    let start_scrub = state.last_position.replace(position).unwrap_or(position);
+    let scrub_step = (position - start_scrub) / out.len() as f32;
+
+    // keep quiet if the buffer is empty
+    let buffer = &state.snapshots[state.playing];
+    if buffer.is_empty() {
+        out.fill(T::default());
+        return len
+    }
+
+    // use the last recorded buffer as a wavetable, scan at the original speed * playback_rate
+    let mut offset = state.current_offset_norm;
+    let increment = 1.0 / buffer.len() as f32;
+    let rate_step = (playback_rate - start_rate) / out.len() as f32;
+    let period = state.current_period.max(1);
+    // tape stops and rises over the period
+    let rate_start = params.rate_start.get();
+    let rate_slope = (params.rate_end.get() - rate_start) / period as f32;
+    let division_len = (period / divisions).max(1);
+    let swing_delay = (division_len as f32 * params.division_swing.get()).round() as usize;
+    let mut transpose = state.transpose;
+    let mut transpose_ratio = semitones_to_ratio(transpose);
+    let (loop_start, loop_length) = loop_bounds(loop_start, loop_length, increment, state.reversed);
+    for (i, out) in out.iter_mut().enumerate() {
+        // stutter, each division of the period starts the snapshot (or the next slice) over, the
+        // odd ones late when swung
+        if divisions > 1 || slices.is_some() {
+            let division = (cycle_pos + i) / division_len;
+            let restart = if division % 2 == 1 { swing_delay } else { 0 };
+            if (cycle_pos + i) % division_len == restart {
+                offset = match slices {
+                    Some(slices) => {
+                        let slice = if state.random.next_f32() < slice_random {
+                            (state.random.next_f32() * slices as f32) as usize
+                        } else {
+                            division % slices
+                        };
+                        state.slice_start = (start_position + slice as f32 / slices as f32) % 1.0;
+                        state.slice_start
+                    }
+                    None => start_position,
+                };
+                state.head_offsets = [offset; HEADS];
+            }
+        }
+        if transpose != state.transpose_target {
+            let delta = (state.transpose_target - transpose).clamp(-state.transpose_step, state.transpose_step);
+            transpose += delta;
+            transpose_ratio = semitones_to_ratio(transpose);
+        }
+
+        let envelope = rate_start + rate_slope * (cycle_pos + i) as f32;
+        let rate = (start_rate + rate_step * (i + 1) as f32) * envelope;
+        let vibrato = if state.vibrato_depth > 0.0 {
+            state.vibrato_phase = (state.vibrato_phase + state.vibrato_step).fract();
+            semitones_to_ratio(state.vibrato_depth / 100.0 * (state.vibrato_phase * TAU).sin())
+        } else {
+            1.0
+        };
+        // samples of the snapshot passed each sample
+        let step = rate * transpose_ratio * state.rate_variation * state.spread_rate * vibrato;
+
+        if scrub {
+            // the position counts through the looped part, backwards from its end when reversed
+            let position = start_scrub + scrub_step * (i + 1) as f32;
+            let position = if state.reversed { 1.0 - position } else { position };
+            offset = (loop_start + position * loop_length.min(1.0)) % 1.0;
+        }
+        offset = wrap_loop(offset, loop_start, loop_length);
+
+        let mut value = match engine {
+            Engine::Scan if !time_stretch => scan(buffer, offset, step, state.reversed),
+            // the scan position still moves at the playback rate, the grains (or the
+            // windows when stretching) are only transposed
+            engine => {
+                let (head, direction) = if state.reversed { (1.0 - offset, -1.0) } else { (offset, 1.0) };
+                let pitch = direction * transpose_ratio * state.rate_variation * state.spread_rate * vibrato;
+                match engine {
+                    Engine::Granular => state.grains.next(&buffer.samples, head, pitch),
+                    Engine::Scan => state.stretcher.next(&buffer.samples, head, pitch),
+                }
+            }
+        };
+        // silent heads keep moving so they're in place when they come in
+        for (&(head_rate, head_gain), head_offset) in heads.iter().zip(&mut state.head_offsets) {
+            let head_step = step * head_rate;
+            *head_offset = wrap_loop(*head_offset, loop_start, loop_length);
+            if head_gain > 0.0 {
+                value += scan(buffer, *head_offset, head_step, state.reversed) * head_gain;
+            }
+            *head_offset = (*head_offset + increment * head_step) % 1.0;
+        }
+        if let Some(&tail) = state.tail.get(state.tail_pos) {
+            let fade = state.tail_pos as f32 / state.tail.len() as f32;
+            value = value * fade_curve.gain(fade) + tail * fade_curve.gain(1.0 - fade);
+            state.tail_pos += 1;
+        }
+        offset = wrap_slice((offset + increment * step) % 1.0, state.slice_start, slices, increment);
+
+        if let Some(captured) = i.checked_sub(skipped).and_then(|i| captured.get_mut(i)) {
+            *captured += feedback * value;
+        }
+        // after the feedback, the snapshots themselves don't get duller with every period
+        value = state.crusher.process(value);
+        value = state.lowpass.lowpass(state.highpass.highpass(value));
+
+        // pump once per beat when the host tells us the tempo, once per repeat otherwise
+        if pump_depth > 0.0 {
+            let phase = match transport.ppq_pos {
+                Some(ppq) => (ppq + i as f64 * transport.ppq_per_sample).rem_euclid(1.0) as f32,
+                None => ((cycle_pos + i) % period) as f32 / period as f32,
+            };
+            value *= modulation::pump_gain(phase, pump_depth, pump_shape);
+        }
+        if tremolo_depth > 0.0 {
+            let phase = match (tremolo_beats, transport.ppq_pos) {
+                (Some(beats), Some(ppq)) if transport.playing => {
+                    let phase = ((ppq + i as f64 * transport.ppq_per_sample) / beats).rem_euclid(1.0) as f32;
+                    // picked up from here when the host stops
+                    state.tremolo_phase = phase;
+                    phase
+                }
+                _ => {
+                    // a stopped host keeps its position, synced the tremolo runs on at its tempo
+                    let step = match tremolo_beats {
+                        Some(beats) => (transport.ppq_per_sample / beats) as f32,
+                        None => state.tremolo_step,
+                    };
+                    state.tremolo_phase = (state.tremolo_phase + step).fract();
+                    state.tremolo_phase
+                }
+            };
+            state.tremolo_gain = ramp(state.tremolo_gain, tremolo_shape.gain(phase), state.enable_step);
+            value *= 1.0 - tremolo_depth * (1.0 - state.tremolo_gain);
+        }
+        // the gate pattern splits the period into equal steps
+        let gate_step = ((cycle_pos + i) * GATE_STEPS / period).min(GATE_STEPS - 1);
+        state.gate_gain = ramp(state.gate_gain, if gate[gate_step] { 1.0 } else { 0.0 }, state.enable_step);
+        value *= state.gate_gain;
+        if state.attack > 0 || state.decay > 0 {
+            value *= amp_envelope(cycle_pos + i, state.attack, state.decay);
+        }
+
+        value = state.delay.process(value);
+
+        state.playback_gain = ramp(state.playback_gain, state.playback_target, state.enable_step);
+        let output_gain = start_output_gain + output_gain_step * (i + 1) as f32;
+        *out = T::from_f32(value * output_gain * state.playback_gain / HEADROOM);
+    }
+    state.current_offset_norm = offset;
+    state.transpose = transpose;
+    len
+}
+
+// the snapshot in slot `a` to read and the one in slot `b` to write, which must differ
+fn slot_pair(slots: &mut [Snapshot], a: usize, b: usize) -> (&Snapshot, &mut Snapshot) {
+    if a < b {
+        let (low, high) = slots.split_at_mut(b);
+        (&low[a], &mut high[0])
+    } else {
+        let (low, high) = slots.split_at_mut(a);
+        (&high[0], &mut low[b])
+    }
+}
+
+// slice mode plays one of this many equal parts of the snapshot each division
+fn slice_count(params: &Params) -> Option<usize> {
+    match params.slice_length.get() as u32 {
+        0 => None,
+        length => Some(1 << length),
+    }
+}
+
+// the looped part in terms of the scan offset, mirrored backwards like the offset is
+fn loop_bounds(loop_start: f32, loop_length: f32, increment: f32, reversed: bool) -> (f32, f32) {
+    let loop_length = loop_length.max(increment);
+    let loop_start = if reversed { (1.0 - loop_start - loop_length).rem_euclid(1.0) } else { loop_start };
+    (loop_start, loop_length)
+}
+
+// the slice playing keeps looping until the next division, an offset a rounding
+// error short of its end already wraps since `scan` would read past the slice there
+fn wrap_slice(offset: f32, slice_start: f32, slices: Option<usize>, increment: f32) -> f32 {
+    let slices = match slices {
+        Some(slices) => slices,
+        None => return offset,
+    };
+    let slice_width = 1.0 / slices as f32;
+    let tolerance = INDEX_TOLERANCE * increment;
+    let into_slice = (offset - slice_start).rem_euclid(1.0);
+    if into_slice + tolerance >= slice_width {
+        (slice_start + ((into_slice + tolerance) % slice_width - tolerance).max(0.0)) % 1.0
+    } else {
+        offset
+    }
+}
+
+// anywhere outside the looped part wraps back into it
+fn wrap_loop(offset: f32, loop_start: f32, loop_length: f32) -> f32 {
+    let into_loop = (offset - loop_start).rem_euclid(1.0);
+    if loop_length < 1.0 && into_loop >= loop_length {
+        (loop_start + into_loop % loop_length) % 1.0
+    } else {
+        offset
+    }
+}
+
+// reads the sample of the snapshot at or before the normalized `offset`, `step` is
+// how many samples of it pass each sample
+fn scan(snapshot: &Snapshot, offset: f32, step: f32, reversed: bool) -> f32 {
+    // from twice the original rate on a copy filtered for it is read so it doesn't
+    // alias, a finer one while that isn't built yet
+    let level = if step >= 2.0 { usize::min(step.log2() as usize, MIP_LEVELS) } else { 0 };
+    let buffer = match usize::min(level, snapshot.mipmaps_built()) {
+        0 => &snapshot.samples,
+        level => &snapshot.mipmaps[level - 1],
+    };
+    let len = buffer.len() as f32;
+    // backwards the position is mirrored, the start offset counts from the end
+    let idx = if reversed { (len - 1.0 - offset * len).rem_euclid(len) } else { offset * len };
+    // an offset a rounding error short of a sample still reads that sample
+    let low_idx = (idx + INDEX_TOLERANCE).floor() as usize % buffer.len();
+
+    buffer[low_idx]
+}
+
+// low-passes `source` for half its rate and appends `count` more of every other
+// sample of it to `target`, wrapping around since the snapshot loops
+fn half_band(source: &[f32], target: &mut Vec<f32>, count: usize) {
+    let reach = 2 * HALF_BAND.len() - 1;
+    let len = source.len();
+    for i in target.len()..target.len() + count {
+        let center = 2 * i;
+        let sides = if center >= reach && center + reach < len {
+            HALF_BAND.iter()
+                .enumerate()
+                .map(|(k, tap)| tap * (source[center - 2 * k - 1] + source[center + 2 * k + 1]))
+                .sum::<f32>()
+        } else {
+            let at = |i: isize| source[i.rem_euclid(len as isize) as usize];
+            HALF_BAND.iter()
+                .enumerate()
+                .map(|(k, tap)| {
+                    let distance = 2 * k as isize + 1;
+                    tap * (at(center as isize - distance) + at(center as isize + distance))
+                })
+                .sum::<f32>()
+        };
+        target.push(0.5 * source[center] + sides);
+    }
+}
+
+// writes `captured` into `snapshot` from `pos` on, wrapping around, and returns where the next window starts
+fn write_window(snapshot: &mut [f32], captured: &[f32], pos: usize, overdub: bool) -> usize {
+    let len = snapshot.len();
+    for (i, &sample) in captured.iter().take(len).enumerate() {
+        let slot = &mut snapshot[(pos + i) % len];
+        *slot = if overdub { *slot + sample } else { sample };
+    }
+    (pos + captured.len()) % len
+}
+
+// the part of `snapshot` between loop points that join up smoothly, searched
+// for within `search` samples of each end, all of it if there are none
+fn find_loop_points(snapshot: &[f32], mode: LoopPoints, search: usize) -> Range<usize> {
+    let len = snapshot.len();
+    let search = search.min(len / 2);
+    if search < 2 {
+        return 0..len
+    }
+    match mode {
+        LoopPoints::Off => 0..len,
+        LoopPoints::ZeroCrossings => {
+            // both rising so the wave goes on the way it left off
+            let rising = |&i: &usize| snapshot[i - 1] < 0.0 && snapshot[i] >= 0.0;
+            let start = (1..search).find(rising);
+            let end = (len - search..len).rev().find(rising);
+            match (start, end) {
+                (Some(start), Some(end)) => start..end,
+                _ => 0..len,
+            }
+        }
+        LoopPoints::BestMatch => {
+            // compares what follows each end with the start of the snapshot, first every
+            // few ends on every few samples, then every end around the best of those,
+            // so the search stays short enough for the sample the period ends on
+            let overlap = search / 2;
+            let difference = |end: usize, stride: usize| -> f32 {
+                snapshot[end..end + overlap].iter()
+                    .zip(snapshot)
+                    .step_by(stride)
+                    .map(|(a, b)| (a - b) * (a - b))
+                    .sum()
+            };
+            let best = |ends: RangeInclusive<usize>, stride: usize| {
+                ends.step_by(stride)
+                    .map(|end| (difference(end, stride), end))
+                    .fold((f32::INFINITY, len), |best, candidate| if candidate.0 < best.0 { candidate } else { best })
+                    .1
+            };
+            let (first, last) = (len - search, len - overlap);
+            let coarse = best(first..=last, LOOP_SEARCH_STRIDE);
+            let around = coarse.saturating_sub(LOOP_SEARCH_STRIDE).max(first)..=(coarse + LOOP_SEARCH_STRIDE).min(last);
+            0..best(around, 1)
+        }
+    }
+}
+
+// fades the first and last `len` samples in and out so the snapshot doesn't
+// click where the capture cut into the waveform
+fn fade_edges(snapshot: &mut [f32], len: usize, curve: FadeCurve) {
+    let len = usize::min(len, snapshot.len() / 2);
+    let last = snapshot.len().saturating_sub(1);
+    for i in 0..len {
+        let gain = curve.gain(i as f32 / len as f32);
+        snapshot[i] *= gain;
+        snapshot[last - i] *= gain;
+    }
+}
+
+// passes the feedback through up to the knee, above it approaches (but never reaches) the maximum
+pub(crate) fn soft_feedback(amount: f32) -> f32 {
+    if amount <= FEEDBACK_KNEE {
+        return amount
+    }
+    let range = FEEDBACK_MAX - FEEDBACK_KNEE;
+    FEEDBACK_KNEE + range * ((amount - FEEDBACK_KNEE) / range).tanh()
+}
+
+// passes the sample through up to the knee, above it approaches (but never reaches) the ceiling
+pub(crate) fn soft_clip(sample: f32, ceiling: f32) -> f32 {
+    let knee = ceiling * CLIP_KNEE;
+    if sample.abs() <= knee {
+        return sample
+    }
+    let range = ceiling - knee;
+    (knee + range * ((sample.abs() - knee) / range).tanh()).copysign(sample)
+}
+
+/// Samples the output is held back by with these settings, what the delaying
+/// stages add up to. The host compensates for it.
+pub(crate) fn latency(params: &Params, sample_rate: f32) -> usize {
+    Limiter::lookahead(params, sample_rate)
+}
+
+/// holds each channel back by a fixed number of samples, kept at the precision it
+/// was given so `f64` passes through unchanged
+#[derive(Default)]
+pub(crate) struct FixedDelay {
+    // the last `delay` samples of each channel, in a ring
+    lines: Vec<Vec<f64>>,
+    // where the oldest sample is, the same for all channels
+    pos: usize,
+}
+
+impl FixedDelay {
+    /// silent and holding `channels` back by `delay`, allocates
+    pub(crate) fn allocate(&mut self, channels: usize, delay: usize) {
+        self.lines = vec![vec![0.0; delay]; channels];
+        self.pos = 0;
+    }
+
+    pub(crate) fn delay(&self) -> usize {
+        self.lines.first().map_or(0, Vec::len)
+    }
+
+    /// drops what's held back, keeping the delay
+    pub(crate) fn clear(&mut self) {
+        self.lines.iter_mut().for_each(|line| line.fill(0.0));
+    }
+
+    /// takes the next sample of `chan` and gives the one `delay` samples before
+    /// it, `advance` moves on once all channels of the frame are in
+    pub(crate) fn process<T: Sample>(&mut self, chan: usize, sample: T) -> T {
+        match self.lines.get_mut(chan) {
+            Some(line) if !line.is_empty() => T::from_f64(mem::replace(&mut line[self.pos], sample.to_f64())),
+            _ => sample,
+        }
+    }
+
+    pub(crate) fn advance(&mut self) {
+        let delay = self.delay();
+        if delay > 0 {
+            self.pos = (self.pos + 1) % delay;
+        }
+    }
+}
+
+/// Turns all channels of the output down together while they'd go past the
+/// ceiling so the stereo image holds. It holds the output back by its lookahead
+/// and starts turning down that early, so a peak comes out under the ceiling
+/// without the gain jumping on it.
+pub(crate) struct Limiter {
+    gain: f32,
+    // most gain each frame held back can take, the oldest at `pos`, one more than
+    // the lookahead with the frame just in
+    ceilings: Vec<f32>,
+    pos: usize,
+    held: FixedDelay,
+}
+
+impl Default for Limiter {
+    fn default() -> Self {
+        Self {
+            gain: 1.0,
+            ceilings: vec![1.0],
+            pos: 0,
+            held: FixedDelay::default(),
+        }
+    }
+}
+
+impl Limiter {
+    /// samples the limiter looks ahead by with these settings, only when it's on
+    pub(crate) fn lookahead(params: &Params, sample_rate: f32) -> usize {
+        match OutputClip::from_param(params.output_clip.get()) {
+            OutputClip::Limiter => (LIMITER_LOOKAHEAD_MS / 1000.0 * sample_rate).round() as usize,
+            _ => 0,
+        }
+    }
+
+    /// silent and looking `lookahead` samples ahead on `channels`, allocates
+    pub(crate) fn allocate(&mut self, channels: usize, lookahead: usize) {
+        self.held.allocate(channels, lookahead);
+        self.ceilings = vec![1.0; lookahead + 1];
+        self.pos = 0;
+        self.gain = 1.0;
+    }
+
+    pub(crate) fn lookahead_len(&self) -> usize {
+        self.held.delay()
+    }
+
+    /// drops what's held back and lets go of the gain
+    pub(crate) fn clear(&mut self) {
+        self.held.clear();
+        self.ceilings.fill(1.0);
+        self.gain = 1.0;
+    }
+
+    /// lets go of the gain while the limiter is off, what's held back still comes out
+    pub(crate) fn release(&mut self) {
+        self.gain = 1.0;
+    }
+
+    /// Takes the loudest channel of the next frame, returns the gain for the frame
+    /// coming out of `hold_back`. The gain moves down in a straight line to each
+    /// peak ahead and lets go by `release` of the way each frame.
+    pub(crate) fn next_gain(&mut self, peak: f32, ceiling: f32, release: f32) -> f32 {
+        let len = self.ceilings.len();
+        self.ceilings[self.pos] = if peak > ceiling { ceiling / peak } else { 1.0 };
+        self.pos = (self.pos + 1) % len;
+        let mut allowed = 1.0f32;
+        for ahead in 0..len {
+            let ceiling = self.ceilings[(self.pos + ahead) % len];
+            allowed = allowed.min(1.0 - (1.0 - ceiling) * (len - ahead) as f32 / len as f32);
+        }
+        self.gain += (1.0 - self.gain) * release;
+        self.gain = self.gain.min(allowed);
+        self.gain
+    }
+
+    /// see `FixedDelay::process`
+    pub(crate) fn hold_back<T: Sample>(&mut self, chan: usize, sample: T) -> T {
+        self.held.process(chan, sample)
+    }
+
+    pub(crate) fn advance(&mut self) {
+        self.held.advance();
+    }
+}
+
+/// one-pole low-pass, the high-pass is what the low-pass takes out
+#[derive(Default)]
+struct OnePole {
+    // zero holds the low-pass at zero, one passes the input unchanged
+    coef: f32,
+    state: f32,
+}
+
+impl OnePole {
+    fn new(coef: f32) -> Self {
+        Self { coef, state: 0.0 }
+    }
+
+    fn set_coef(&mut self, coef: f32) {
+        self.coef = coef;
+        // a high-pass switched off doesn't leave a frozen offset behind
+        if coef == 0.0 {
+            self.state = 0.0;
+        }
+    }
+
+    fn lowpass(&mut self, input: f32) -> f32 {
+        self.state += self.coef * (input - self.state);
+        self.state
+    }
+
+    fn highpass(&mut self, input: f32) -> f32 {
+        input - self.lowpass(input)
+    }
+}
+
+/// delays samples by a settable number of samples, gliding to a new delay
+#[derive(Default)]
+struct DelayLine {
+    // the delayed samples, allocated for the longest delay
+    buffer: Vec<f32>,
+    // samples the output is currently delayed by and the delay it glides to,
+    // no target until one is set, the first one is taken right away
+    delay: f32,
+    target: Option<f32>,
+    // where the next sample is written
+    pos: usize,
+}
+
+impl DelayLine {
+    fn allocate(&mut self, max_delay: usize) {
+        if self.buffer.len() <= max_delay {
+            self.buffer = vec![0.0; max_delay + 1];
+            self.pos = 0;
+        }
+    }
+
+    // silent and without a delay set, keeping the buffer
+    fn reset(&mut self) {
+        self.buffer.fill(0.0);
+        self.delay = 0.0;
+        self.target = None;
+        self.pos = 0;
+    }
+
+    fn set_delay(&mut self, samples: f32) {
+        let samples = samples.min(self.buffer.len().saturating_sub(1) as f32);
+        if self.target.is_none() {
+            self.delay = samples;
+        }
+        self.target = Some(samples);
+    }
+
+    // the input is always written so there's nothing stale to hear when the delay comes back
+    fn process(&mut self, input: f32) -> f32 {
+        let len = self.buffer.len();
+        if len == 0 {
+            return input
+        }
+        self.buffer[self.pos] = input;
+        self.delay = ramp(self.delay, self.target.unwrap_or(0.0), DELAY_GLIDE);
+        let read = (self.pos as f32 - self.delay).rem_euclid(len as f32);
+        let low = read.floor() as usize % len;
+        let high = (low + 1) % len;
+        self.pos = (self.pos + 1) % len;
+        self.buffer[low] + (self.buffer[high] - self.buffer[low]) * read.fract()
+    }
+}
+
+/// holds samples to lower the sample rate and rounds them to fewer bits
+struct Crusher {
+    // quantization steps per unit of full scale, none if zero
+    levels: f32,
+    // output samples each input sample is held for, at least one
+    downsample: f32,
+    // samples left until the next input sample is taken
+    phase: f32,
+    held: f32,
+}
+
+impl Default for Crusher {
+    fn default() -> Self {
+        Self {
+            levels: 0.0,
+            downsample: 1.0,
+            phase: 0.0,
+            held: 0.0,
+        }
+    }
+}
+
+impl Crusher {
+    fn set_settings(&mut self, bits: f32, downsample: f32) {
+        self.levels = if bits < 16.0 { 2.0f32.powf(bits - 1.0) } else { 0.0 };
+        self.downsample = downsample.max(1.0);
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        self.phase -= 1.0;
+        if self.phase < 0.0 {
+            self.phase += self.downsample;
+            // rounded at full scale, one bit is just the sign
+            self.held = if self.levels > 0.0 {
+                (input / HEADROOM * self.levels).round().clamp(-self.levels, self.levels) / self.levels * HEADROOM
+            } else {
+                input
+            };
+        }
+        self.held
+    }
+}
+
+fn one_pole_coef(cutoff: f32, sample_rate: f32) -> f32 {
+    1.0 - (-TAU * cutoff / sample_rate).exp()
+}
+
+// rate ratio and start offset of each channel playing a mono input, spread from
+// slower and earlier on the first one to faster and later on the last, the
+// phase offset moves the later channels further into the snapshot whatever the input
+fn spread_variation(chan: usize, channel_count: usize, spread: f32, phase_offset: f32) -> (f32, f32) {
+    if channel_count < 2 {
+        return (1.0, 0.0)
+    }
+    let position = chan as f32 / (channel_count - 1) as f32;
+    let rate = semitones_to_ratio((position * 2.0 - 1.0) * spread * SPREAD_SEMITONES);
+    (rate, position * (spread * SPREAD_OFFSET + phase_offset))
+}
+
+/// The entry of `NOTE_VALUES` the normalized period picks, evenly spread over the range.
+pub(crate) fn note_value(norm: f32) -> usize {
+    Scale::Step { steps: NOTE_VALUES.len() }.to_scaled(norm) as usize
+}
+
+// length of the next period, in sync the ratio of beats (or the note value) is used as long as the host gives us a tempo
+pub(crate) fn period_samples(params: &Params, transport: &Transport) -> usize {
+    let quantize = Quantize::from_param(params.quantize.get());
+    match transport.ppq_pos {
+        Some(ppq) if params.sync.get() > 0.5 => {
+            let beats = if params.note_values.get() > 0.5 {
+                transport.note_value_beats(note_value(params.period.modulated_raw()))
+            } else {
+                ((params.sync_numerator.get() + 1.0) / (params.sync_denominator.get() + 1.0)) as f64
+            };
+            // the groove moves the boundaries by less than half a period so the
+            // nearest one on the grid is the one starting now
+            let step = (ppq / beats).round() as i64;
+            let beats = beats * params.groove.period_scale(step) as f64;
+            ((beats / transport.ppq_per_sample).round() as usize).max(1)
+        }
+        // the period ends on the grid line nearest to where it would end otherwise, but never right away
+        Some(ppq) if quantize != Quantize::Off => {
+            let grid = match quantize {
+                Quantize::Bar => transport.ppq_per_bar.unwrap_or(4.0),
+                _ => 1.0,
+            };
+            let end = ppq + params.length(params.period.modulated()) as f64 * transport.ppq_per_sample;
+            let end = f64::max((end / grid).round() * grid, (ppq / grid).floor() * grid + grid);
+            (((end - ppq) / transport.ppq_per_sample).round() as usize).max(1)
+        }
+        _ => (params.length(params.period.modulated()).round() as usize).max(1),
+    }
+}
+
+// brings `snapshot` (kept at `HEADROOM`) to `target` dBFS, measured by its peak or
+// RMS level, quiet ones get at most `MAX_MAKEUP_DB` and silence stays silent
+fn normalize_snapshot(snapshot: &mut [f32], mode: Normalize, target: f32) {
+    let level = match mode {
+        Normalize::Off => return,
+        Normalize::Peak => snapshot.iter().fold(0.0, |peak: f32, sample| peak.max(sample.abs())),
+        Normalize::Rms => {
+            let sum = snapshot.iter().map(|sample| sample * sample).sum::<f32>();
+            (sum / snapshot.len().max(1) as f32).sqrt()
+        }
+    } / HEADROOM;
+    if level < SILENCE_LEVEL {
+        return
+    }
+    let gain = f32::min(db_to_gain(target) / level, db_to_gain(MAX_MAKEUP_DB));
+    snapshot.iter_mut().for_each(|sample| *sample *= gain);
+}
+
+// level of a repeat `pos` samples into its period, rising over `attack` samples
+// and then falling to silence over `decay` samples unless that's zero
+fn amp_envelope(pos: usize, attack: usize, decay: usize) -> f32 {
+    let rise = if pos < attack { pos as f32 / attack as f32 } else { 1.0 };
+    let fall = match decay {
+        0 => 1.0,
+        decay => 1.0 - (pos.saturating_sub(attack) as f32 / decay as f32).min(1.0),
+    };
+    rise * fall
+}
+
+// the capture length in samples for a period of `period` samples
+fn capture_samples(params: &Params, period: usize) -> usize {
+    if params.capture_ratio.get() > 0.5 {
+        ((params.capture_len.modulated_raw() * period as f32).round() as usize).max(1)
+    } else {
+        (params.length(params.capture_len.modulated()).round() as usize).max(1)
+    }
+}
+
+pub(crate) fn semitones_to_ratio(semitones: f32) -> f32 {
+    (semitones / 12.0).exp2()
+}
+
+// moves `value` towards `target` by at most `step`
+pub(crate) fn ramp(value: f32, target: f32, step: f32) -> f32 {
+    value + (target - value).clamp(-step, step)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::{FRAC_1_SQRT_2, FRAC_PI_4};
+    use crate::sim::{Signal, Simulation};
+    use vst::plugin::PluginParameters;
+
+    fn params(period: f32, capture_len: f32) -> Params {
+        let params = Params::default();
+        params.period.set(period);
+        params.capture_len.set(capture_len);
+        params
+    }
+
+    // the playback position is normalized so it accumulates some rounding error
+    fn assert_close(a: &[f32], b: &[f32]) {
+        assert_eq!(a.len(), b.len());
+        for (a, b) in a.iter().zip(b) {
+            assert!((a - b).abs() < 1e-3, "{} != {}", a, b);
+        }
+    }
+
+    fn run(params: &Params, state: &mut ChannelState, inp: &[f32]) -> Vec<f32> {
+        let mut out = vec![0.0; inp.len()];
+        process_channel(params, &Transport::default(), state, inp, &mut out);
+        state.assert_invariants();
+        out
+    }
+    #[test]
+    fn starts_silent() {
+        let params = params(100.0, 100.0);
+        let mut state = ChannelState::default();
+        let out = run(&params, &mut state, &[1.0; 64]);
+        assert!(out.iter().all(|&s| s == 0.0));
+        assert_eq!(state.current_len(), 0);
+        assert_eq!(state.captured(), &[1.0; 64][..]);
+    }
+
+    #[test]
+    fn standalone_repeats_input() {
+        let mut repeat = SnapshotRepeat::new();
+        let params = params(64.0, 64.0);
+        repeat.set_param(0, params.get_normalized(0));
+        repeat.set_param(1, params.get_normalized(1));
+        // no edge fades so the repeats come out flat
+        repeat.set_param(38, 0.0);
+        assert_eq!(repeat.param(0), params.get_normalized(0));
+
+        let mut out = [1.0; 64];
+        repeat.process(&[1.0; 64], &mut out);
+        assert_eq!(out, [0.0; 64]);
+        repeat.process(&[0.0; 64], &mut out);
+        assert_close(&out, &[1.0; 64]);
+    }
+
+    #[test]
+    fn empty_blocks_keep_state() {
+        let params = params(100.0, 100.0);
+        let mut state = ChannelState::with_snapshot(&[0.5; 10], 100);
+        run(&params, &mut state, &[]);
+        assert_eq!(state.cycle(), (0, 100));
+        assert_eq!(state.current_len(), 10);
+        assert_eq!(state.captured(), &[] as &[f32]);
+    }
+
+    #[test]
+    fn quiet_captures_keep_snapshot() {
+        let params = params(64.0, 64.0);
+        params.edge_fade.set(0.0);
+        params.capture_threshold.set(-20.0);
+        let mut state = ChannelState::default();
+        run(&params, &mut state, &[0.05; 64]);
+        run(&params, &mut state, &[0.5; 64]);
+        // under the threshold, nothing to play yet
+        assert_eq!(state.current_len(), 0);
+        run(&params, &mut state, &[0.05; 64]);
+        assert_close(&state.snapshot(), &[0.5; 64]);
+        run(&params, &mut state, &[0.0; 64]);
+        assert_close(&state.snapshot(), &[0.5; 64]);
+    }
+
+    #[test]
+    fn history_recalls_earlier_captures() {
+        let params = params(64.0, 64.0);
+        params.edge_fade.set(0.0);
+        let mut state = ChannelState::default();
+        for level in &[0.1, 0.2, 0.3, 0.4] {
+            run(&params, &mut state, &[*level; 64]);
+        }
+        assert_close(&state.snapshot(), &[0.3; 64]);
+
+        // two back, held there while the input goes on
+        params.history.set(2.0);
+        run(&params, &mut state, &[0.5; 64]);
+        assert_close(&state.snapshot(), &[0.1; 64]);
+        run(&params, &mut state, &[0.6; 64]);
+        assert_close(&state.snapshot(), &[0.1; 64]);
+        // further back than kept plays the oldest
+        params.history.set(7.0);
+        run(&params, &mut state, &[0.6; 64]);
+        assert_close(&state.snapshot(), &[0.1; 64]);
+
+        // back to the latest capture halfway through a period, the capture going
+        // on replaces it at the end of the period
+        run(&params, &mut state, &[0.6; 32]);
+        params.history.set(0.0);
+        run(&params, &mut state, &[0.7; 32]);
+        assert_close(&state.snapshot(), &[0.3; 64]);
+        run(&params, &mut state, &[0.0; 64]);
+        assert_close(&state.snapshot()[32..], &[0.7; 32]);
+    }
+
+    #[test]
+    fn silent_captures_skipped() {
+        let params = params(64.0, 64.0);
+        params.edge_fade.set(0.0);
+        params.skip_silence.set(1.0);
+        let mut state = ChannelState::default();
+        run(&params, &mut state, &[0.5; 64]);
+        run(&params, &mut state, &[0.0; 64]);
+        run(&params, &mut state, &[0.0; 64]);
+        assert_close(&state.snapshot(), &[0.5; 64]);
+        // quiet isn't silent
+        run(&params, &mut state, &[0.01; 64]);
+        run(&params, &mut state, &[0.0; 64]);
+        assert_close(&state.snapshot(), &[0.01; 64]);
+    }
+
+    #[test]
+    fn capture_longer_than_period() {
+        let params = params(100.0, 1000.0);
+        let mut state = ChannelState::default();
+        run(&params, &mut state, &[1.0; 64]);
+        run(&params, &mut state, &[1.0; 36]);
+        // the capture is cut to the period
+        assert_eq!(state.captured().len(), 100);
+    }
+
+    #[test]
+    fn snapshots_keep_headroom() {
+        let params = params(64.0, 64.0);
+        let mut state = ChannelState::default();
+        run(&params, &mut state, &[1.0; 64]);
+        let out = run(&params, &mut state, &[0.0; 64]);
+        // a full scale input sits well under it inside and comes back out at full scale
+        assert!(state.current().samples.iter().all(|&sample| sample == HEADROOM));
+        assert_close(&out, &[1.0; 64]);
+    }
+
+    #[test]
+    fn wet_filters_shape_repeats() {
+        let params = params(64.0, 64.0);
+        let mut state = ChannelState::with_snapshot(&[1.0; 64], 64);
+        state.set_wet_filter(0.0, 0.5);
+        let out = run(&params, &mut state, &[0.0; 64]);
+        // the low-pass smooths the step at the start
+        assert_close(&out[..3], &[0.5, 0.75, 0.875]);
+
+        let mut state = ChannelState::with_snapshot(&[1.0; 64], 64);
+        state.set_wet_filter(0.5, 1.0);
+        let out = run(&params, &mut state, &[0.0; 64]);
+        // the high-pass takes out the offset
+        assert!(out[63].abs() < 1e-6, "{}", out[63]);
+    }
+
+    #[test]
+    fn crusher_reduces_repeats() {
+        let params = params(8.0, 8.0);
+        let snapshot = [0.1, 0.2, 0.3, 0.4, -0.1, -0.2, -0.3, -0.4];
+        let mut state = ChannelState::with_snapshot(&snapshot, 8);
+        state.set_crush(3.0, 2.0);
+        let out = run(&params, &mut state, &[0.0; 8]);
+        // every other sample held, on a grid of quarters
+        assert_close(&out, &[0.0, 0.0, 0.25, 0.25, 0.0, 0.0, -0.25, -0.25]);
+    }
+
+    #[test]
+    fn limiter_turns_down_ahead_of_peaks() {
+        let mut limiter = Limiter::default();
+        limiter.allocate(1, 8);
+        let input = [[0.25; 16], [1.0; 16]].concat();
+        let output = input.iter().map(|&sample| {
+            let gain = limiter.next_gain(sample, 0.5, 0.0);
+            let sample = limiter.hold_back(0, sample) * gain;
+            limiter.advance();
+            (gain, sample)
+        }).collect::<Vec<_>>();
+        assert!(output.iter().all(|&(_, sample)| sample <= 0.5));
+        assert_eq!(output[24].1, 0.5);
+        // down in even steps over the lookahead, not all at once on the peak
+        assert_eq!(output[15].0, 1.0);
+        assert!(output[15..25].windows(2).all(|pair| pair[0].0 - pair[1].0 <= 0.5 / 9.0 + 1e-6));
+    }
+
+    #[test]
+    fn channel_delay_shifts_repeats() {
+        let params = params(8.0, 8.0);
+        let snapshot = [0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8];
+        let mut state = ChannelState::with_snapshot(&snapshot, 8);
+        state.allocate(44_100.0, reserved_len(&params));
+        state.set_delay(3.0);
+        let out = run(&params, &mut state, &[0.0; 8]);
+        assert_close(&out, &[0.0, 0.0, 0.0, 0.1, 0.2, 0.3, 0.4, 0.5]);
+        // the rest comes out in the next block, ahead of the silent capture
+        let out = run(&params, &mut state, &[0.0; 8]);
+        assert_close(&out[..4], &[0.6, 0.7, 0.8, 0.0]);
+
+        // changes glide, turned off the line keeps filling so nothing stale comes back with it
+        let mut state = ChannelState::with_snapshot(&[1.0; 8], 1000);
+        state.allocate(44_100.0, reserved_len(&params));
+        state.set_delay(0.0);
+        run(&params, &mut state, &[0.0; 8]);
+        state.set_delay(40.0);
+        let out = run(&params, &mut state, &[0.0; 40]);
+        assert!(out.iter().all(|&sample| (sample - 1.0).abs() < 1e-6));
+    }
+
+    #[test]
+    fn captures_stay_in_allocated_buffers() {
+        let params = params(64.0, 64.0);
+        let mut state = ChannelState::default();
+        state.allocate(44_100.0, reserved_len(&params));
+        // only as long as the period, not the longest one there can be
+        assert_eq!(state.next_buffer.capacity(), 64);
+        let mipmaps = |state: &ChannelState| state.snapshots.iter()
+            .flat_map(|snapshot| snapshot.mipmaps.iter().map(|mipmap| mipmap.as_ptr()))
+            .collect::<Vec<_>>();
+        let allocated = mipmaps(&state);
+        // around the history more than once
+        for _ in 0..HISTORY_LEN * 2 {
+            run(&params, &mut state, &[0.5; 64]);
+        }
+        assert_close(&state.snapshot(), &[0.5; 64]);
+        assert!(state.snapshots.iter().all(|snapshot| snapshot.samples.capacity() >= 64));
+        assert!(state.next_buffer.capacity() >= 64);
+        assert!(state.tail.capacity() >= (MAX_TAIL_MS / 1000.0 * 44_100.0) as usize);
+        // the mipmaps are decimated and filled in place
+        assert_eq!(mipmaps(&state), allocated);
+        for (level, mipmap) in state.current().mipmaps.iter().enumerate() {
+            assert_eq!(mipmap.len(), 64 >> (level + 1));
+        }
+        // going back through the history only picks another slot
+        let slots = state.snapshots.iter().map(|snapshot| snapshot.samples.as_ptr()).collect::<Vec<_>>();
+        params.history.set(3.0);
+        run(&params, &mut state, &[0.5; 64]);
+        assert_eq!(state.current().samples.as_ptr(), slots[(state.newest + 3) % HISTORY_LEN]);
+        assert_eq!(mipmaps(&state), allocated);
+    }
+
+    #[test]
+    fn mipmaps_keep_fast_playback_from_aliasing() {
+        // whole cycles so the loop joins up
+        let tone = |cycles: usize| (0..4096).map(|i| (TAU * (cycles * i) as f32 / 4096.0).sin()).collect::<Vec<_>>();
+        // at 4x the rate every sample of the mipmap for it is read once
+        let level = |snapshot: &Snapshot| {
+            let energy = (0..1024).map(|i| scan(snapshot, i as f32 / 1024.0, 4.0, false).powi(2)).sum::<f32>();
+            (energy / 1024.0).sqrt() / HEADROOM
+        };
+
+        // at 0.3 of the rate it folds back to 0.2 at 4x
+        let mut snapshot = Snapshot::prepare(&tone(1229), 4096);
+        let filtered = level(&snapshot);
+        snapshot.mipmaps.iter_mut().for_each(Vec::clear);
+        let aliased = level(&snapshot);
+        assert!(aliased > 0.5, "{}", aliased);
+        assert!(filtered < aliased / 1000.0, "{} against {}", filtered, aliased);
+        // built a bit at a time, the raw samples play until the level for 4x is done
+        snapshot.build_mipmaps(1024);
+        assert_eq!((snapshot.mipmaps_built(), level(&snapshot)), (0, aliased));
+        snapshot.build_mipmaps(2048);
+        assert_eq!((snapshot.mipmaps_built(), level(&snapshot)), (2, filtered));
+
+        // well below it comes through
+        let passed = level(&Snapshot::prepare(&tone(82), 4096));
+        assert!((passed - FRAC_1_SQRT_2).abs() < 0.01, "{}", passed);
+    }
+
+    #[test]
+    fn reset_keeps_buffers() {
+        let params = params(64.0, 64.0);
+        let mut state = ChannelState::default();
+        state.allocate(44_100.0, reserved_len(&params));
+        state.set_delay(10.0);
+        run(&params, &mut state, &[0.5; 64]);
+        run(&params, &mut state, &[0.5; 64]);
+        let buffers = state.snapshots.iter().map(|snapshot| snapshot.samples.as_ptr()).collect::<Vec<_>>();
+        state.reset();
+        assert_eq!(state.current_len(), 0);
+        assert_eq!(state.cycle(), (0, 0));
+        assert_eq!(state.snapshots.iter().map(|snapshot| snapshot.samples.as_ptr()).collect::<Vec<_>>(), buffers);
+        assert!(state.next_buffer.capacity() >= 64);
+        // nothing of the delayed repeats is left either
+        let out = run(&params, &mut state, &[0.0; 64]);
+        assert!(out.iter().all(|&sample| sample == 0.0));
+    }
+
+    #[test]
+    fn vibrato_swings_playback() {
+        let params = params(1000.0, 1000.0);
+        let snapshot = (0..1000).map(|i| i as f32 / 1000.0).collect::<Vec<_>>();
+        let mut state = ChannelState::with_snapshot(&snapshot, 1000);
+        // an octave either way, one swing over the snapshot
+        state.set_vibrato(0.001, 1200.0);
+        let out = run(&params, &mut state, &[0.0; 1000]);
+        // ahead while it's sharp, and slower while it's flat
+        assert!(out[250] > 0.3, "{}", out[250]);
+        assert!(out[750] - out[500] < 0.25, "{}", out[750] - out[500]);
+    }
+
+    #[test]
+    fn capture_follows_period_ratio() {
+        let params = params(100.0, 100.0);
+        params.capture_ratio.set(1.0);
+        params.capture_len.set_raw(0.5);
+        let mut state = ChannelState::default();
+        run(&params, &mut state, &[1.0; 64]);
+        assert_eq!(state.captured().len(), 50);
+
+        // the same share of a longer period
+        params.period.set(200.0);
+        for _ in 0..3 {
+            run(&params, &mut state, &[1.0; 64]);
+        }
+        assert_eq!(state.captured().len(), 100);
+    }
+
+    #[test]
+    fn jitter_varies_period_lengths() {
+        let params = params(100.0, 100.0);
+        params.period_jitter.set(20.0);
+        let mut sim = Simulation::new(params, 1);
+        sim.run(Signal::Sine { hz: 441.0 }, 10_000);
+        let periods = sim.periods();
+        assert!(periods.iter().all(|period| (80..=120).contains(period)), "{:?}", periods);
+        assert!(periods.iter().any(|&period| period != periods[0]));
+    }
+
+    #[test]
+    fn envelope_shapes_each_repeat() {
+        let params = params(64.0, 64.0);
+        let mut state = ChannelState::with_snapshot(&[1.0; 64], 64);
+        state.set_envelope(10, 20);
+        let out = run(&params, &mut state, &[1.0; 64]);
+        assert_close(&out[..11], &(0..=10).map(|i| i as f32 / 10.0).collect::<Vec<_>>());
+        assert_close(&[out[20], out[25]], &[0.5, 0.25]);
+        assert!(out[30..].iter().all(|&sample| sample == 0.0));
+
+        // the next period starts over
+        let out = run(&params, &mut state, &[0.0; 64]);
+        assert_close(&out[..2], &[0.0, 0.1]);
+    }
+
+    #[test]
+    fn fade_curves_span_full_range() {
+        for val in 0..4 {
+            let curve = FadeCurve::from_param(val as f32);
+            assert_eq!((curve.gain(0.0), curve.gain(1.0)), (0.0, 1.0));
+        }
+        // the two sides of a crossfade keep the power or the amplitude
+        let (fade_in, fade_out) = (FadeCurve::EqualPower.gain(0.3), FadeCurve::EqualPower.gain(0.7));
+        assert!((fade_in * fade_in + fade_out * fade_out - 1.0).abs() < 1e-6);
+        assert!((FadeCurve::SCurve.gain(0.3) + FadeCurve::SCurve.gain(0.7) - 1.0).abs() < 1e-6);
+        assert!((FadeCurve::Exponential.gain(0.5) - db_to_gain(-30.0)).abs() < 1e-6);
+
+        let mut snapshot = [1.0; 8];
+        fade_edges(&mut snapshot, 2, FadeCurve::EqualPower);
+        assert_close(&snapshot, &[0.0, FRAC_PI_4.sin(), 1.0, 1.0, 1.0, 1.0, FRAC_PI_4.sin(), 0.0]);
+    }
+
+    #[test]
+    fn swaps_after_period() {
+        let params = params(64.0, 32.0);
+        let mut state = ChannelState::default();
+        let inp = (0..64).map(|i| i as f32).collect::<Vec<_>>();
+        run(&params, &mut state, &inp);
+        assert_eq!(state.captured(), &inp[..32]);
+
+        let out = run(&params, &mut state, &[0.0; 64]);
+        assert_eq!(state.current_len(), 32);
+        assert_eq!(state.cycle(), (64, 64));
+        // plays back the captured half of the previous period twice
+        assert_close(&out[..32], &inp[..32]);
+        assert_close(&out[32..], &inp[..32]);
+    }
+
+    #[test]
+    fn normalizes_new_snapshots() {
+        let params = params(64.0, 64.0);
+        params.normalize.set(1.0);
+        params.normalize_target.set(-6.0);
+        let mut state = ChannelState::default();
+        let inp = (0..64).map(|i| if i % 2 == 0 { 0.1 } else { -0.05 }).collect::<Vec<_>>();
+        run(&params, &mut state, &inp);
+        let out = run(&params, &mut state, &[0.0; 64]);
+        let peak = db_to_gain(-6.0);
+        assert_close(&out, &inp.iter().map(|sample| sample * peak / 0.1).collect::<Vec<_>>());
+
+        // silence isn't brought up at all, quiet input only so far
+        run(&params, &mut state, &[0.0; 64]);
+        assert!(state.snapshot().iter().all(|&s| s == 0.0));
+        params.normalize.set(2.0);
+        run(&params, &mut state, &[0.002; 64]);
+        run(&params, &mut state, &[0.0; 64]);
+        assert_close(&state.snapshot(), &[0.002 * db_to_gain(MAX_MAKEUP_DB); 64]);
+    }
+
+    #[test]
+    fn dc_filter_removes_offset() {
+        let run = |filter: f32| {
+            let params = params(4410.0, 4410.0);
+            params.dc_filter.set(filter);
+            let mut sim = Simulation::new(params, 256);
+            sim.run(Signal::Dc { level: 0.5 }, 44_100);
+            sim.output[39_690..].iter().fold(0.0, |peak: f32, sample| peak.max(sample.abs()))
+        };
+        assert!(run(0.0) > 0.49);
+        assert!(run(1.0) < 0.001, "{}", run(1.0));
+    }
+
+    #[test]
+    fn loops_part_of_snapshot() {
+        let params = params(64.0, 64.0);
+        params.loop_start.set(0.25);
+        params.loop_length.set(0.25);
+        let mut state = ChannelState::default();
+        let inp = (0..64).map(|i| i as f32).collect::<Vec<_>>();
+        run(&params, &mut state, &inp);
+        let out = run(&params, &mut state, &[0.0; 64]);
+        assert_close(&out, &[&inp[16..32]; 4].concat());
+    }
+
+    #[test]
+    fn scrub_follows_position() {
+        let params = params(128.0, 64.0);
+        params.scrub.set(1.0);
+        params.position.set(0.5);
+        let mut state = ChannelState::default();
+        let inp = (0..64).map(|i| i as f32).collect::<Vec<_>>();
+        run(&params, &mut state, &inp);
+        run(&params, &mut state, &[0.0; 64]);
+        let out = run(&params, &mut state, &[0.0; 64]);
+        assert_close(&out, &[32.0; 64]);
+
+        // moves across the block to the new position
+        params.position.set(0.75);
+        let out = run(&params, &mut state, &[0.0; 64]);
+        assert_close(&out, &(1..=64).map(|i| 32 + i / 4).map(|i| i as f32).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn taps_add_heads_at_rate_multiples() {
+        let params = params(100.0, 100.0);
+        params.taps.set(2.0);
+        params.tap_1_rate.set(0.5);
+        params.tap_1_level.set(0.0);
+        params.tap_2_level.set(f32::NEG_INFINITY);
+        let ramp = (0..100).map(|i| i as f32 / 100.0).collect::<Vec<_>>();
+        let mut state = ChannelState::with_snapshot(&ramp, 100);
+        let out = run(&params, &mut state, &[0.0; 64]);
+        // the tap runs through the ramp at half the speed of the main head, the silent one adds nothing
+        assert_close(&out, &(0..64).map(|i| (i + i / 2) as f32 / 100.0).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn harmony_layers_read_at_intervals() {
+        let params = params(100.0, 100.0);
+        params.octave_down_level.set(0.0);
+        params.fifth_level.set(-6.0);
+        let ramp = (0..100).map(|i| i as f32 / 100.0).collect::<Vec<_>>();
+        let mut state = ChannelState::with_snapshot(&ramp, 100);
+        let out = run(&params, &mut state, &[0.0; 64]);
+        let fifth = db_to_gain(-6.0);
+        let expected = (0..64).map(|i| (i + i / 2) as f32 / 100.0 + (i * 3 / 2 % 100) as f32 / 100.0 * fifth);
+        assert_close(&out, &expected.collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn sync_period_is_ratio_of_beats() {
+        let params = params(100.0, 100.0);
+        params.sync.set(1.0);
+        params.sync_numerator.set(4.0);
+        params.sync_denominator.set(3.0);
+        let transport = Transport {
+            playing: true,
+            ppq_pos: Some(0.0),
+            ppq_per_sample: 0.01,
+            ..Default::default()
+        };
+        let mut state = ChannelState::default();
+        process_channel(&params, &transport, &mut state, &[0.0; 64], &mut [0.0; 64]);
+        // 5:4 of a beat lasting 100 samples
+        assert_eq!(state.cycle(), (64, 125));
+
+        // typed in as text
+        assert!(params.string_to_parameter(20, "7:8".to_string()));
+        assert_eq!(params.get_parameter_text(19), "7");
+        assert_eq!(params.get_parameter_text(20), "8");
+
+        // without a tempo the period applies
+        let mut state = ChannelState::default();
+        run(&params, &mut state, &[0.0; 64]);
+        assert_eq!(state.cycle(), (64, 100));
+    }
+
+    #[test]
+    fn synced_tremolo_gates_repeats() {
+        let params = params(16.0, 16.0);
+        params.tremolo_depth.set(1.0);
+        params.tremolo_shape.set(1.0);
+        params.tremolo_sync.set(1.0);
+        assert!(params.string_to_parameter(121, "1/16".to_string()));
+        let transport = Transport {
+            playing: true,
+            ppq_pos: Some(0.0),
+            ppq_per_sample: 1.0 / 32.0,
+            ..Default::default()
+        };
+        let mut state = ChannelState::with_snapshot(&[0.5; 16], 16);
+        let mut out = [0.0; 16];
+        process_channel(&params, &transport, &mut state, &[0.5; 16], &mut out);
+        // a sixteenth lasts 8 samples, on for the first half of it
+        let gate = [0.5, 0.5, 0.5, 0.5, 0.0, 0.0, 0.0, 0.0];
+        assert_close(&out, &[gate, gate].concat());
+        assert_eq!(params.get_parameter_text(121), "1/16");
+
+        // runs on where it was when the host stops, at the same tempo
+        let stopped = Transport { playing: false, ppq_pos: Some(0.5), ..transport };
+        process_channel(&params, &stopped, &mut state, &[0.5; 16], &mut out);
+        assert_close(&out, &[gate, gate].concat());
+
+        // the edges fade like the gate pattern
+        let mut state = ChannelState::with_snapshot(&[0.5; 16], 16);
+        state.enable_step = 0.25;
+        process_channel(&params, &transport, &mut state, &[0.0; 16], &mut out);
+        let fading = [0.5, 0.5, 0.5, 0.5, 0.375, 0.25, 0.125, 0.0, 0.125, 0.25, 0.375, 0.5, 0.375, 0.25, 0.125, 0.0];
+        assert_close(&out, &fading);
+    }
+
+    #[test]
+    fn gate_pattern_mutes_steps() {
+        let params = params(32.0, 32.0);
+        params.gate_steps[1].set(0.0);
+        params.gate_steps[15].set(0.0);
+        let mut state = ChannelState::with_snapshot(&[0.5; 32], 32);
+        let out = run(&params, &mut state, &[0.0; 32]);
+        // two samples to each step
+        let mut expected = [0.5; 32];
+        expected[2..4].fill(0.0);
+        expected[30..].fill(0.0);
+        assert_close(&out, &expected);
+        assert_eq!(params.get_parameter_text(125), "Off");
+    }
+
+    #[test]
+    fn sync_period_picks_note_value() {
+        let params = params(100.0, 100.0);
+        params.sync.set(1.0);
+        params.note_values.set(1.0);
+        let transport = Transport {
+            playing: true,
+            ppq_pos: Some(0.0),
+            ppq_per_sample: 0.01,
+            ppq_per_bar: Some(3.0),
+            ..Default::default()
+        };
+        assert!(params.string_to_parameter(0, "1/8T".to_string()));
+        assert_eq!(params.get_parameter_text(0), "1/8T");
+        assert_eq!(period_samples(&params, &transport), 33);
+        assert!(params.string_to_parameter(0, "1/4d".to_string()));
+        assert_eq!(period_samples(&params, &transport), 150);
+        // the bars follow the time signature
+        params.period.set_raw(1.0);
+        assert_eq!(params.get_parameter_text(0), "4 bars");
+        assert_eq!(period_samples(&params, &transport), 1200);
+        assert!(!params.string_to_parameter(0, "1/3".to_string()));
+
+        // the period is a length again without sync
+        params.sync.set(0.0);
+        assert_eq!(params.get_parameter_text(0), "10.000 s");
+    }
+
+    #[test]
+    fn groove_shifts_synced_periods() {
+        let params = params(100.0, 100.0);
+        params.sync.set(1.0);
+        params.groove.set(&[0.0, 0.2]);
+        let transport = |ppq_pos| Transport {
+            playing: true,
+            ppq_pos: Some(ppq_pos),
+            ppq_per_sample: 0.01,
+            ..Default::default()
+        };
+        // the second beat starts late, at 1.2, so the first one is longer
+        assert_eq!(period_samples(&params, &transport(0.0)), 120);
+        assert_eq!(period_samples(&params, &transport(1.2)), 80);
+        assert_eq!(period_samples(&params, &transport(2.0)), 120);
+
+        // stored with the state
+        let state = params.serialize();
+        let restored = Params::default();
+        restored.deserialize(&state, false);
+        assert_eq!(restored.groove.offsets(), params.groove.offsets());
+    }
+
+    #[test]
+    fn quantize_snaps_period_to_grid() {
+        let params = params(130.0, 100.0);
+        params.quantize.set(1.0);
+        let transport = |ppq_pos, ppq_per_bar| Transport {
+            playing: true,
+            stopped: false,
+            ppq_pos: Some(ppq_pos),
+            ppq_per_sample: 0.01,
+            ppq_per_bar,
+        };
+        // a beat is 100 samples, 1.3 beats end on the second beat
+        assert_eq!(period_samples(&params, &transport(0.0, None)), 100);
+        // started off the grid, ends on it
+        assert_eq!(period_samples(&params, &transport(0.25, None)), 175);
+        params.period.set(10.0);
+        assert_eq!(period_samples(&params, &transport(0.0, None)), 100);
+
+        params.quantize.set(2.0);
+        assert_eq!(period_samples(&params, &transport(0.5, None)), 350);
+        assert_eq!(period_samples(&params, &transport(0.5, Some(3.0))), 250);
+    }
+
+    #[test]
+    fn division_restarts_snapshot() {
+        let params = params(32.0, 32.0);
+        params.repeat_division.set(2.0);
+        let snapshot = (0..16).map(|i| i as f32).collect::<Vec<_>>();
+        let mut state = ChannelState::with_snapshot(&snapshot, 32);
+        let out = run(&params, &mut state, &[0.0; 32]);
+        for slice in out.chunks(8) {
+            assert_close(slice, &snapshot[..8]);
+        }
+
+        // every other restart half a slice late
+        params.division_swing.set(0.5);
+        let mut state = ChannelState::with_snapshot(&snapshot, 32);
+        let out = run(&params, &mut state, &[0.0; 32]);
+        for pair in out.chunks(16) {
+            assert_close(&pair[..12], &snapshot[..12]);
+            assert_close(&pair[12..], &snapshot[..4]);
+        }
+    }
+
+    #[test]
+    fn divisions_play_slices() {
+        let params = params(16.0, 16.0);
+        params.repeat_division.set(2.0);
+        params.slice_length.set(3.0);
+        let snapshot = (0..16).map(|i| i as f32).collect::<Vec<_>>();
+        let mut state = ChannelState::with_snapshot(&snapshot, 16);
+        // each division loops a slice two samples long
+        let out = run(&params, &mut state, &[0.0; 16]);
+        assert_close(&out, &[0.0, 1.0, 0.0, 1.0, 2.0, 3.0, 2.0, 3.0, 4.0, 5.0, 4.0, 5.0, 6.0, 7.0, 6.0, 7.0]);
+
+        params.slice_random.set(1.0);
+        let mut state = ChannelState::with_snapshot(&snapshot, 16);
+        let out = run(&params, &mut state, &[0.0; 16]);
+        assert_close(&out, &[0.0, 1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 1.0, 2.0, 3.0, 2.0, 3.0, 0.0, 1.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn rate_changes_glide_across_block() {
+        let params = params(1000.0, 1000.0);
+        let snapshot = (0..100).map(|i| i as f32).collect::<Vec<_>>();
+        let mut state = ChannelState::with_snapshot(&snapshot, 1000);
+        let out = run(&params, &mut state, &[0.0; 10]);
+        assert_close(&out, &snapshot[..10]);
+
+        params.playback_rate.set(3.0);
+        let out = run(&params, &mut state, &[0.0; 4]);
+        // from twice the rate on the copy kept at every other sample is read
+        assert_close(&out, &[10.0, 10.0, 12.0, 16.0]);
+        // at the new rate from then on
+        let out = run(&params, &mut state, &[0.0; 2]);
+        assert_close(&out, &[18.0, 22.0]);
+    }
+
+    #[test]
+    fn gain_changes_ramp_across_block() {
+        let params = params(1000.0, 1000.0);
+        let mut state = ChannelState::with_snapshot(&[1.0; 100], 1000);
+        run(&params, &mut state, &[0.0; 4]);
+
+        params.output_gain.set(f32::NEG_INFINITY);
+        let out = run(&params, &mut state, &[0.0; 4]);
+        assert_close(&out, &[0.75, 0.5, 0.25, 0.0]);
+        let out = run(&params, &mut state, &[0.0; 2]);
+        assert_close(&out, &[0.0, 0.0]);
+    }
+
+    #[test]
+    fn tail_crossfades_under_new_snapshot() {
+        let params = params(8.0, 8.0);
+        let mut state = ChannelState::with_snapshot(&[1.0; 8], 8);
+        state.set_tail(4);
+        run(&params, &mut state, &[0.0; 8]);
+        // the silent capture takes over while the old snapshot fades out
+        let out = run(&params, &mut state, &[0.0; 8]);
+        assert_close(&out, &[1.0, 0.75, 0.5, 0.25, 0.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn tail_plays_like_the_snapshot() {
+        // looping the first half, the outgoing snapshot carries on inside the loop
+        let params = params(8.0, 8.0);
+        params.loop_length.set(0.5);
+        let mut state = ChannelState::with_snapshot(&[1.0, 1.0, 1.0, 1.0, 9.0, 9.0, 9.0, 9.0], 8);
+        state.set_tail(2);
+        state.current_offset_norm = 0.75;
+        state.render_tail(&params, 1.0);
+        assert_close(&state.tail, &[HEADROOM; 2]);
+
+        // and slows down with the rate envelope
+        params.loop_length.set(1.0);
+        params.rate_end.set(0.0);
+        let snapshot = (0..8).map(|i| i as f32).collect::<Vec<_>>();
+        let mut state = ChannelState::with_snapshot(&snapshot, 8);
+        state.set_tail(8);
+        state.render_tail(&params, 1.0);
+        let stopping = state.tail.clone();
+        params.rate_end.set(1.0);
+        state.render_tail(&params, 1.0);
+        assert!(stopping.iter().zip(&state.tail).all(|(stopping, steady)| stopping <= steady));
+        assert!(stopping[7] < state.tail[7]);
+    }
+
+    #[test]
+    fn capture_starts_at_zero_crossing() {
+        let params = params(8.0, 8.0);
+        let mut state = ChannelState::default();
+        state.set_crossing_timeout(4);
+        run(&params, &mut state, &[0.5; 8]);
+        run(&params, &mut state, &[0.5, 0.4, -0.1, -0.2, -0.3, 0.1, 0.2, 0.3]);
+        assert_eq!(state.captured(), &[-0.1, -0.2, -0.3, 0.1, 0.2, 0.3]);
+
+        // gives up waiting after the timeout
+        run(&params, &mut state, &[0.5; 8]);
+        assert_eq!(state.captured(), &[0.5; 4]);
+
+        // the feedback lines up with the input captured at the same time
+        params.feedback.set(0.5);
+        let mut state = ChannelState::default();
+        let playing = [0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8];
+        run(&params, &mut state, &playing);
+        state.set_crossing_timeout(4);
+        let inp = [0.5, 0.4, -0.1, -0.2, -0.3, 0.1, 0.2, 0.3];
+        let out = run(&params, &mut state, &inp);
+        assert_close(&out, &playing);
+        let feedback = state.captured().iter()
+            .zip(&inp[2..])
+            .map(|(captured, inp)| captured - inp)
+            .collect::<Vec<_>>();
+        let gain = feedback[0] / playing[2];
+        assert!(gain > 0.0);
+        assert_close(&feedback, &playing[2..].iter().map(|out| out * gain).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn captures_trimmed_to_loop_points() {
+        // a sine a hundred samples long, cut off part of the way through a cycle at both ends
+        let sine = (0..1030).map(|i| (TAU * (i as f32 + 30.5) / 100.0).sin()).collect::<Vec<_>>();
+        assert_eq!(find_loop_points(&sine, LoopPoints::ZeroCrossings, 200), 70..970);
+        assert_eq!(find_loop_points(&sine, LoopPoints::BestMatch, 200), 0..900);
+        assert_eq!(find_loop_points(&sine, LoopPoints::Off, 200), 0..1030);
+        // the full search at 44.1 kHz still lands on a whole number of cycles
+        let long_sine = (0..5000).map(|i| (TAU * i as f32 / 97.0).sin()).collect::<Vec<_>>();
+        assert_eq!(find_loop_points(&long_sine, LoopPoints::BestMatch, 882).end % 97, 0);
+
+        let params = params(1030.0, 1030.0);
+        params.loop_points.set(1.0);
+        let mut state = ChannelState { loop_search: 200, ..Default::default() };
+        run(&params, &mut state, &sine);
+        run(&params, &mut state, &[0.0; 8]);
+        assert_eq!(state.snapshot().len(), 900);
+    }
+
+    #[test]
+    fn snapshot_edges_fade() {
+        let params = params(16.0, 16.0);
+        let mut state = ChannelState::default();
+        state.set_edge_fade(4);
+        run(&params, &mut state, &[1.0; 16]);
+        let out = run(&params, &mut state, &[0.0; 16]);
+        assert_close(&out[..5], &[0.0, 0.25, 0.5, 0.75, 1.0]);
+        assert_close(&out[11..], &[1.0, 0.75, 0.5, 0.25, 0.0]);
+    }
+
+    #[test]
+    fn rate_envelope_slows_each_period() {
+        let params = params(16.0, 16.0);
+        params.rate_start.set(1.0);
+        params.rate_end.set(0.0);
+        let snapshot = (0..16).map(|i| i as f32).collect::<Vec<_>>();
+        let mut state = ChannelState::with_snapshot(&snapshot, 16);
+        let out = run(&params, &mut state, &[0.0; 16]);
+        // a tape stop, slowing down towards the end of the period
+        let positions = (0..16).scan(0.0, |pos: &mut f32, i| {
+            let read = pos.floor();
+            *pos += 1.0 - i as f32 / 16.0;
+            Some(read)
+        });
+        assert_close(&out, &positions.collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn fast_playback_is_band_limited() {
+        let params = params(1000.0, 1000.0);
+        params.playback_rate.set(4.0);
+        // the highest frequency there is, aliases to DC at four times the rate
+        let snapshot = (0..256).map(|i| if i % 2 == 0 { 1.0 } else { -1.0 }).collect::<Vec<_>>();
+        let mut state = ChannelState::with_snapshot(&snapshot, 1000);
+        let out = run(&params, &mut state, &[0.0; 32]);
+        assert!(out.iter().all(|sample| sample.abs() < 0.2), "{:?}", out);
+
+        // slow changes pass unchanged, away from where the snapshot wraps around
+        let snapshot = (0..256).map(|i| i as f32).collect::<Vec<_>>();
+        let mut state = ChannelState::with_snapshot(&snapshot, 1000);
+        let out = run(&params, &mut state, &[0.0; 32]);
+        assert_close(&out[8..], &(8..32).map(|i| i as f32 * 4.0).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn period_shorter_than_block() {
+        let params = params(16.0, 16.0);
+        let mut state = ChannelState::default();
+        let inp = (0..256).map(|i| i as f32 / 256.0).collect::<Vec<_>>();
+        let out = run(&params, &mut state, &inp);
+        // each period repeats the one before it, swapped right at its end
+        assert!(out[..16].iter().all(|&sample| sample == 0.0));
+        assert_close(&out[16..], &inp[..240]);
+        assert_eq!(state.cycle(), (16, 16));
+        assert_eq!(state.captured(), &inp[240..]);
+    }
+
+    #[test]
+    fn freeze_keeps_snapshot_and_glides() {
+        let params = params(32.0, 32.0);
+        let mut state = ChannelState::with_snapshot(&[1.0; 32], 32);
+        state.set_freeze(true, -12.0, 64.0);
+        for _ in 0..4 {
+            run(&params, &mut state, &[0.0; 32]);
+            // the captured silence never replaces the frozen snapshot
+            assert_eq!(state.snapshot(), &[1.0; 32][..]);
+        }
+        assert_eq!(state.transpose, -12.0);
+
+        state.set_freeze(false, -12.0, 64.0);
+        run(&params, &mut state, &[0.0; 32]);
+        assert!(state.transpose > -12.0 && state.transpose < 0.0);
+    }
+
+    #[test]
+    fn feedback_sums_playback_into_capture() {
+        let params = params(32.0, 32.0);
+        params.feedback.set(0.5);
+        let mut state = ChannelState::with_snapshot(&[1.0; 32], 32);
+        run(&params, &mut state, &[0.25; 32]);
+        assert_close(&state.captured(), &[0.75; 32]);
+    }
+
+    #[test]
+    fn capture_position_places_input() {
+        let params = params(4.0, 4.0);
+        params.capture_position.set(1.0);
+        params.capture_offset.set(0.25);
+        let mut state = ChannelState::with_snapshot(&[0.0; 4], 4);
+        run(&params, &mut state, &[1.0, 2.0, 3.0, 4.0]);
+        run(&params, &mut state, &[0.0; 4]);
+        assert_eq!(state.snapshot(), &[4.0, 1.0, 2.0, 3.0]);
+
+        // windows of the capture length move through the longer snapshot
+        params.period.set(16.0);
+        params.capture_position.set(2.0);
+        let mut state = ChannelState::with_snapshot(&[0.0; 8], 16);
+        run(&params, &mut state, &[9.0; 16]);
+        run(&params, &mut state, &[1.0; 16]);
+        assert_eq!(state.snapshot(), &[9.0; 8]);
+        run(&params, &mut state, &[2.0; 16]);
+        assert_eq!(state.snapshot(), &[1.0, 1.0, 1.0, 1.0, 9.0, 9.0, 9.0, 9.0]);
+        run(&params, &mut state, &[0.0; 16]);
+        assert_eq!(state.snapshot(), &[1.0, 1.0, 1.0, 1.0, 2.0, 2.0, 2.0, 2.0]);
+    }
+
+    #[test]
+    fn mosaic_refreshes_segments() {
+        let params = params(16.0, 4.0);
+        params.capture_position.set(3.0);
+        params.mosaic_segments.set(0.0);
+        let mut state = ChannelState::with_snapshot(&[0.0; 8], 16);
+        run(&params, &mut state, &[9.0; 16]);
+        run(&params, &mut state, &[1.0; 16]);
+        assert_eq!(state.snapshot(), &[9.0, 9.0, 9.0, 9.0, 0.0, 0.0, 0.0, 0.0]);
+        run(&params, &mut state, &[2.0; 16]);
+        assert_eq!(state.snapshot(), &[9.0, 9.0, 9.0, 9.0, 1.0, 1.0, 1.0, 1.0]);
+
+        params.mosaic_probability.set(0.0);
+        run(&params, &mut state, &[0.0; 16]);
+        assert_eq!(state.snapshot(), &[9.0, 9.0, 9.0, 9.0, 1.0, 1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn reversed_periods_play_backwards() {
+        let params = params(4.0, 4.0);
+        params.reverse_probability.set(1.0);
+        let mut state = ChannelState::default();
+        run(&params, &mut state, &[0.0, 1.0, 2.0, 3.0]);
+        let out = run(&params, &mut state, &[0.0; 4]);
+        assert_close(&out, &[3.0, 2.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn overdub_layers_onto_snapshot() {
+        let params = params(32.0, 32.0);
+        params.overdub.set(1.0);
+        let mut state = ChannelState::with_snapshot(&[0.25; 16], 32);
+        run(&params, &mut state, &[0.5; 32]);
+        run(&params, &mut state, &[0.0; 32]);
+        // keeps the length of the first take
+        assert_eq!(state.snapshot(), &[0.75; 16][..]);
+    }
+
+    #[test]
+    fn capture_and_playback_enable() {
+        let params = params(32.0, 32.0);
+        let mut state = ChannelState::with_snapshot(&[1.0; 32], 32);
+        state.set_enabled(false, false, 8.0);
+        let out = run(&params, &mut state, &[0.5; 32]);
+        // fades out instead of cutting off
+        assert!(out[0] > 0.5 && out[7] < 0.5);
+        assert!(out[8..].iter().all(|&s| s == 0.0));
+
+        run(&params, &mut state, &[0.5; 32]);
+        // nothing was captured to replace the snapshot with
+        assert_eq!(state.snapshot(), &[1.0; 32][..]);
+    }
+
+    #[test]
+    fn gains_apply_to_capture_and_repeats() {
+        let params = params(32.0, 32.0);
+        params.input_trim.set(-6.0);
+        params.output_gain.set(f32::NEG_INFINITY);
+        let mut state = ChannelState::with_snapshot(&[1.0; 32], 32);
+        let out = run(&params, &mut state, &[1.0; 32]);
+        assert!(out.iter().all(|&s| s == 0.0));
+        assert_close(&state.captured(), &[db_to_gain(-6.0); 32]);
+    }
+
+    #[test]
+    fn feedback_limiter_keeps_loop_stable() {
+        let params = params(32.0, 32.0);
+        params.feedback.set(1.2);
+        let mut state = ChannelState::default();
+        for _ in 0..64 {
+            run(&params, &mut state, &[0.5; 32]);
+            assert!(state.snapshot_peak < 2.0 * FEEDBACK_CEILING * HEADROOM, "peak {}", state.snapshot_peak);
+        }
+        assert!(state.feedback_gain < 1.0);
+
+        // lets go once there's no feedback to limit
+        params.feedback.set(0.0);
+        run(&params, &mut state, &[0.5; 32]);
+        assert_eq!(state.feedback_gain, 1.0);
+    }
+}
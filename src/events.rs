@@ -0,0 +1,126 @@
+//! Triggers acting on the snapshots at a given sample of the processed block.
+//!
+//! Every source (parameter changes, MIDI, anything driving the plugin from
+//! outside) pushes into the same queue, `process` then splits the block at the
+//! queued times and applies the triggers in order.
+
+use std::collections::VecDeque;
+
+// the queue never grows past this so pushing doesn't allocate on the audio thread
+const CAPACITY: usize = 256;
+
+/// Something that happens to the snapshots of all channels at once.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Trigger {
+    /// starts capturing the next snapshot from this sample
+    Capture,
+    /// ends the period, the captured part of the next snapshot starts playing
+    Swap,
+    /// turns freeze on or off
+    Freeze(bool),
+    /// drops the playing snapshot and starts a new capture
+    Clear,
+    /// a MIDI note with this number started
+    NoteOn(u8),
+    /// a MIDI note with this number ended
+    NoteOff(u8),
+    /// a MIDI controller with this number moved to this value, see `MidiLearn`
+    ControlChange(u8, u8),
+    /// sets the parameter at this index to this normalized value, for automation
+    /// landing on its sample rather than at the start of the block, host
+    /// automation can't use it since the host doesn't say when a change happens
+    Parameter(i32, f32),
+    /// switches to the A (0) or B (1) scene
+    Scene(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Event {
+    // samples from the start of the next processed block
+    time: usize,
+    trigger: Trigger,
+}
+
+pub(crate) struct EventQueue {
+    // sorted by time, events at the same time keep the order they were pushed in
+    events: VecDeque<Event>,
+}
+
+impl Default for EventQueue {
+    fn default() -> Self {
+        Self {
+            events: VecDeque::with_capacity(CAPACITY),
+        }
+    }
+}
+
+impl EventQueue {
+    /// Queues `trigger` to happen `time` samples into the next processed block,
+    /// returns false when the queue is full and the trigger was dropped.
+    pub(crate) fn push(&mut self, time: usize, trigger: Trigger) -> bool {
+        if self.events.len() >= CAPACITY {
+            return false
+        }
+        let idx = self.events.iter().rposition(|event| event.time <= time).map_or(0, |idx| idx + 1);
+        self.events.insert(idx, Event { time, trigger });
+        true
+    }
+
+    /// Takes the next trigger due at or before `time`.
+    pub(crate) fn pop_due(&mut self, time: usize) -> Option<Trigger> {
+        match self.events.front() {
+            Some(event) if event.time <= time => self.events.pop_front().map(|event| event.trigger),
+            _ => None,
+        }
+    }
+
+    /// When the next trigger is due, if any.
+    pub(crate) fn next_time(&self) -> Option<usize> {
+        self.events.front().map(|event| event.time)
+    }
+
+    /// Moves past a processed block of `len` samples, triggers queued beyond it
+    /// are kept for the next one.
+    pub(crate) fn advance(&mut self, len: usize) {
+        self.events.iter_mut().for_each(|event| event.time = event.time.saturating_sub(len));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(queue: &mut EventQueue, time: usize) -> Vec<Trigger> {
+        std::iter::from_fn(|| queue.pop_due(time)).collect()
+    }
+
+    #[test]
+    fn ordered_by_time_then_push_order() {
+        let mut queue = EventQueue::default();
+        queue.push(10, Trigger::Swap);
+        queue.push(0, Trigger::Freeze(true));
+        queue.push(10, Trigger::Clear);
+        queue.push(5, Trigger::Capture);
+
+        assert_eq!(drain(&mut queue, 0), [Trigger::Freeze(true)]);
+        assert_eq!(queue.next_time(), Some(5));
+        assert_eq!(drain(&mut queue, 10), [Trigger::Capture, Trigger::Swap, Trigger::Clear]);
+        assert_eq!(queue.next_time(), None);
+    }
+
+    #[test]
+    fn later_triggers_carry_over() {
+        let mut queue = EventQueue::default();
+        queue.push(100, Trigger::Swap);
+        assert_eq!(drain(&mut queue, 63), []);
+        queue.advance(64);
+        assert_eq!(queue.next_time(), Some(36));
+    }
+
+    #[test]
+    fn full_queue_drops() {
+        let mut queue = EventQueue::default();
+        assert!((0..CAPACITY).all(|time| queue.push(time, Trigger::Capture)));
+        assert!(!queue.push(0, Trigger::Clear));
+    }
+}
@@ -0,0 +1,151 @@
+//! Granular playback of the snapshot.
+//!
+//! Instead of scanning the snapshot once, short windowed grains are started at
+//! a steady rate around the play head, each with its own position and pitch
+//! scattered by the spray settings.
+
+use std::f32::consts::TAU;
+
+use crate::random::Random;
+use crate::dsp::semitones_to_ratio;
+
+// grains starting while this many are playing are dropped
+const MAX_GRAINS: usize = 64;
+
+/// grain settings in samples, the same for all channels
+#[derive(Clone, Copy)]
+pub(crate) struct GrainSettings {
+    /// length of each grain
+    pub size: f32,
+    /// time between the starts of two grains
+    pub interval: f32,
+    /// how far from the play head grains start, as a fraction of the snapshot
+    pub position_spray: f32,
+    /// semitones the pitch of each grain is scattered by both ways
+    pub pitch_spray: f32,
+}
+
+impl Default for GrainSettings {
+    fn default() -> Self {
+        Self {
+            size: 1.0,
+            interval: 1.0,
+            position_spray: 0.0,
+            pitch_spray: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Grain {
+    // samples into the snapshot
+    position: f32,
+    // samples the position moves each output sample
+    step: f32,
+    age: f32,
+    len: f32,
+}
+
+impl Grain {
+    fn playing(&self) -> bool {
+        self.age < self.len
+    }
+}
+
+pub(crate) struct GrainCloud {
+    grains: [Grain; MAX_GRAINS],
+    settings: GrainSettings,
+    // samples until the next grain starts
+    until_next: f32,
+    random: Random,
+}
+
+impl Default for GrainCloud {
+    fn default() -> Self {
+        Self {
+            grains: [Grain::default(); MAX_GRAINS],
+            settings: Default::default(),
+            until_next: 0.0,
+            // seeded the same on all channels so they scatter the same way and keep their image
+            random: Random::new(0),
+        }
+    }
+}
+
+impl GrainCloud {
+    pub(crate) fn reseed(&mut self, seed: u32) {
+        self.random = Random::new(seed);
+    }
+
+    pub(crate) fn set_settings(&mut self, settings: GrainSettings) {
+        self.settings = settings;
+    }
+
+    /// The next output sample of all playing grains reading `buffer`, new grains
+    /// start around `head` (normalized) at `pitch` (a playback rate ratio).
+    pub(crate) fn next(&mut self, buffer: &[f32], head: f32, pitch: f32) -> f32 {
+        let settings = self.settings;
+        self.until_next -= 1.0;
+        if self.until_next <= 0.0 {
+            self.until_next += settings.interval.max(1.0);
+            self.start_grain(buffer.len(), head, pitch);
+        }
+
+        let len = buffer.len() as f32;
+        let mut sum = 0.0;
+        for grain in self.grains.iter_mut().filter(|grain| grain.playing()) {
+            // hann window
+            let window = 0.5 - 0.5 * (TAU * grain.age / grain.len).cos();
+            sum += interpolate(buffer, grain.position) * window;
+            grain.position = (grain.position + grain.step).rem_euclid(len);
+            grain.age += 1.0;
+        }
+
+        // the windows average to one half, keep the level of a single scan however much they overlap
+        let overlap = settings.size / settings.interval.max(1.0);
+        sum * 2.0 / overlap.max(2.0)
+    }
+
+    fn start_grain(&mut self, buffer_len: usize, head: f32, pitch: f32) {
+        let settings = self.settings;
+        let position_spray = (self.random.next_f32() * 2.0 - 1.0) * settings.position_spray;
+        let pitch_spray = (self.random.next_f32() * 2.0 - 1.0) * settings.pitch_spray;
+        if let Some(grain) = self.grains.iter_mut().find(|grain| !grain.playing()) {
+            *grain = Grain {
+                position: ((head + position_spray) * buffer_len as f32).rem_euclid(buffer_len as f32),
+                step: pitch * semitones_to_ratio(pitch_spray),
+                age: 0.0,
+                len: settings.size.max(1.0),
+            };
+        }
+    }
+}
+
+// linear interpolation, wrapping around the end
+pub(crate) fn interpolate(buffer: &[f32], position: f32) -> f32 {
+    let low_idx = (position.floor() as usize).min(buffer.len() - 1);
+    let high_idx = (low_idx + 1) % buffer.len();
+    let fract = position.fract();
+    buffer[low_idx] + (buffer[high_idx] - buffer[low_idx]) * fract
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlapping_grains_keep_level() {
+        let mut cloud = GrainCloud::default();
+        cloud.set_settings(GrainSettings {
+            size: 400.0,
+            interval: 50.0,
+            position_spray: 0.5,
+            pitch_spray: 12.0,
+        });
+        let buffer = [1.0; 1000];
+        let out = (0..10_000).map(|_| cloud.next(&buffer, 0.0, 1.0)).collect::<Vec<_>>();
+        // once the cloud is full
+        let mean = out[1000..].iter().sum::<f32>() / (out.len() - 1000) as f32;
+        assert!((mean - 1.0).abs() < 0.05, "mean {}", mean);
+    }
+}
@@ -0,0 +1,108 @@
+//! Groove templates shifting the synced period boundaries off the grid.
+//!
+//! A template is a list of per-step offsets, each a fraction of the period the
+//! boundary starting that step is moved by, repeating after the last step. As
+//! text, it's one offset per line in percent, or `swing <percent>` for the
+//! classic drum machine swing where 50% is straight.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use vst::util::AtomicFloat;
+
+/// templates longer than this are cut
+pub const MAX_STEPS: usize = 16;
+// a boundary can't move past the middle of its neighbours
+const MAX_OFFSET: f32 = 0.5;
+
+pub struct GrooveTemplate {
+    // only the first `len` are used, atomic so the audio thread never waits for an edit
+    offsets: Vec<AtomicFloat>,
+    len: AtomicUsize,
+}
+
+impl Default for GrooveTemplate {
+    fn default() -> Self {
+        Self {
+            offsets: (0..MAX_STEPS).map(|_| AtomicFloat::new(0.0)).collect(),
+            len: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl GrooveTemplate {
+    /// Parses the text form into offsets as fractions of the period.
+    pub fn parse(text: &str) -> Result<Vec<f32>, String> {
+        let mut offsets = Vec::new();
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            let percent = |text: &str| text.trim_end_matches('%').parse::<f32>()
+                .map_err(|_| format!("invalid groove step `{}`", line));
+            match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+                // every second step is delayed towards the next one
+                ["swing", swing] => offsets.extend_from_slice(&[0.0, percent(swing)? / 50.0 - 1.0]),
+                [offset] => offsets.push(percent(offset)? / 100.0),
+                _ => return Err(format!("invalid groove step `{}`", line)),
+            }
+        }
+        Ok(offsets)
+    }
+
+    /// Replaces the template, an empty one keeps the boundaries on the grid.
+    pub fn set(&self, offsets: &[f32]) {
+        let len = usize::min(offsets.len(), MAX_STEPS);
+        for (slot, &offset) in self.offsets.iter().zip(&offsets[..len]) {
+            slot.set(offset.clamp(-MAX_OFFSET, MAX_OFFSET));
+        }
+        self.len.store(len, Ordering::Release);
+    }
+
+    /// Loads a template from a text file.
+    pub fn load(&self, path: &Path) -> io::Result<()> {
+        let offsets = Self::parse(&fs::read_to_string(path)?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        self.set(&offsets);
+        Ok(())
+    }
+
+    /// The current offsets as fractions of the period.
+    pub fn offsets(&self) -> Vec<f32> {
+        self.offsets[..self.len.load(Ordering::Acquire)].iter().map(AtomicFloat::get).collect()
+    }
+
+    fn offset(&self, step: i64) -> f32 {
+        match self.len.load(Ordering::Acquire) {
+            0 => 0.0,
+            len => self.offsets[step.rem_euclid(len as i64) as usize].get(),
+        }
+    }
+
+    // how much longer or shorter than the grid the period starting `step` is
+    pub(crate) fn period_scale(&self, step: i64) -> f32 {
+        1.0 + self.offset(step + 1) - self.offset(step)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_offsets_and_swing() {
+        assert_eq!(GrooveTemplate::parse("0\n# comment\n\n-10%\n25"), Ok(vec![0.0, -0.1, 0.25]));
+        assert_eq!(GrooveTemplate::parse("swing 75%"), Ok(vec![0.0, 0.5]));
+        assert!(GrooveTemplate::parse("late").is_err());
+    }
+
+    #[test]
+    fn periods_follow_offsets() {
+        let groove = GrooveTemplate::default();
+        assert_eq!(groove.period_scale(3), 1.0);
+
+        groove.set(&[0.0, 0.2]);
+        assert_eq!(groove.period_scale(0), 1.2);
+        assert_eq!(groove.period_scale(1), 0.8);
+        assert_eq!(groove.period_scale(-2), 1.2);
+    }
+}
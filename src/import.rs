@@ -0,0 +1,190 @@
+//! Loading audio files into the snapshot buffer.
+//!
+//! Files are decoded, resampled and prepared for playback on a worker thread,
+//! the audio thread then swaps them in at the start of the next processed
+//! block. How it went is kept in a `FileStatus`.
+
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use vst::util::AtomicFloat;
+
+use crate::dsp::{max_snapshot_len, Snapshot};
+use crate::wav;
+
+/// What became of the last file read or written on a worker thread.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FileStatus {
+    /// nothing was started yet
+    Idle,
+    /// the worker is still at it
+    Busy,
+    /// done, a loaded file plays from the next processed block
+    Done,
+    /// failed with this error
+    Failed(String),
+}
+
+impl FileStatus {
+    pub(crate) fn of(result: &io::Result<()>) -> Self {
+        match result {
+            Ok(()) => FileStatus::Done,
+            Err(err) => FileStatus::Failed(err.to_string()),
+        }
+    }
+}
+
+// prepared buffers waiting for the audio thread, which swaps them in and leaves
+// the ones it swapped out for the next worker to drop
+pub(crate) struct Pending<T> {
+    slot: Mutex<(bool, T)>,
+}
+
+impl<T: Default> Default for Pending<T> {
+    fn default() -> Self {
+        Self {
+            slot: Mutex::new((false, T::default())),
+        }
+    }
+}
+
+impl<T> Pending<T> {
+    pub(crate) fn put(&self, value: T) {
+        *self.slot.lock().unwrap() = (true, value);
+    }
+
+    // never blocks, if the worker is just handing over we pick it up next time
+    pub(crate) fn take(&self, load: impl FnOnce(&mut T)) {
+        if let Ok(mut slot) = self.slot.try_lock() {
+            if slot.0 {
+                slot.0 = false;
+                load(&mut slot.1);
+            }
+        }
+    }
+}
+
+pub struct SnapshotImport {
+    // sample rate the decoded audio gets converted to
+    sample_rate: AtomicFloat,
+    // number of channels the decoded audio is mapped to
+    channel_count: AtomicUsize,
+    // the last imported file, stored with the plugin state
+    path: Mutex<Option<PathBuf>>,
+    // decoded snapshots waiting for the audio thread, one per channel
+    pending: Pending<Vec<Snapshot>>,
+    status: Mutex<FileStatus>,
+}
+
+impl Default for SnapshotImport {
+    fn default() -> Self {
+        Self {
+            sample_rate: AtomicFloat::new(44_100.0),
+            channel_count: AtomicUsize::new(2),
+            path: Mutex::new(None),
+            pending: Pending::default(),
+            status: Mutex::new(FileStatus::Idle),
+        }
+    }
+}
+
+impl SnapshotImport {
+    /// Starts decoding a WAV file on a worker thread, once done it replaces the
+    /// playing snapshot until the next recapture. The result is also kept for
+    /// `status`.
+    pub fn import(self: &Arc<Self>, path: PathBuf) -> thread::JoinHandle<io::Result<()>> {
+        *self.path.lock().unwrap() = Some(path.clone());
+        *self.status.lock().unwrap() = FileStatus::Busy;
+        let this = Arc::clone(self);
+        thread::spawn(move || {
+            let result = this.decode(&path).map(|snapshots| this.pending.put(snapshots));
+            *this.status.lock().unwrap() = FileStatus::of(&result);
+            result
+        })
+    }
+
+    /// The last imported file.
+    pub fn path(&self) -> Option<PathBuf> {
+        self.path.lock().unwrap().clone()
+    }
+
+    /// How the last import went.
+    pub fn status(&self) -> FileStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    pub(crate) fn set_sample_rate(&self, rate: f32) {
+        self.sample_rate.set(rate);
+    }
+
+    pub(crate) fn set_channel_count(&self, count: usize) {
+        self.channel_count.store(count, Ordering::Relaxed);
+    }
+
+    // called on the audio thread with the imported snapshots, if there are any
+    pub(crate) fn take_pending(&self, load: impl FnOnce(&mut Vec<Snapshot>)) {
+        self.pending.take(load)
+    }
+
+    fn decode(&self, path: &Path) -> io::Result<Vec<Snapshot>> {
+        let mut wav = wav::read(BufReader::new(File::open(path)?))?;
+        let sample_rate = self.sample_rate.get();
+        let ratio = sample_rate as f64 / wav.sample_rate as f64;
+
+        // a narrower file repeats its last channel
+        let last = wav.channels.last().cloned().unwrap_or_default();
+        wav.channels.resize(self.channel_count.load(Ordering::Relaxed), last);
+
+        let max_len = max_snapshot_len(sample_rate);
+        Ok(wav.channels.iter().map(|chan| Snapshot::prepare(&resample(chan, ratio), max_len)).collect())
+    }
+}
+
+// linear interpolation is plenty for loading a loop
+pub(crate) fn resample(samples: &[f32], ratio: f64) -> Vec<f32> {
+    if (ratio - 1.0).abs() < 1e-9 || samples.is_empty() {
+        return samples.to_vec();
+    }
+    let len = (samples.len() as f64 * ratio).round() as usize;
+    (0..len)
+        .map(|i| {
+            let pos = i as f64 / ratio;
+            let low = pos.floor() as usize;
+            let high = usize::min(low + 1, samples.len() - 1);
+            let fract = (pos - low as f64) as f32;
+            let low = samples[usize::min(low, samples.len() - 1)];
+            low + (samples[high] - low) * fract
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsp::HEADROOM;
+
+    #[test]
+    fn imports_prepared_or_reported() {
+        let import = Arc::new(SnapshotImport::default());
+        import.set_channel_count(1);
+        assert_eq!(import.status(), FileStatus::Idle);
+        let path = std::env::temp_dir().join(format!("snapshot_repeat_import_{}.wav", std::process::id()));
+        let wav = wav::Wav { sample_rate: 44_100, channels: vec![vec![0.5; 10]] };
+        wav::write(File::create(&path).unwrap(), &wav).unwrap();
+        import.import(path.clone()).join().unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(import.status(), FileStatus::Done);
+        // ready to swap in, at the internal level
+        let mut imported = Vec::new();
+        import.take_pending(|snapshots| imported = snapshots.iter().map(|s| s.samples().to_vec()).collect());
+        assert_eq!(imported, vec![vec![0.5 * HEADROOM; 10]]);
+
+        let err = import.import(path).join().unwrap().unwrap_err();
+        assert_eq!(import.status(), FileStatus::Failed(err.to_string()));
+        import.take_pending(|_| panic!("nothing to load"));
+    }
+}
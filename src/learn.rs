@@ -0,0 +1,108 @@
+//! MIDI learn, hardware controllers driving the parameters.
+//!
+//! Each of the 128 controller numbers can be mapped to a parameter. While a
+//! parameter is learning, the next controller moved gets mapped to it. The
+//! controller values (0 ..= 127) become the normalized value of the mapped
+//! parameter, its own scale takes it from there like with host automation.
+
+use std::sync::atomic::{AtomicI32, Ordering};
+
+const CONTROLLERS: usize = 128;
+// an unmapped controller, or no parameter learning
+const NONE: i32 = -1;
+
+pub struct MidiLearn {
+    // index of the parameter each controller drives, atomic so the audio thread never waits for an edit
+    mappings: Vec<AtomicI32>,
+    learning: AtomicI32,
+}
+
+impl Default for MidiLearn {
+    fn default() -> Self {
+        Self {
+            mappings: (0..CONTROLLERS).map(|_| AtomicI32::new(NONE)).collect(),
+            learning: AtomicI32::new(NONE),
+        }
+    }
+}
+
+impl MidiLearn {
+    /// Maps the next controller moved to the parameter at `index`, in place of
+    /// any controller driving it so far.
+    pub fn learn(&self, index: i32) {
+        self.learning.store(index, Ordering::Release);
+    }
+
+    /// Stops waiting for a controller without mapping one.
+    pub fn cancel(&self) {
+        self.learning.store(NONE, Ordering::Release);
+    }
+
+    /// The parameter waiting for a controller, if any.
+    pub fn learning(&self) -> Option<i32> {
+        Some(self.learning.load(Ordering::Acquire)).filter(|&index| index != NONE)
+    }
+
+    /// Maps controller `cc` to the parameter at `index`, replacing whatever it drove before.
+    pub fn map(&self, cc: u8, index: i32) {
+        if let Some(mapping) = self.mappings.get(cc as usize) {
+            mapping.store(index, Ordering::Release);
+        }
+    }
+
+    pub fn unmap(&self, cc: u8) {
+        self.map(cc, NONE);
+    }
+
+    /// Drops all the mappings.
+    pub fn clear(&self) {
+        self.mappings.iter().for_each(|mapping| mapping.store(NONE, Ordering::Release));
+    }
+
+    /// The parameter controller `cc` drives, if any.
+    pub fn mapping(&self, cc: u8) -> Option<i32> {
+        self.mappings.get(cc as usize)
+            .map(|mapping| mapping.load(Ordering::Acquire))
+            .filter(|&index| index != NONE)
+    }
+
+    /// All the mapped controllers with the parameters they drive.
+    pub fn mappings(&self) -> Vec<(u8, i32)> {
+        (0..CONTROLLERS as u8).filter_map(|cc| self.mapping(cc).map(|index| (cc, index))).collect()
+    }
+
+    // called on the audio thread for each controller message, finishes learning
+    // and returns the parameter to set with its normalized value
+    pub(crate) fn control_change(&self, cc: u8, value: u8) -> Option<(i32, f32)> {
+        let learning = self.learning.swap(NONE, Ordering::AcqRel);
+        if learning != NONE {
+            for mapping in self.mappings.iter().filter(|mapping| mapping.load(Ordering::Acquire) == learning) {
+                mapping.store(NONE, Ordering::Release);
+            }
+            self.map(cc, learning);
+        }
+        self.mapping(cc).map(|index| (index, value.min(127) as f32 / 127.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn learns_next_controller() {
+        let learn = MidiLearn::default();
+        assert_eq!(learn.control_change(74, 127), None);
+
+        learn.learn(2);
+        assert_eq!(learn.learning(), Some(2));
+        assert_eq!(learn.control_change(74, 127), Some((2, 1.0)));
+        assert_eq!(learn.learning(), None);
+        assert_eq!(learn.control_change(74, 0), Some((2, 0.0)));
+
+        // learning again moves the parameter to the new controller
+        learn.learn(2);
+        learn.control_change(1, 64);
+        assert_eq!(learn.mappings(), vec![(1, 2)]);
+    }
+}
@@ -1,243 +1,1757 @@
-#![allow(incomplete_features)]
-
-#![feature(const_generics)]
-#![feature(slice_fill)]
-
-
 use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::ffi::c_void;
 use std::mem;
-use std::marker::PhantomData;
-use vst::buffer::AudioBuffer;
-use vst::plugin::{Category, Info, Plugin, PluginParameters};
+use std::ptr;
+use std::fmt::Write;
+use std::f32::consts::FRAC_PI_4;
+use std::ops::Range;
+use vst::api::{Events, Supported, TimeInfoFlags};
+use vst::buffer::{AudioBuffer, Inputs, Outputs};
+use vst::channels::ChannelInfo;
+use vst::host::{Host, OpCode};
+use vst::plugin::{CanDo, Category, HostCallback, Info, Plugin, PluginParameters};
 use vst::plugin_main;
-use vst::util::AtomicFloat;
 
-const CHANNELS: usize = 2;
+mod analysis;
+mod bank;
+mod denormals;
+mod dsp;
+mod events;
+mod granular;
+mod groove;
+mod import;
+mod learn;
+mod midi;
+mod modulation;
+mod properties;
+mod random;
+mod scenes;
+#[cfg(test)]
+mod sim;
+mod stretch;
+mod triple_buffer;
+mod visualization;
+pub mod wav;
 
-#[derive(Default)]
-struct SnapshotRepeatPlugin {
+pub use analysis::{AnalysisPipeline, Analyzer, Rms};
+pub use bank::SnapshotBank;
+use denormals::DenormalGuard;
+use dsp::{
+    db_to_gain, note_value, period_samples, process_channel, ramp, semitones_to_ratio, soft_clip, soft_feedback,
+    CapturePosition, ChannelSettings, ChannelState, Engine, FadeCurve, FixedDelay, Limiter, LoopMode, LoopPoints,
+    Normalize, OutputClip, Params, Quantize, Sample, ScaledParameter, SidechainMode, Transport, TremoloShape,
+    WhenStopped,
+    COPY_SCENE_PARAM, ENABLE_FADE_MS, FEEDBACK_LIMITER_PARAM, FEEDBACK_PARAM, FREEZE_TRANSPOSE_PARAM, GATE_STEPS,
+    HEADROOM, LOCK_LEVELS_PARAM, LOCK_SYNC_PARAM, MAX_MAKEUP_DB, NOTE_VALUES, PLAYBACK_RATE_PARAM,
+    ROTATE_DEPTH_PARAM, SAFE_MODE_PARAM, SCENE_PARAM, SILENCE_LEVEL,
+};
+pub use dsp::SnapshotRepeat;
+use events::EventQueue;
+use modulation::{Destination, Modulation, Source};
+use random::Random;
+use scenes::in_scene;
+pub use events::Trigger;
+pub use groove::GrooveTemplate;
+pub use import::{FileStatus, SnapshotImport};
+pub use learn::MidiLearn;
+use visualization::overview_levels;
+pub use visualization::{SnapshotOverview, Visualization, OVERVIEW_LEN, OVERVIEW_LEVELS};
+
+// VST2 (as exposed by the `vst` crate) has no way to agree on a layout with the
+// host, so we declare enough pins for 7.1 and process as many as we get
+const MAX_CHANNELS: usize = 8;
+// the stereo sidechain pair comes after the main inputs
+const SIDECHAIN_CHANNELS: usize = 2;
+const PARAMETERS: i32 = 140;
+// names the parameters are stored under in the state chunk, in index order, unlike
+// the indices they stay put when parameters are reordered and can be shared with
+// other plugin formats, so never rename or reuse one
+const PARAMETER_IDS: [&str; PARAMETERS as usize] = [
+    "period",
+    "capture_len",
+    "playback_rate",
+    "pump_depth",
+    "pump_shape",
+    "rotate_depth",
+    "rotate_rate",
+    "loop_mode",
+    "freeze",
+    "freeze_transpose",
+    "freeze_glide",
+    "sidechain",
+    "feedback",
+    "feedback_limiter",
+    "overdub",
+    "clear",
+    "input_trim",
+    "output_gain",
+    "sync",
+    "sync_numerator",
+    "sync_denominator",
+    "bypass",
+    "midi_trigger",
+    "latch_random",
+    "seed",
+    "capture_enable",
+    "playback_enable",
+    "mix",
+    "duck",
+    "engine",
+    "grain_size",
+    "grain_density",
+    "position_spray",
+    "pitch_spray",
+    "safe_mode",
+    "repeat_division",
+    "quantize",
+    "division_swing",
+    "edge_fade_ms",
+    "repeat_probability",
+    "capture_position",
+    "capture_offset",
+    "reverse_probability",
+    "mosaic_segments",
+    "mosaic_probability",
+    "mosaic_random",
+    "slice_length",
+    "slice_random",
+    "silence_hold",
+    "rate_start",
+    "rate_end",
+    "start_fade",
+    "zero_crossing",
+    "lock_sync",
+    "lock_levels",
+    "wet_highpass",
+    "wet_lowpass",
+    "crush_bits",
+    "downsample",
+    "spread",
+    "manual_bpm",
+    "tail_crossfade_ms",
+    "capture_ratio",
+    "period_jitter",
+    "attack",
+    "decay",
+    "fade_curve",
+    "rate_display",
+    "mono_capture",
+    "normalize",
+    "normalize_target",
+    "dc_filter",
+    "output_clip",
+    "clip_ceiling",
+    "loop_start",
+    "loop_length",
+    "scrub",
+    "position",
+    "reset_on_start",
+    "when_stopped",
+    "taps",
+    "tap_1_rate",
+    "tap_1_level",
+    "tap_2_rate",
+    "tap_2_level",
+    "tap_3_rate",
+    "tap_3_level",
+    "tap_4_rate",
+    "tap_4_level",
+    "octave_down_level",
+    "octave_up_level",
+    "fifth_level",
+    "time_stretch",
+    "capture_threshold",
+    "skip_silence",
+    "auto_gain",
+    "lfo_1_rate",
+    "lfo_2_rate",
+    "mod_controller",
+    "route_1_source",
+    "route_1_destination",
+    "route_1_depth",
+    "route_2_source",
+    "route_2_destination",
+    "route_2_depth",
+    "route_3_source",
+    "route_3_destination",
+    "route_3_depth",
+    "route_4_source",
+    "route_4_destination",
+    "route_4_depth",
+    "note_values",
+    "scene",
+    "copy_scene",
+    "history",
+    "loop_points",
+    "phase_offset",
+    "channel_delay",
+    "vibrato_rate",
+    "vibrato_depth",
+    "tremolo_depth",
+    "tremolo_rate",
+    "tremolo_shape",
+    "tremolo_sync",
+    "gate_step_1",
+    "gate_step_2",
+    "gate_step_3",
+    "gate_step_4",
+    "gate_step_5",
+    "gate_step_6",
+    "gate_step_7",
+    "gate_step_8",
+    "gate_step_9",
+    "gate_step_10",
+    "gate_step_11",
+    "gate_step_12",
+    "gate_step_13",
+    "gate_step_14",
+    "gate_step_15",
+    "gate_step_16",
+];
+// IDs of parameters from before their scale changed, with the index they're at
+// now and the top of the old scale, which was linear from zero
+const LEGACY_IDS: [(&str, i32, f32); 2] = [("edge_fade", 38, 5.0), ("tail_crossfade", 61, 50.0)];
+// what each parameter does, in index order, for anything explaining them to the user,
+// the steps of the gate pattern at the end share `GATE_STEP_DESCRIPTION`
+const PARAMETER_DESCRIPTIONS: [&str; PARAMETERS as usize - GATE_STEPS] = [
+    "Length of each period, after which the latest capture starts repeating.",
+    "How much of each period is captured, at most the whole period.",
+    "Speed the snapshot is played back at, 1x being the original.",
+    "How much the repeats are ducked at the start of each beat, or each period without a host tempo.",
+    "How fast the pump recovers, higher is faster.",
+    "How far the stereo field of the repeats swings, at most 45 degrees each way.",
+    "How fast the stereo field of the repeats swings.",
+    "What happens when the host transport jumps: ignore it, start a new period or align to the song.",
+    "Keeps looping the current snapshot instead of replacing it each period.",
+    "Semitones the frozen snapshot is transposed by.",
+    "How long the playback glides into the freeze transposition.",
+    "Whether the main input or the sidechain is captured, and whether the repeats play over the main input.",
+    "How much of the playing snapshot is mixed into the next capture.",
+    "Read only, how much the feedback is turned down to keep the snapshots under 0 dBFS.",
+    "Sums each capture into the playing snapshot instead of replacing it.",
+    "Drops the snapshot and starts a new capture when switched on.",
+    "Gain of the captured input, muted at the bottom.",
+    "Gain of the repeats, muted at the bottom.",
+    "Makes each period last a ratio of beats of the host tempo instead of the period length.",
+    "Beats each synced period lasts, before dividing by the denominator.",
+    "What the synced beats are divided by.",
+    "Crossfades to the unprocessed input, the repeats keep running in the background.",
+    "Each MIDI note on starts playing what has been captured so far.",
+    "With MIDI trigger, each note picks a random playback rate and start position until it ends.",
+    "Starting point of the random sequence, the same seed repeats the same choices.",
+    "Whether new snapshots are captured, the last one keeps playing when off.",
+    "Whether the repeats are heard, everything keeps running when off.",
+    "Balance between the dry input and the repeats.",
+    "How much the dry input is turned down while the repeats play.",
+    "Plays the snapshot straight through (scan) or as a cloud of short grains (granular).",
+    "Length of each grain.",
+    "Grains started each second.",
+    "How far from the scan position grains start, up to the whole snapshot.",
+    "Semitones each grain is transposed by at random, both ways.",
+    "Read only, on when broken output keeps happening and the input is passed through until cleared.",
+    "Restarts the snapshot several times each period for stutter rolls.",
+    "Ends each period on the nearest beat or bar line of the host tempo.",
+    "Delays every other restart of the repeat division.",
+    "Fade at the edges of each snapshot so it doesn't click where the capture cut in.",
+    "Chance of each period being repeated, the dry input passes through otherwise.",
+    "Where the captured input goes in the snapshot: the start, an offset, a moving window or a mosaic segment.",
+    "Where the capture starts in the offset position, as a fraction of the snapshot.",
+    "Chance of each period playing the snapshot backwards.",
+    "How many parts the snapshot is split into in the mosaic position.",
+    "Chance of a mosaic segment being refreshed each period.",
+    "Whether the mosaic segments are refreshed in turn or at random.",
+    "Chops the snapshot into slices each division loops one of.",
+    "Chance of a division playing a random slice instead of the next one.",
+    "Silence after which the snapshot stops being replaced until the input comes back.",
+    "Playback rate multiplier at the start of each period.",
+    "Playback rate multiplier at the end of each period.",
+    "How long the repeats fade in after the transport starts or jumps.",
+    "Each capture waits briefly for the input to cross zero so the snapshots don't click.",
+    "Loading a preset keeps the sync settings and the groove.",
+    "Loading a preset keeps the input trim, output gain and mix.",
+    "Cutoff of the high-pass filter on the repeats.",
+    "Cutoff of the low-pass filter on the repeats.",
+    "Bit depth the repeats are reduced to.",
+    "Factor the sample rate of the repeats is reduced by.",
+    "Plays each channel slightly detuned and offset, turning a mono input into wide stereo repeats.",
+    "Tempo the synced features follow when the host doesn't provide one.",
+    "How long the outgoing snapshot keeps playing under the start of the new one.",
+    "Sets the capture length as a share of the period instead of in samples.",
+    "Randomly lengthens or shortens each period by up to this much.",
+    "Fades each repeat in over this long from the start of its period.",
+    "Fades each repeat out to silence over this long after the attack.",
+    "Shape of the fades at the snapshot edges, the tail crossfade and bypass.",
+    "Shows the playback rate in semitones and cents instead of as a multiplier.",
+    "Captures the mono sum of the inputs, all channels repeat the same snapshot.",
+    "Brings each new snapshot to the target level by its peak or RMS level.",
+    "Level normalized snapshots are brought to.",
+    "Removes DC offset from the input as it's captured, offsets thump at every loop.",
+    "Keeps the output under the ceiling with a soft clipper or a limiter.",
+    "Level the output clipper or limiter never lets the output past.",
+    "Where in the snapshot the looped part starts.",
+    "How much of the snapshot playback loops in, all of it at 100%.",
+    "Playback follows Position instead of running through the snapshot on its own.",
+    "Where in the looped part of the snapshot playback is while scrubbing.",
+    "Starts over from a fresh capture whenever the host starts playing, so renders come out the same every time.",
+    "Whether the repeats keep running while the host is stopped, or the plugin holds still passing the dry input or silence.",
+    "Extra play heads scanning the same snapshot at their own rates, summed with the main one for chorused or harmonized repeats.",
+    "Playback rate of tap 1 as a multiple of the main playback rate.",
+    "Level of tap 1 relative to the main play head.",
+    "Playback rate of tap 2 as a multiple of the main playback rate.",
+    "Level of tap 2 relative to the main play head.",
+    "Playback rate of tap 3 as a multiple of the main playback rate.",
+    "Level of tap 3 relative to the main play head.",
+    "Playback rate of tap 4 as a multiple of the main playback rate.",
+    "Level of tap 4 relative to the main play head.",
+    "Level of a layer an octave below the repeats, read from the snapshot at half the playback rate. Off at the bottom.",
+    "Level of a layer an octave above the repeats, read from the snapshot at twice the playback rate. Off at the bottom.",
+    "Level of a layer a fifth above the repeats, read from the snapshot at one and a half times the playback rate. Off at the bottom.",
+    "Lets the scan engine slow down or speed up the snapshot without changing its pitch, by overlapping short windows of it.",
+    "A capture only replaces the snapshot if the input got this loud during it, otherwise the previous snapshot keeps playing. Off at the bottom.",
+    "Keeps playing the previous snapshot when a capture came out essentially silent, instead of repeating the silence.",
+    "Keeps the repeats as loud as the input by measuring both and making up the difference, so changing the mix or bypassing doesn't jump in loudness.",
+    "Rate of the sine LFO 1 the modulation routes can use.",
+    "Rate of the sine LFO 2 the modulation routes can use.",
+    "The MIDI controller the modulation routes use as their controller source, the mod wheel by default.",
+    "What modulation route 1 modulates with: one of the two LFOs, the input level, a random value for each period or a MIDI controller.",
+    "The parameter modulation route 1 moves: playback rate, period, capture length, mix, loop start, pump depth or rotate depth.",
+    "How far modulation route 1 moves its destination, as a share of the destination's whole range, negative values invert the source.",
+    "What modulation route 2 modulates with: one of the two LFOs, the input level, a random value for each period or a MIDI controller.",
+    "The parameter modulation route 2 moves: playback rate, period, capture length, mix, loop start, pump depth or rotate depth.",
+    "How far modulation route 2 moves its destination, as a share of the destination's whole range, negative values invert the source.",
+    "What modulation route 3 modulates with: one of the two LFOs, the input level, a random value for each period or a MIDI controller.",
+    "The parameter modulation route 3 moves: playback rate, period, capture length, mix, loop start, pump depth or rotate depth.",
+    "How far modulation route 3 moves its destination, as a share of the destination's whole range, negative values invert the source.",
+    "What modulation route 4 modulates with: one of the two LFOs, the input level, a random value for each period or a MIDI controller.",
+    "The parameter modulation route 4 moves: playback rate, period, capture length, mix, loop start, pump depth or rotate depth.",
+    "How far modulation route 4 moves its destination, as a share of the destination's whole range, negative values invert the source.",
+    "In sync, makes the period parameter pick a note value (1/32 to 4 bars, dotted and triplet) instead of using the sync ratio.",
+    "Switches between two scenes, A and B, each remembering its own settings to compare while tweaking.",
+    "Switching it on copies the current scene's settings to the other scene.",
+    "Goes back to one of the last captured snapshots, to get back a loop that was just replaced. New captures are held while going back.",
+    "Trims each capture to loop points that join up smoothly, at zero crossings or where the end best matches the start, so the seam doesn't click even without fades.",
+    "Starts the right channel further into the snapshot than the left, by a fraction of its length, for wide stereo textures from a mono-ish capture.",
+    "Delays the repeats of one side against the other by up to 40 ms for Haas style widening, the right side when positive and the left when negative.",
+    "How fast the vibrato swings the pitch of the repeats.",
+    "How far the vibrato swings the pitch of the repeats either way, for tape warble and chorus like repeats.",
+    "How far the tremolo takes the level of the repeats down, the dry signal is left alone. With the square shape it chops the repeats like a trance gate.",
+    "How fast the tremolo goes, in hertz or as a note value when synced to the tempo.",
+    "The shape of the tremolo, a smooth sine, an on/off square or a saw falling over each cycle.",
+    "Syncs the tremolo to the tempo of the host, its rate picks a note value and the cycles line up with the beats.",
+];
+const GATE_STEP_DESCRIPTION: &str =
+    "Whether the repeats play through this step of the gate pattern, the period is split into 16 steps and the repeats are muted in the ones switched off.";
+// the parameter groups that can be locked
+const SYNC_GROUP: [i32; 6] = [18, 19, 20, 36, 60, 111];
+const LEVELS_GROUP: [i32; 3] = [16, 17, 27];
+// blocks with broken output after which processing falls back to safe mode
+const SAFE_MODE_ANOMALIES: u32 = 3;
+// how far (in samples) the host position may be off before we consider it a jump
+const TRANSPORT_JUMP_TOLERANCE: f64 = 16.0;
+// how long the crossfade into and out of bypass takes
+const BYPASS_FADE_MS: f32 = 10.0;
+// the envelope following the repeats for ducking the dry signal
+const DUCK_ATTACK_MS: f32 = 1.0;
+const DUCK_RELEASE_MS: f32 = 150.0;
+// level of the repeats at which the dry signal is ducked by the full amount
+const DUCK_FULL_LEVEL: f32 = 0.25;
+// how fast the output limiter lets go once the peaks are back under the ceiling
+const LIMITER_RELEASE_MS: f32 = 50.0;
+// time the levels matched by auto gain are averaged over
+const LEVEL_MATCH_MS: f32 = 300.0;
+// range of the playback rate variation latched by each note, in semitones both ways
+const LATCH_RATE_SEMITONES: f32 = 2.0;
+// number of places in the snapshot a latched note can start playing from
+const LATCH_SLICES: f32 = 8.0;
+
+pub struct SnapshotRepeatPlugin {
+    // not available when running outside of a host
+    host: Option<HostCallback>,
+    sample_rate: f32,
     params: Arc<Params>,
-    channel_states: [ChannelState; CHANNELS],
+    visualization: Arc<Visualization>,
+    analysis: Arc<AnalysisPipeline>,
+    // one for each channel there can be, allocated up front so a change of the
+    // channels passed by the host only changes how many of them are processed
+    channel_states: Vec<ChannelState>,
+    // channels processed in the last block
+    channel_count: usize,
+    // where the host transport should be at the start of the next block if it keeps playing
+    expected_ppq_pos: Option<f64>,
+    // position of the clock run at the manual tempo, it goes on from wherever the host left off
+    manual_ppq_pos: f64,
+    // whether the host transport was playing during the last block
+    playing: bool,
+    // gain of the repeats fading in after the transport starts or jumps
+    start_gain: f32,
+    // triggers waiting for their sample of the next block
+    events: EventQueue,
+    // freeze state applied to the channels, set by `Trigger::Freeze`
+    frozen: bool,
+    // last seen value of the freeze parameter, its changes become triggers
+    freeze_param: bool,
+    // last seen value of the clear parameter, switching it on triggers a clear
+    clear_param: bool,
+    // how much of the output is the dry input, fades towards the bypass parameter
+    bypass_mix: f32,
+    // how much of the output is the dry input because the period isn't repeated
+    skip_mix: f32,
+    // how far the output has faded to what's held while the transport is stopped,
+    // and what that was the last time it was held
+    hold_mix: f32,
+    hold_mode: WhenStopped,
+    // mix at the end of the last block, the next one moves from there
+    last_mix: Option<f32>,
+    // draws the variations latched by MIDI notes
+    random: Random,
+    // seed the generator was last reset with
+    seed: Option<u32>,
+    // the note holding the current variation
+    latched_note: Option<u8>,
+    // holds back the input the output is crossfaded with past the limiter, as far as
+    // the delaying stages hold back the output, which is the latency reported
+    dry_delay: FixedDelay,
+    // the input of each channel held back for the block, only reallocated when blocks get longer
+    held_inputs: Vec<Vec<f64>>,
+    // level of the repeats followed for ducking
+    duck_envelope: f32,
+    // gain of the dry signal for each sample of the block, only reallocated when blocks get longer
+    duck_gains: Vec<f32>,
+    // blocks with broken output since the last clear
+    anomalies: u32,
+    // the output limiter, its lookahead is part of the latency
+    limiter: Limiter,
+    // mean square levels of the input and the repeats, and the gain matching them
+    input_power: f32,
+    wet_power: f32,
+    match_gain: f32,
+    // average of the captured inputs for mono capture, only reallocated when blocks get longer
+    mono_sum: Vec<f32>,
+    // overview levels of the front channel's snapshot, redone for each new one
+    overview: Vec<Vec<(f32, f32)>>,
+    // sources of the modulation matrix
+    modulation: Modulation,
+    // stands in for the host's transport in tests
+    #[cfg(test)]
+    test_transport: Option<Transport>,
 }
 
-trait Scale {
-    fn to_norm(scaled: f32) -> f32;
-    fn to_scaled(norm: f32) -> f32;
+impl Default for SnapshotRepeatPlugin {
+    fn default() -> Self {
+        Self {
+            host: None,
+            sample_rate: 44_100.0,
+            params: Default::default(),
+            visualization: Default::default(),
+            analysis: Default::default(),
+            channel_states: (0..MAX_CHANNELS).map(|_| ChannelState::default()).collect(),
+            channel_count: 0,
+            expected_ppq_pos: None,
+            manual_ppq_pos: 0.0,
+            playing: false,
+            start_gain: 1.0,
+            events: Default::default(),
+            frozen: false,
+            freeze_param: false,
+            clear_param: false,
+            bypass_mix: 0.0,
+            skip_mix: 0.0,
+            hold_mix: 0.0,
+            hold_mode: WhenStopped::Silence,
+            last_mix: None,
+            random: Random::new(0),
+            seed: None,
+            latched_note: None,
+            dry_delay: Default::default(),
+            held_inputs: vec![Vec::new(); MAX_CHANNELS],
+            duck_envelope: 0.0,
+            duck_gains: Vec::new(),
+            anomalies: 0,
+            limiter: Default::default(),
+            input_power: 0.0,
+            wet_power: 0.0,
+            match_gain: 1.0,
+            mono_sum: Vec::new(),
+            overview: SnapshotOverview::new().levels,
+            modulation: Default::default(),
+            #[cfg(test)]
+            test_transport: None,
+        }
+    }
 }
 
-struct LinScale<const LOW: f32, const HIGH: f32>;
+impl Plugin for SnapshotRepeatPlugin {
+    fn get_info(&self) -> Info {
+        Info {
+            name: "Snapshot Repeat".to_string(),
+            vendor: "ametisf".to_string(),
+            unique_id: 141375252,
+            version: 1,
+            inputs: (MAX_CHANNELS + SIDECHAIN_CHANNELS) as i32,
+            outputs: MAX_CHANNELS as i32,
+            parameters: PARAMETERS,
+            initial_delay: self.dry_delay.delay() as i32,
+            midi_inputs: 1,
+            preset_chunks: true,
+            f64_precision: true,
+            category: Category::Effect,
+            ..Default::default()
+        }
+    }
 
-impl<const LOW: f32, const HIGH: f32> Scale for LinScale<LOW, HIGH> {
-    fn to_norm(scaled: f32) -> f32 {
-        debug_assert!(LOW <= scaled && scaled <= HIGH);
-        (scaled - LOW) / (HIGH - LOW)
+    fn new(host: HostCallback) -> Self {
+        Self {
+            host: Some(host),
+            ..Default::default()
+        }
     }
 
-    fn to_scaled(norm: f32) -> f32 {
-        debug_assert!(0.0 <= norm && norm <= 1.0);
-        LOW + norm * (HIGH - LOW)
+    // `vst` has filled in the `AEffect` by the time the host opens the plugin
+    fn init(&mut self) {
+        if let Some(host) = &self.host {
+            let effect = host.raw_effect();
+            if !effect.is_null() {
+                unsafe { properties::install(effect, &self.params) };
+            }
+        }
+    }
+
+    fn set_sample_rate(&mut self, rate: f32) {
+        self.sample_rate = rate;
+        self.params.import.set_sample_rate(rate);
+        self.params.bank.set_sample_rate(rate);
+        self.params.sample_rate.set(rate);
+        self.analysis.set_sample_rate(rate);
+        let len = dsp::reserved_len(&self.params);
+        self.channel_states.iter_mut().for_each(|chan_state| chan_state.allocate(rate, len));
+    }
+
+    // the host turns the plugin off while bypassing it or changing the setup, the
+    // snapshots would be seconds old by the time it comes back and were captured at
+    // whatever rate was used before, frozen ones are held on purpose and stay
+    fn suspend(&mut self) {
+        if !self.frozen {
+            self.channel_states.iter_mut().for_each(ChannelState::reset);
+        }
     }
-}
 
-struct ScaledParameter<S: Scale> {
-    inner: AtomicFloat,
-    _scale: PhantomData<S>,
+    fn resume(&mut self) {
+        // in case the host never set the sample rate
+        let (sample_rate, len) = (self.sample_rate, dsp::reserved_len(&self.params));
+        self.channel_states.iter_mut().for_each(|chan_state| chan_state.allocate(sample_rate, len));
+        self.update_latency();
+        self.pick_up();
+    }
+
+    // the host stops calling `process` for a while, without turning the plugin off
+    fn stop_process(&mut self) {
+        self.pick_up();
+    }
+
+    // the host's bypass switch, passed on by `properties`
+    fn vendor_specific(&mut self, index: i32, value: isize, _ptr: *mut c_void, _opt: f32) -> isize {
+        if index != properties::HOST_BYPASS {
+            return 0
+        }
+        self.params.host_bypass.store(value != 0, Ordering::Relaxed);
+        1
+    }
+
+    fn can_do(&self, can_do: CanDo) -> Supported {
+        match can_do {
+            // needed for anything synced to the host tempo
+            CanDo::ReceiveTimeInfo => Supported::Yes,
+            // some hosts won't offer MIDI routing to the plugin without these
+            CanDo::ReceiveEvents | CanDo::ReceiveMidiEvent => Supported::Yes,
+            // we never produce MIDI
+            CanDo::SendEvents | CanDo::SendMidiEvent => Supported::No,
+            // the host's bypass is taken in front of `vst`, see `properties`, and
+            // crossfades like the bypass parameter
+            CanDo::Bypass => Supported::Yes,
+            _ => Supported::Maybe,
+        }
+    }
+
+    fn get_parameter_object(&mut self) -> Arc<dyn PluginParameters> {
+        Arc::clone(&self.params) as _
+    }
+
+    fn get_input_info(&self, input: i32) -> ChannelInfo {
+        let name = match input as usize {
+            MAX_CHANNELS => "Sidechain L".to_string(),
+            chan if chan > MAX_CHANNELS => "Sidechain R".to_string(),
+            chan => format!("Input {}", chan + 1),
+        };
+        ChannelInfo::new(name, None, true, None)
+    }
+
+    // called before each `process` with the events of the coming block
+    fn process_events(&mut self, events: &Events) {
+        midi::queue_events(events, &mut self.events);
+    }
+
+    fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
+        let _denormals = DenormalGuard::new();
+        let samples = buffer.samples();
+        let (inputs, outputs) = buffer.split();
+        self.process_channels(samples, inputs, outputs);
+    }
+
+    fn process_f64(&mut self, buffer: &mut AudioBuffer<f64>) {
+        let _denormals = DenormalGuard::new();
+        let samples = buffer.samples();
+        let (inputs, outputs) = buffer.split();
+        self.process_channels(samples, inputs, outputs);
+    }
 }
 
-impl<S: Scale> ScaledParameter<S> {
-    fn new(init: f32) -> Self {
-        Self {
-            inner: AtomicFloat::new(S::to_norm(init)),
-            _scale: PhantomData,
+impl SnapshotRepeatPlugin {
+    /// A sentence or two on what the parameter at `index` does, for tooltips and help texts.
+    pub fn parameter_description(index: i32) -> Option<&'static str> {
+        match index {
+            124..=139 => Some(GATE_STEP_DESCRIPTION),
+            0..=123 => Some(PARAMETER_DESCRIPTIONS[index as usize]),
+            _ => None,
+        }
+    }
+
+    /// Handle for loading audio files as the snapshot, usable from any thread.
+    pub fn snapshot_import(&self) -> Arc<SnapshotImport> {
+        Arc::clone(&self.params.import)
+    }
+
+    /// Handle for saving the snapshot history to a bank file and loading it back, usable from any thread.
+    pub fn snapshot_bank(&self) -> Arc<SnapshotBank> {
+        Arc::clone(&self.params.bank)
+    }
+
+    /// Groove template applied to the synced periods, stored with the plugin state.
+    pub fn groove(&self) -> Arc<GrooveTemplate> {
+        Arc::clone(&self.params.groove)
+    }
+
+    /// Mappings of MIDI controllers to parameters, stored with the plugin state.
+    pub fn midi_learn(&self) -> Arc<MidiLearn> {
+        Arc::clone(&self.params.midi_learn)
+    }
+
+    /// Values published by the audio thread for drawing the plugin state, like
+    /// the waveform of the playing snapshot with the playhead.
+    pub fn visualization(&self) -> Arc<Visualization> {
+        Arc::clone(&self.visualization)
+    }
+
+    /// Analysis of the snapshots as they get captured, the front channel stands in for all of them.
+    pub fn analysis(&self) -> Arc<AnalysisPipeline> {
+        Arc::clone(&self.analysis)
+    }
+
+    /// Queues a trigger `time` samples into the next processed block, returns
+    /// false if too many triggers are waiting and this one was dropped.
+    pub fn trigger(&mut self, time: usize, trigger: Trigger) -> bool {
+        self.events.push(time, trigger)
+    }
+
+    // freeze and clear parameter changes become triggers at `time`
+    fn queue_parameter_triggers(&mut self, time: usize) {
+        let freeze_param = self.params.freeze.get() > 0.5;
+        if freeze_param != self.freeze_param {
+            self.freeze_param = freeze_param;
+            self.events.push(time, Trigger::Freeze(freeze_param));
+        }
+        let clear_param = self.params.clear.get() > 0.5;
+        if clear_param && !self.clear_param {
+            self.events.push(time, Trigger::Clear);
+        }
+        self.clear_param = clear_param;
+    }
+
+    fn apply_trigger(&mut self, trigger: Trigger) {
+        match trigger {
+            Trigger::Capture => self.channel_states.iter_mut().for_each(ChannelState::restart_cycle),
+            Trigger::Swap => self.channel_states.iter_mut().for_each(ChannelState::end_cycle),
+            Trigger::Freeze(frozen) => self.frozen = frozen,
+            Trigger::Clear => {
+                self.channel_states.iter_mut().for_each(ChannelState::clear);
+                // whatever broke the output is gone with the snapshots
+                self.anomalies = 0;
+                self.params.safe_mode.store(false, Ordering::Release);
+            }
+            Trigger::NoteOn(note) => if self.params.midi_trigger.get() > 0.5 {
+                let (rate, offset) = if self.params.latch_random.get() > 0.5 {
+                    self.latched_note = Some(note);
+                    let semitones = (self.random.next_f32() * 2.0 - 1.0) * LATCH_RATE_SEMITONES;
+                    let slice = (self.random.next_f32() * LATCH_SLICES).floor();
+                    (semitones_to_ratio(semitones), slice / LATCH_SLICES)
+                } else {
+                    self.latched_note = None;
+                    (1.0, 0.0)
+                };
+                for chan_state in &mut self.channel_states {
+                    chan_state.set_variation(rate, offset);
+                    chan_state.end_cycle();
+                }
+            },
+            Trigger::NoteOff(note) => if self.latched_note == Some(note) {
+                self.latched_note = None;
+                self.channel_states.iter_mut().for_each(|chan_state| chan_state.set_variation(1.0, 0.0));
+            },
+            Trigger::ControlChange(cc, value) => {
+                if cc == self.params.mod_controller.get() as u8 {
+                    self.modulation.set_controller(value);
+                }
+                if let Some((index, value)) = self.params.midi_learn.control_change(cc, value) {
+                    self.params.set_normalized(index, value);
+                    // keeps the host's automation and display in step
+                    if let Some(host) = &self.host {
+                        host.automate(index, value);
+                    }
+                }
+            }
+            Trigger::Parameter(index, value) => self.params.set_normalized(index, value),
+            Trigger::Scene(scene) => {
+                self.params.set_normalized(SCENE_PARAM, scene.min(1) as f32);
+                self.params.scenes.update(&self.params);
+            }
+        }
+    }
+
+    // the delaying stages are set up for the settings the plugin is turned on with,
+    // `vst` only reads the latency from `get_info` when the plugin is created, later
+    // changes go to the host directly, only from `resume` since hosts pick up a new
+    // delay while the plugin is off and the audio thread mustn't call back into them
+    fn update_latency(&mut self) {
+        let reported = self.dry_delay.delay();
+        let latency = dsp::latency(&self.params, self.sample_rate);
+        self.limiter.allocate(MAX_CHANNELS, Limiter::lookahead(&self.params, self.sample_rate));
+        self.dry_delay.allocate(MAX_CHANNELS, latency);
+        if latency == reported {
+            return
+        }
+        if let Some(host) = &self.host {
+            let effect = host.raw_effect();
+            if let (Some(callback), false) = (host.raw_callback(), effect.is_null()) {
+                // the host reads the new value from the plugin's `AEffect` once told the setup changed
+                unsafe { (*effect).initialDelay = latency as i32 };
+                callback(effect, OpCode::IOChanged as i32, 0, 0, ptr::null_mut(), 0.0);
+            }
+        }
+    }
+
+    // shared by both precisions
+    fn process_channels<T: Sample>(&mut self, samples: usize, inputs: Inputs<T>, mut outputs: Outputs<T>) {
+        let input_count = usize::min(inputs.len(), outputs.len());
+        // any inputs past the main channels are the sidechain
+        let sidechain_start = Some(input_count)
+            .filter(|start| inputs.len() >= start + SIDECHAIN_CHANNELS);
+        // every output is played, when the host passes fewer inputs they repeat
+        // across the outputs, a mono input on all of them
+        let channel_count = if input_count > 0 { usize::min(outputs.len(), MAX_CHANNELS) } else { 0 };
+        let source = |chan: usize| chan % input_count.max(1);
+
+        if self.channel_count != channel_count {
+            self.channel_count = channel_count;
+            self.params.import.set_channel_count(channel_count);
+            self.params.bank.set_channel_count(channel_count);
+            // the new channels decide in lockstep with the others from here on
+            self.seed = None;
+        }
+
+        let transport = self.transport();
+        self.params.display_ppq_per_sample.set(transport.ppq_pos.map_or(0.0, |_| transport.ppq_per_sample as f32));
+        self.params.display_ppq_per_bar.set(transport.ppq_per_bar.unwrap_or(4.0) as f32);
+        self.follow_transport(&transport);
+
+        // scene switches that didn't come through the host, e.g. from a mapped controller
+        self.params.scenes.update(&self.params);
+        // the host shows and records the values of the scene switched to
+        if self.params.scenes.take_changed() {
+            if let Some(host) = &self.host {
+                host.update_display();
+            }
+        }
+
+        let seed = self.params.seed.get() as u32;
+        if self.seed != Some(seed) {
+            self.seed = Some(seed);
+            self.random = Random::new(seed);
+            self.modulation.reseed(seed);
+            self.channel_states.iter_mut().for_each(|chan_state| chan_state.reseed(seed));
+        }
+
+        // parameter changes become triggers at the start of the block
+        self.queue_parameter_triggers(0);
+
+        self.hold_back_inputs(&inputs, channel_count, source, samples);
+
+        if self.params.safe_mode.load(Ordering::Acquire) {
+            // triggers are still applied so a clear can get out of it
+            while let Some(trigger) = self.events.pop_due(samples) {
+                self.apply_trigger(trigger);
+            }
+            self.events.advance(samples);
+            for chan in 0..channel_count {
+                copy_input(&self.held_inputs[chan], outputs.get_mut(chan));
+            }
+            self.limiter.clear();
+            return
+        }
+
+        // held while the host is stopped so there's no stale snapshot looping on and on,
+        // once faded over triggers still land so a clear or freeze isn't lost
+        let held = self.held(&transport);
+        if let Some(when_stopped) = held {
+            self.hold_mode = when_stopped;
+        }
+        if held.is_some() && self.hold_mix >= 1.0 {
+            while let Some(trigger) = self.events.pop_due(samples) {
+                self.apply_trigger(trigger);
+            }
+            self.events.advance(samples);
+            for chan in 0..channel_count {
+                match self.hold_mode {
+                    WhenStopped::Dry => copy_input(&self.held_inputs[chan], outputs.get_mut(chan)),
+                    _ => outputs.get_mut(chan).fill(T::default()),
+                }
+            }
+            self.limiter.clear();
+            self.crossfade_bypass(&mut outputs, channel_count);
+            return
+        }
+
+        // imports and banks are prepared on their workers, they're only swapped in here
+        let params = &*self.params;
+        let channel_states = &mut self.channel_states;
+        params.import.take_pending(|snapshots| {
+            let period = period_samples(params, &transport);
+            channel_states.iter_mut()
+                .zip(snapshots)
+                .for_each(|(chan_state, snapshot)| chan_state.load_snapshot(snapshot, period));
+        });
+        // a chunk at a time while a save needs the history
+        self.params.bank.copy_history(&self.channel_states[..channel_count]);
+        let channel_states = &mut self.channel_states;
+        params.bank.take_pending(|bank| {
+            let period = period_samples(params, &transport);
+            channel_states.iter_mut()
+                .zip(bank)
+                .for_each(|(chan_state, slots)| chan_state.load_history(slots, period));
+        });
+
+        let sidechain_mode = SidechainMode::from_param(self.params.sidechain.get());
+        let capture_source = |chan: usize| match (sidechain_mode, sidechain_start) {
+            (SidechainMode::Off, _) | (_, None) => source(chan),
+            (_, Some(start)) => start + source(chan) % SIDECHAIN_CHANNELS,
+        };
+        // all channels capture the average of the channels they'd capture otherwise
+        let mono_capture = self.params.mono_capture.get() > 0.5;
+        if mono_capture {
+            let sources = match (sidechain_mode, sidechain_start) {
+                (SidechainMode::Off, _) | (_, None) => 0..input_count,
+                (_, Some(start)) => start..start + SIDECHAIN_CHANNELS,
+            };
+            let gain = 1.0 / sources.len().max(1) as f32;
+            // only reallocates when blocks get longer
+            self.mono_sum.clear();
+            self.mono_sum.resize(samples, 0.0);
+            for chan in sources {
+                for (sum, inp) in self.mono_sum.iter_mut().zip(inputs.get(chan)) {
+                    *sum += inp.to_f32() * gain;
+                }
+            }
+        }
+
+        // the repeats of each channel, the block is split wherever a trigger is due,
+        // and into slices the modulation holds for while a route is active
+        let mut start = 0;
+        loop {
+            while let Some(trigger) = self.events.pop_due(start) {
+                self.apply_trigger(trigger);
+                // queued parameter changes act like the ones made before the block
+                if let Trigger::Parameter(..) | Trigger::Scene(_) = trigger {
+                    self.queue_parameter_triggers(start);
+                }
+            }
+            let slice_end = if modulation::active(&self.params.routes()) { start + modulation::SLICE } else { samples };
+            let end = self.events.next_time().map_or(samples, |time| usize::min(time, samples)).min(slice_end);
+            self.modulate(&inputs, input_count, start..end);
+            let settings = ChannelSettings::new(&self.params, self.sample_rate, &self.channel_states[..channel_count]);
+
+            let params = &*self.params;
+            let frozen = self.frozen;
+            let transport = transport.advanced(start);
+            for (chan, chan_state) in self.channel_states[..channel_count].iter_mut().enumerate() {
+                chan_state.apply(&settings, frozen, chan, channel_count);
+                let out = &mut outputs.get_mut(chan)[start..end];
+                if mono_capture {
+                    process_channel(params, &transport, chan_state, &self.mono_sum[start..end], out);
+                } else {
+                    process_channel(params, &transport, chan_state, &inputs.get(capture_source(chan))[start..end], out);
+                }
+            }
+
+            if end >= samples {
+                break
+            }
+            start = end;
+        }
+        self.events.advance(samples);
+        self.check_output(&mut outputs, channel_count);
+
+        // whatever is left in the snapshot when the transport starts or jumps is likely stale
+        if self.start_gain < 1.0 {
+            let step = 1000.0 / (self.params.start_fade.get() * self.sample_rate);
+            let mut gain = self.start_gain;
+            for chan in 0..channel_count {
+                gain = self.start_gain;
+                for out in outputs.get_mut(chan) {
+                    gain = ramp(gain, 1.0, step);
+                    *out = out.scale(gain);
+                }
+            }
+            self.start_gain = gain;
         }
+
+        for (chan, chan_state) in self.channel_states[..channel_count].iter_mut().enumerate() {
+            if mem::take(&mut chan_state.new_period) && chan == 0 {
+                self.modulation.next_period();
+            }
+            if mem::take(&mut chan_state.fresh_snapshot) && chan == 0 {
+                self.analysis.submit(chan_state.playing_snapshot(), 1.0 / HEADROOM);
+                overview_levels(chan_state.playing_snapshot(), 1.0 / HEADROOM, &mut self.overview);
+            }
+        }
+        if let Some(chan_state) = self.channel_states.first() {
+            self.visualization.publish_snapshot(&self.overview, chan_state.playing_snapshot().len(), chan_state.playhead());
+        }
+
+        let params = &*self.params;
+        let feedback_gain = self.channel_states[..channel_count].iter()
+            .map(|chan_state| chan_state.feedback_gain)
+            .fold(1.0, f32::min);
+        params.feedback_limit.set(feedback_gain);
+
+        self.expected_ppq_pos = transport.advanced(samples).ppq_pos;
+        self.manual_ppq_pos = self.expected_ppq_pos.unwrap_or(0.0);
+
+        // stereo effects on the front pair of the repeats
+        if channel_count >= 2 {
+            self.rotate(outputs.get_mut(0), outputs.get_mut(1));
+        }
+        self.publish_modulation(feedback_gain);
+
+        self.match_levels(&inputs, &mut outputs, channel_count, source, samples);
+
+        // the dry signal, the sidechain mix mode always keeps all of it
+        // moving linearly across the block like the gains, to where the last slice modulated it
+        let mix = self.params.mix.modulated();
+        let start_mix = self.last_mix.replace(mix).unwrap_or(mix);
+        let mix_step = (mix - start_mix) / samples.max(1) as f32;
+        let keep_dry = sidechain_mode == SidechainMode::Mix;
+        if keep_dry || start_mix < 1.0 || mix < 1.0 {
+            self.follow_repeats(&outputs, channel_count, samples);
+            for chan in 0..channel_count {
+                inputs.get(source(chan)).iter()
+                    .zip(outputs.get_mut(chan))
+                    .zip(&self.duck_gains)
+                    .enumerate()
+                    .for_each(|(i, ((inp, out), duck))| {
+                        let mix = start_mix + mix_step * (i + 1) as f32;
+                        let dry_gain = if keep_dry { 1.0 } else { 1.0 - mix };
+                        *out = out.scale(mix) + inp.scale(dry_gain * duck)
+                    });
+            }
+        }
+
+        // periods that aren't repeated pass the dry input through, the front channel decides for all of them
+        let skipped = self.channel_states.first().map_or(false, |chan_state| chan_state.skipped);
+        let skip = if skipped { 1.0 } else { 0.0 };
+        let curve = FadeCurve::from_param(self.params.fade_curve.get());
+        let fade = Fade { step: 1000.0 / (ENABLE_FADE_MS * self.sample_rate), curve };
+        let dry = |chan: usize| inputs.get(source(chan));
+        self.skip_mix = crossfade_to_input(dry, &mut outputs, channel_count, self.skip_mix, skip, fade);
+
+        // holding and bypass stay untouched by the clipper, from here on the input
+        // is held back as far as the output
+        self.clip_output(&mut outputs, channel_count, samples);
+
+        // going into and out of holding fades like bypass, the repeats run on until it's done
+        let hold = if held.is_some() { 1.0 } else { 0.0 };
+        let fade = Fade { step: 1000.0 / (BYPASS_FADE_MS * self.sample_rate), curve };
+        let held_inputs = &self.held_inputs;
+        let dry = |chan: usize| held_inputs[chan].as_slice();
+        self.hold_mix = match self.hold_mode {
+            WhenStopped::Dry => crossfade_to_input(dry, &mut outputs, channel_count, self.hold_mix, hold, fade),
+            _ => fade_to_silence(&mut outputs, channel_count, self.hold_mix, hold, fade),
+        };
+
+        self.crossfade_bypass(&mut outputs, channel_count);
+    }
+
+    // the input the output is crossfaded with past the limiter, held back as far as
+    // the output is so the two stay lined up
+    fn hold_back_inputs<T: Sample>(
+        &mut self,
+        inputs: &Inputs<T>,
+        channel_count: usize,
+        source: impl Fn(usize) -> usize,
+        samples: usize,
+    ) {
+        for held in &mut self.held_inputs[..channel_count] {
+            if held.len() < samples {
+                held.resize(samples, 0.0);
+            }
+        }
+        for i in 0..samples {
+            for (chan, held) in self.held_inputs[..channel_count].iter_mut().enumerate() {
+                held[i] = self.dry_delay.process(chan, inputs.get(source(chan))[i].to_f64());
+            }
+            self.dry_delay.advance();
+        }
+    }
+
+    // everything keeps running while bypassed so there's nothing stale to hear when coming back
+    fn crossfade_bypass<T: Sample>(&mut self, outputs: &mut Outputs<T>, channel_count: usize) {
+        let bypassed = self.params.bypass.get() > 0.5 || self.params.host_bypass.load(Ordering::Relaxed);
+        let bypass = if bypassed { 1.0 } else { 0.0 };
+        let curve = FadeCurve::from_param(self.params.fade_curve.get());
+        let fade = Fade { step: 1000.0 / (BYPASS_FADE_MS * self.sample_rate), curve };
+        let held_inputs = &self.held_inputs;
+        let dry = |chan: usize| held_inputs[chan].as_slice();
+        self.bypass_mix = crossfade_to_input(dry, outputs, channel_count, self.bypass_mix, bypass, fade);
     }
 
-    fn get_raw(&self) -> f32 {
-        self.inner.get()
+    // NaNs or infinities in the repeats drop the snapshots and silence the block,
+    // if it keeps happening something is wrong beyond the snapshot and only the
+    // input is passed through from then on
+    fn check_output<T: Sample>(&mut self, outputs: &mut Outputs<T>, channel_count: usize) {
+        let broken = (0..channel_count)
+            .any(|chan| outputs.get(chan).iter().any(|sample| !sample.to_f32().is_finite()));
+        if !broken {
+            return
+        }
+        for chan in 0..channel_count {
+            outputs.get_mut(chan).iter_mut().for_each(|sample| *sample = T::from_f32(0.0));
+        }
+        self.channel_states.iter_mut().for_each(ChannelState::clear);
+        self.anomalies += 1;
+        if self.anomalies >= SAFE_MODE_ANOMALIES {
+            self.params.safe_mode.store(true, Ordering::Release);
+        }
     }
 
-    fn set_raw(&self, val: f32) {
-        self.inner.set(val)
+    // brings the repeats to the level of the input, both averaged over
+    // `LEVEL_MATCH_MS`, the gain holds while the repeats are silent
+    fn match_levels<T: Sample>(
+        &mut self,
+        inputs: &Inputs<T>,
+        outputs: &mut Outputs<T>,
+        channel_count: usize,
+        source: impl Fn(usize) -> usize,
+        samples: usize,
+    ) {
+        let enabled = self.params.auto_gain.get() > 0.5;
+        let coefficient = 1.0 - (-1000.0 / (LEVEL_MATCH_MS * self.sample_rate)).exp();
+        let max_gain = db_to_gain(MAX_MAKEUP_DB);
+        for i in 0..samples {
+            let (input_power, wet_power) = (0..channel_count).fold((0.0, 0.0), |(dry, wet), chan| {
+                let (inp, out) = (inputs.get(source(chan))[i].to_f32(), outputs.get(chan)[i].to_f32());
+                (dry + inp * inp, wet + out * out)
+            });
+            self.input_power += (input_power - self.input_power) * coefficient;
+            self.wet_power += (wet_power - self.wet_power) * coefficient;
+            let target = if !enabled {
+                1.0
+            } else if self.wet_power > SILENCE_LEVEL * SILENCE_LEVEL {
+                (self.input_power / self.wet_power).sqrt().clamp(1.0 / max_gain, max_gain)
+            } else {
+                self.match_gain
+            };
+            self.match_gain += (target - self.match_gain) * coefficient;
+            if !enabled && (self.match_gain - 1.0).abs() < 1e-4 {
+                self.match_gain = 1.0;
+            }
+            if enabled || self.match_gain != 1.0 {
+                for chan in 0..channel_count {
+                    let out = &mut outputs.get_mut(chan)[i];
+                    *out = out.scale(self.match_gain);
+                }
+            }
+        }
+    }
+
+    // keeps the output under the ceiling, the output is held back by the limiter's
+    // lookahead whatever the mode so the latency stays what the host was told
+    fn clip_output<T: Sample>(&mut self, outputs: &mut Outputs<T>, channel_count: usize, samples: usize) {
+        let ceiling = db_to_gain(self.params.clip_ceiling.get());
+        let mode = OutputClip::from_param(self.params.output_clip.get());
+        let limiting = mode == OutputClip::Limiter;
+        if !limiting {
+            self.limiter.release();
+        }
+        if limiting || self.limiter.lookahead_len() > 0 {
+            let release = 1.0 - (-1000.0 / (LIMITER_RELEASE_MS * self.sample_rate)).exp();
+            for i in 0..samples {
+                let gain = if limiting {
+                    let peak = (0..channel_count)
+                        .map(|chan| outputs.get(chan)[i].to_f32().abs())
+                        .fold(0.0, f32::max);
+                    self.limiter.next_gain(peak, ceiling, release)
+                } else {
+                    1.0
+                };
+                for chan in 0..channel_count {
+                    let sample = &mut outputs.get_mut(chan)[i];
+                    *sample = self.limiter.hold_back(chan, *sample).scale(gain);
+                }
+                self.limiter.advance();
+            }
+        }
+        if mode == OutputClip::SoftClip {
+            for chan in 0..channel_count {
+                for sample in outputs.get_mut(chan) {
+                    // under the knee the sample passes at its own precision
+                    let clipped = soft_clip(sample.to_f32(), ceiling);
+                    if clipped != sample.to_f32() {
+                        *sample = T::from_f32(clipped);
+                    }
+                }
+            }
+        }
+    }
+
+    // fills `duck_gains` for the block from an envelope following the loudest channel of the repeats
+    fn follow_repeats<T: Sample>(&mut self, outputs: &Outputs<T>, channel_count: usize, samples: usize) {
+        let depth = self.params.duck.get();
+        let attack = 1.0 - (-1000.0 / (DUCK_ATTACK_MS * self.sample_rate)).exp();
+        let release = 1.0 - (-1000.0 / (DUCK_RELEASE_MS * self.sample_rate)).exp();
+
+        self.duck_gains.resize(samples, 1.0);
+        let mut envelope = self.duck_envelope;
+        for (i, gain) in self.duck_gains.iter_mut().enumerate() {
+            let level = (0..channel_count)
+                .map(|chan| outputs.get(chan)[i].to_f32().abs())
+                .fold(0.0, f32::max);
+            let coefficient = if level > envelope { attack } else { release };
+            envelope += (level - envelope) * coefficient;
+            *gain = 1.0 - depth * (envelope / DUCK_FULL_LEVEL).min(1.0);
+        }
+        self.duck_envelope = envelope;
     }
 
-    fn get(&self) -> f32 {
-        S::to_scaled(self.get_raw())
+    // works out the modulation for `range` of the block, then moves the LFOs past it
+    fn modulate<T: Sample>(&mut self, inputs: &Inputs<T>, input_count: usize, range: Range<usize>) {
+        let levels = range.clone().map(|i| {
+            (0..input_count).map(|chan| inputs.get(chan)[i].to_f32().abs()).fold(0.0, f32::max)
+        });
+        self.modulation.follow(levels, self.sample_rate);
+        self.params.set_modulation(self.modulation.offsets(&self.params.routes()));
+        let lfo_rates = [self.params.lfo_1_rate.get(), self.params.lfo_2_rate.get()];
+        self.modulation.advance(lfo_rates, range.len(), self.sample_rate);
+    }
+
+    // the effective values of the parameters that move on their own, the front
+    // channel stands in for all of them since they're modulated in lockstep
+    fn publish_modulation(&self, feedback_gain: f32) {
+        let chan_state = match self.channel_states.first() {
+            Some(chan_state) => chan_state,
+            None => return,
+        };
+        let params = &*self.params;
+        let visualization = &*self.visualization;
+
+        let rate = params.playback_rate.modulated() * semitones_to_ratio(chan_state.transpose);
+        visualization.set_modulation(PLAYBACK_RATE_PARAM, params.playback_rate.normalize(rate));
+        let swing = params.rotate_depth.modulated() * self.modulation.rotation().abs();
+        visualization.set_modulation(ROTATE_DEPTH_PARAM, params.rotate_depth.normalize(swing));
+        let transpose = params.freeze_transpose.normalize(chan_state.transpose);
+        visualization.set_modulation(FREEZE_TRANSPOSE_PARAM, transpose);
+        let feedback = soft_feedback(params.feedback.get()) * feedback_gain;
+        visualization.set_modulation(FEEDBACK_PARAM, params.feedback.normalize(feedback));
+    }
+
+    // what to output instead of the repeats while held for the stopped transport
+    fn held(&self, transport: &Transport) -> Option<WhenStopped> {
+        let when_stopped = WhenStopped::from_param(self.params.when_stopped.get());
+        Some(when_stopped).filter(|&mode| transport.stopped && mode != WhenStopped::Run)
+    }
+
+    fn transport(&self) -> Transport {
+        let flags = TimeInfoFlags::TEMPO_VALID | TimeInfoFlags::PPQ_POS_VALID;
+        let request = flags | TimeInfoFlags::TIME_SIG_VALID;
+        let time_info = self.host.as_ref().and_then(|host| host.get_time_info(request.bits()));
+        let mut transport = Transport::default();
+        #[cfg(test)]
+        if let Some(test_transport) = self.test_transport {
+            return test_transport
+        }
+        if let Some(info) = time_info {
+            let info_flags = TimeInfoFlags::from_bits_truncate(info.flags);
+            transport.playing = info_flags.contains(TimeInfoFlags::TRANSPORT_PLAYING);
+            transport.stopped = !transport.playing;
+            // some hosts report a tempo of zero rather than none
+            if info_flags.contains(flags) && info.tempo > 0.0 {
+                transport.ppq_pos = Some(info.ppq_pos);
+                transport.ppq_per_sample = info.tempo / 60.0 / self.sample_rate as f64;
+            }
+            if info_flags.contains(TimeInfoFlags::TIME_SIG_VALID) && info.time_sig_denominator > 0 {
+                let ppq_per_bar = info.time_sig_numerator as f64 * 4.0 / info.time_sig_denominator as f64;
+                transport.ppq_per_bar = Some(ppq_per_bar);
+            }
+        }
+        let manual_bpm = self.params.manual_bpm.get();
+        if transport.ppq_pos.is_none() && manual_bpm > 0.0 {
+            transport.ppq_pos = Some(self.manual_ppq_pos);
+            transport.ppq_per_sample = manual_bpm as f64 / 60.0 / self.sample_rate as f64;
+        }
+        transport
+    }
+
+    // detects jumps of the playing transport (loops, locates) and reacts according to the loop mode
+    fn follow_transport(&mut self, transport: &Transport) {
+        if transport.playing && !self.playing {
+            self.start_gain = 0.0;
+            // the random decisions start over so each take comes out the same
+            self.seed = None;
+            // everything else back to how a new instance starts
+            if self.params.reset_on_start.get() > 0.5 {
+                self.channel_states.iter_mut().for_each(ChannelState::reset);
+                self.modulation = Modulation::default();
+            }
+        }
+        self.playing = transport.playing;
+
+        let ppq_pos = match (transport.ppq_pos, self.expected_ppq_pos) {
+            (Some(ppq_pos), Some(expected)) if transport.playing => {
+                let tolerance = TRANSPORT_JUMP_TOLERANCE * transport.ppq_per_sample;
+                if (ppq_pos - expected).abs() <= tolerance {
+                    return
+                }
+                ppq_pos
+            }
+            _ => return,
+        };
+        self.start_gain = 0.0;
+
+        match LoopMode::from_param(self.params.loop_mode.get()) {
+            LoopMode::FreeRun => {}
+            LoopMode::Reset => self.channel_states.iter_mut().for_each(ChannelState::restart_cycle),
+            LoopMode::Align => {
+                let pos = ppq_pos / transport.ppq_per_sample;
+                let playback_rate = self.params.playback_rate.modulated();
+                self.channel_states.iter_mut().for_each(|chan_state| chan_state.align_cycle(pos, playback_rate));
+            }
+        }
+    }
+
+    // processing continues after a gap, the envelopes and fades start over and
+    // whatever is left in the snapshot fades in like after a transport jump
+    fn pick_up(&mut self) {
+        self.playing = false;
+        self.expected_ppq_pos = None;
+        self.start_gain = 0.0;
+        self.last_mix = None;
+        self.duck_envelope = 0.0;
+        self.limiter.clear();
+        self.dry_delay.clear();
+        self.bypass_mix = if self.params.bypass.get() > 0.5 { 1.0 } else { 0.0 };
+    }
+
+    // rotates the stereo field with an LFO, the angle is kept within 45 degrees in
+    // both directions so the mono sum of correlated material never cancels out
+    fn rotate<T: Sample>(&mut self, left: &mut [T], right: &mut [T]) {
+        let depth = self.params.rotate_depth.modulated();
+        let increment = self.params.rotate_rate.get() / self.sample_rate;
+        if depth <= 0.0 {
+            // keep the LFO running so it doesn't restart from the same spot
+            self.modulation.next_rotation(increment * left.len() as f32);
+            return
+        }
+
+        for (left, right) in left.iter_mut().zip(right) {
+            let angle = depth * FRAC_PI_4 * self.modulation.next_rotation(increment);
+            let (sin, cos) = angle.sin_cos();
+            let (l, r) = (*left, *right);
+            *left = l.scale(cos) + r.scale(-sin);
+            *right = l.scale(sin) + r.scale(cos);
+        }
     }
 }
 
-struct Params {
-    /// period between recapturing
-    period: ScaledParameter<LinScale<1.0, { 44_100.0 * 10.0 }>>,
-    /// size of the captured buffer
-    capture_len: ScaledParameter<LinScale<1.0, { 44_100.0 * 10.0 }>>,
-    /// playback rate
-    playback_rate: ScaledParameter<LinScale<0.01, 100.0>>,
+// how a crossfade moves, `step` of the way each sample along `curve`
+#[derive(Clone, Copy)]
+struct Fade {
+    step: f32,
+    curve: FadeCurve,
 }
 
-impl Default for Params {
-    fn default() -> Params {
-        Params {
-            period: ScaledParameter::new(44_100.0),
-            capture_len: ScaledParameter::new(44_100.0),
-            playback_rate: ScaledParameter::new(1.0),
+// fades the output from `mix` towards `target` parts of the input, each channel
+// its `dry` one, returns the mix reached
+fn crossfade_to_input<'a, T: Sample, D: Sample + 'a>(
+    dry: impl Fn(usize) -> &'a [D],
+    outputs: &mut Outputs<T>,
+    channel_count: usize,
+    mix: f32,
+    target: f32,
+    fade: Fade,
+) -> f32 {
+    if target <= 0.0 && mix <= 0.0 {
+        return mix
+    }
+    let mut end_mix = mix;
+    for chan in 0..channel_count {
+        end_mix = mix;
+        for (inp, out) in dry(chan).iter().zip(outputs.get_mut(chan)) {
+            end_mix = ramp(end_mix, target, fade.step);
+            let inp = T::from_f64(inp.to_f64());
+            *out = out.scale(fade.curve.gain(1.0 - end_mix)) + inp.scale(fade.curve.gain(end_mix));
         }
     }
+    end_mix
 }
 
-struct ChannelState {
-    // buffer used for interpolation
-    current_buffer: Box<[f32]>,
-    // normalized (0.0 .. 1.0) offset into the current buffer
-    current_offset_norm: f32,
-
-    // how many samples used the current buffer
-    current_offset_total: usize,
-    // how many samples should the current buffer be used for in total
-    current_period: usize,
-
-    // the buffer to be used next (if any)
-    next_buffer: Box<[f32]>,
-    // how many of the samples in the next buffer have been written
-    next_buffer_len: usize,
+// the held back input of a channel into its output
+fn copy_input<T: Sample>(held: &[f64], output: &mut [T]) {
+    output.iter_mut().zip(held).for_each(|(out, &inp)| *out = T::from_f64(inp));
 }
 
-impl Default for ChannelState {
-    fn default() -> Self {
-        Self {
-            current_buffer: Box::new([]),
-            current_offset_norm: 0.0,
-            current_offset_total: 0,
-            current_period: 0,
-            next_buffer: Box::new([]),
-            next_buffer_len: 0,
+// fades the output from `mix` towards `target` parts of silence, returns the mix reached
+fn fade_to_silence<T: Sample>(outputs: &mut Outputs<T>, channel_count: usize, mix: f32, target: f32, fade: Fade) -> f32 {
+    if target <= 0.0 && mix <= 0.0 {
+        return mix
+    }
+    let mut end_mix = mix;
+    for chan in 0..channel_count {
+        end_mix = mix;
+        for out in outputs.get_mut(chan) {
+            end_mix = ramp(end_mix, target, fade.step);
+            *out = out.scale(fade.curve.gain(1.0 - end_mix));
         }
     }
+    end_mix
 }
 
-impl Plugin for SnapshotRepeatPlugin {
-    fn get_info(&self) -> Info {
-        Info {
-            name: "Snapshot Repeat".to_string(),
-            vendor: "ametisf".to_string(),
-            unique_id: 141375252,
-            version: 1,
-            inputs: CHANNELS as i32,
-            outputs: CHANNELS as i32,
-            parameters: 3,
-            category: Category::Effect,
-            ..Default::default()
+impl Params {
+    // whether the period (0) or the tremolo rate (121) stands for one of `NOTE_VALUES`
+    fn picks_note_value(&self, index: i32) -> bool {
+        match index {
+            0 => self.sync.get() > 0.5 && self.note_values.get() > 0.5,
+            121 => self.tremolo_sync.get() > 0.5,
+            _ => false,
         }
     }
 
-    fn get_parameter_object(&mut self) -> Arc<dyn PluginParameters> {
-        Arc::clone(&self.params) as _
+    // the state chunk is plain text with a `key=value` pair on each line, where
+    // the keys are parameter IDs or the names of other stored settings
+    fn serialize(&self) -> Vec<u8> {
+        let mut state = String::new();
+        for index in (0..PARAMETERS).filter(|&index| self.can_be_automated(index)) {
+            writeln!(state, "{}={}", PARAMETER_IDS[index as usize], self.get_parameter(index)).unwrap();
+        }
+        if let Some(path) = self.import.path() {
+            writeln!(state, "snapshot_file={}", path.display()).unwrap();
+        }
+        if let Some(path) = self.bank.path() {
+            writeln!(state, "snapshot_bank={}", path.display()).unwrap();
+        }
+        let groove = self.groove.offsets();
+        if !groove.is_empty() {
+            let percent = groove.iter().map(|offset| (offset * 100.0).to_string()).collect::<Vec<_>>();
+            writeln!(state, "groove={}", percent.join(",")).unwrap();
+        }
+        let mappings = self.midi_learn.mappings().into_iter()
+            .filter_map(|(cc, index)| PARAMETER_IDS.get(index as usize).map(|id| format!("{}:{}", cc, id)))
+            .collect::<Vec<_>>();
+        if !mappings.is_empty() {
+            writeln!(state, "midi_cc={}", mappings.join(",")).unwrap();
+        }
+        if let Some(other) = self.scenes.lock().other() {
+            let values = (0..PARAMETERS).filter(|&index| in_scene(self, index))
+                .map(|index| format!("{}:{}", PARAMETER_IDS[index as usize], other[index as usize]))
+                .collect::<Vec<_>>();
+            writeln!(state, "other_scene={}", values.join(",")).unwrap();
+        }
+        state.into_bytes()
     }
 
-    fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
-        debug_assert!(
-            buffer.input_count() == CHANNELS &&
-            buffer.output_count() == CHANNELS
-        );
+    // the parameters in milliseconds
+    fn time_parameter(&self, index: i32) -> Option<&ScaledParameter> {
+        match index {
+            38 => Some(&self.edge_fade),
+            61 => Some(&self.tail_crossfade),
+            _ => None,
+        }
+    }
 
-        let params = &*self.params;
-        buffer.zip()
-            .zip(&mut self.channel_states)
-            .for_each(|((input_buffer, output_buffer), chan_state)| {
-                process_channel(params, chan_state, input_buffer, output_buffer)
-            });
+    // lengths in samples as musical values while there's a tempo, in seconds otherwise
+    fn format_length(&self, samples: f32) -> String {
+        let ppq_per_sample = self.display_ppq_per_sample.get() as f64;
+        if ppq_per_sample > 0.0 {
+            format_beats(samples as f64 * ppq_per_sample, self.display_ppq_per_bar.get() as f64)
+        } else {
+            format!("{:.3} s", samples / self.sample_rate.get())
+        }
+    }
+
+    // whether loading a preset leaves the parameter alone
+    fn locked(&self, index: i32) -> bool {
+        match index {
+            // the locks belong to the instance, not to the presets
+            LOCK_SYNC_PARAM | LOCK_LEVELS_PARAM => true,
+            _ if SYNC_GROUP.contains(&index) => self.lock_sync.get() > 0.5,
+            _ if LEVELS_GROUP.contains(&index) => self.lock_levels.get() > 0.5,
+            _ => false,
+        }
+    }
+
+    // presets leave the locked groups alone, the whole state (as saved with the
+    // project) restores everything
+    fn deserialize(&self, data: &[u8], preset: bool) {
+        let keep_groove = preset && self.lock_sync.get() > 0.5;
+        // states without a groove are straight
+        if !keep_groove {
+            self.groove.set(&[]);
+        }
+        // the controller mappings belong to the setup, presets leave them alone
+        if !preset {
+            self.midi_learn.clear();
+        }
+        // no scene switches halfway through, states without the other scene have the same in both
+        let mut scenes = self.scenes.lock();
+        let mut other_scene = Vec::new();
+        for line in String::from_utf8_lossy(data).lines() {
+            let eq = match line.find('=') {
+                Some(eq) => eq,
+                None => continue,
+            };
+            let (key, value) = (&line[..eq], &line[eq + 1..]);
+            match key {
+                "snapshot_file" => {
+                    self.import.import(value.into());
+                }
+                "snapshot_bank" => {
+                    self.bank.load(value.into());
+                }
+                "midi_cc" => if !preset {
+                    for (cc, id) in value.split(',').filter_map(|mapping| mapping.split_once(':')) {
+                        if let (Ok(cc), Some(index)) = (cc.parse(), PARAMETER_IDS.iter().position(|&known| known == id)) {
+                            self.midi_learn.map(cc, index as i32);
+                        }
+                    }
+                },
+                "groove" => if let (false, Ok(offsets)) = (keep_groove, GrooveTemplate::parse(&value.replace(',', "\n"))) {
+                    self.groove.set(&offsets);
+                },
+                "other_scene" => {
+                    for (id, value) in value.split(',').filter_map(|pair| pair.split_once(':')) {
+                        let index = PARAMETER_IDS.iter().position(|&known| known == id);
+                        if let (Some(index), Ok(value)) = (index, value.parse()) {
+                            other_scene.push((index as i32, value));
+                        }
+                    }
+                }
+                // converted to the new scale, the time stays the same
+                _ if LEGACY_IDS.iter().any(|&(id, _, _)| id == key) => {
+                    let &(_, index, high) = LEGACY_IDS.iter().find(|&&(id, _, _)| id == key).unwrap();
+                    if let (Some(param), Ok(value)) = (self.time_parameter(index), value.parse::<f32>()) {
+                        if !(preset && self.locked(index)) {
+                            param.set_raw(param.normalize(value * high));
+                        }
+                    }
+                }
+                _ => {
+                    // states saved before the IDs were introduced use the indices
+                    let index = PARAMETER_IDS.iter()
+                        .position(|&id| id == key)
+                        .map(|index| index as i32)
+                        .or_else(|| key.parse().ok());
+                    if let (Some(index), Ok(value)) = (index, value.parse()) {
+                        if !(preset && self.locked(index)) {
+                            self.set_parameter(index, value);
+                        }
+                    }
+                }
+            }
+        }
+        scenes.loaded(self, &other_scene);
+    }
+}
+
+// bars from one bar up, shorter lengths as the nearest note value down to 1/64
+fn format_beats(quarters: f64, ppq_per_bar: f64) -> String {
+    let bars = quarters / ppq_per_bar;
+    if bars > 0.995 {
+        let plural = if format!("{:.2}", bars) == "1.00" { "" } else { "s" };
+        return format!("{:.2} bar{}", bars, plural)
+    }
+    let whole_notes = quarters / 4.0;
+    for &denominator in &[2, 4, 8, 16, 32, 64] {
+        let numerator = whole_notes * denominator as f64;
+        if numerator >= 0.5 && (numerator - numerator.round()).abs() < 1e-3 {
+            return format!("{}/{}", numerator.round(), denominator)
+        }
     }
+    format!("{:.2} beats", quarters)
 }
 
-// all the actual DSP logic is here
-fn process_channel(
-    params: &Params,
-    state: &mut ChannelState,
-    inp: &[f32],
-    out: &mut [f32],
-) {
-    let period = params.period.get().round() as usize;
-    // dbg!(period);
-    let capture_len = params.capture_len.get().round() as usize;
-    // dbg!(capture_len);
-    let playback_rate = params.playback_rate.get();
-    // dbg!(playback_rate);
-    // eprintln!("");
-
-    // finished one period, swap buffers and update parameters
-    if state.current_offset_total >= state.current_period {
-        state.current_period = period;
-        state.current_offset_total = 0;
-
-        // takes the minimum because we can't manage to capture more than `period` samples
-        let next_buffer_size = usize::min(capture_len, period);
-        state.next_buffer_len = 0;
-
-        state.current_buffer = mem::replace(
-            &mut state.next_buffer,
-            vec![0.0; next_buffer_size].into_boxed_slice(),
-        );
-        state.current_offset_norm = 0.0;
-    }
-    state.current_offset_total += inp.len();
-
-    // if the next buffer is not full write to it from the input
-    if state.next_buffer.len() > state.next_buffer_len {
-        inp.iter().zip(&mut state.next_buffer[state.next_buffer_len..])
-            .for_each(|(inp, out)| *out = *inp);
-        state.next_buffer_len += inp.len();
-    }
-
-    // keep quiet if the buffer is empty
-    if state.current_buffer.len() == 0 {
-        out.fill(0.0);
-        return
-    }
-
-    // use the last recorded buffer as a wavetable, scan at the original speed * playback_rate
-    let mut offset = state.current_offset_norm;
-    let increment = (1.0 / state.current_buffer.len() as f32) * playback_rate;
-    let buffer = &state.current_buffer;
-    for out in out {
-        let idx = offset * (buffer.len() as f32);
-        let low_idx = idx.floor() as usize;
-        let high_idx = (low_idx + 1) % buffer.len();
-        let fract = idx.fract();
-
-        let low = buffer[low_idx];
-        let high = buffer[high_idx];
-
-        *out = low + (high - low) * fract;
-
-        *out = buffer[low_idx];
-        offset = (offset + increment) % 1.0;
-    }
-    state.current_offset_norm = offset;
+// a playback rate as the interval it transposes by, to the nearest cent
+fn format_interval(rate: f32) -> String {
+    let cents = (1200.0 * rate.log2()).round() as i32;
+    let semitones = (cents as f32 / 100.0).round() as i32;
+    match cents - semitones * 100 {
+        0 => format!("{:+} st", semitones),
+        cents => format!("{:+} st, {:+} ct", semitones, cents),
+    }
+}
+
+// *Off* at zero, seconds from one second up
+fn format_ms(ms: f32) -> String {
+    match ms {
+        ms if ms >= 1000.0 => format!("{:.2} s", ms / 1000.0),
+        ms if ms > 0.0 => format!("{:.1} ms", ms),
+        _ => "Off".to_string(),
+    }
+}
+
+// `250 ms` or `1.5 s`, plain numbers are milliseconds
+fn parse_ms(text: &str) -> Option<f32> {
+    let text = text.trim();
+    let (number, scale) = match text.strip_suffix("ms") {
+        Some(number) => (number, 1.0),
+        None => text.strip_suffix('s').map_or((text, 1.0), |number| (number, 1000.0)),
+    };
+    number.trim().parse::<f32>().ok().map(|number| number * scale)
+}
+
+// `1.5x` as a multiplier, anything else as an interval like `+7 st, -12 ct`
+// where either part can be left out and plain numbers are semitones
+fn parse_rate(text: &str) -> Option<f32> {
+    let text = text.trim().to_lowercase();
+    if let Some(multiplier) = text.strip_suffix('x') {
+        return multiplier.trim().parse().ok()
+    }
+    let mut semitones = 0.0;
+    for part in text.split(',').map(str::trim) {
+        let (number, scale) = match part.strip_suffix("ct") {
+            Some(number) => (number, 0.01),
+            None => (part.strip_suffix("st").unwrap_or(part), 1.0),
+        };
+        semitones += number.trim().parse::<f32>().ok()? * scale;
+    }
+    Some(semitones_to_ratio(semitones))
+}
+
+fn format_db(db: f32) -> String {
+    if db == f32::NEG_INFINITY {
+        "-inf dB".to_string()
+    } else {
+        format!("{:+.1} dB", db)
+    }
 }
 
 impl PluginParameters for Params {
     fn get_parameter(&self, index: i32) -> f32 {
-        match index {
-            0 => self.period.get_raw(),
-            1 => self.capture_len.get_raw(),
-            2 => self.playback_rate.get_raw(),
-            _ => 0.0,
-        }
+        self.get_normalized(index)
     }
 
+    // VST2 has no sample position for a change, it applies from the next block on
     fn set_parameter(&self, index: i32, val: f32) {
-        match index {
-            0 => self.period.set_raw(val),
-            1 => self.capture_len.set_raw(val),
-            2 => self.playback_rate.set_raw(val),
-            _ => {}
+        self.set_normalized(index, val);
+        if index == SCENE_PARAM || index == COPY_SCENE_PARAM {
+            self.scenes.update(self);
         }
     }
 
     fn get_parameter_text(&self, index: i32) -> String {
         match index {
-            0 => format!("{:.2} samples", self.period.get()),
-            1 => format!("{:.2} samples", self.capture_len.get()),
+            0 if self.picks_note_value(0) => NOTE_VALUES[note_value(self.period.get_raw())].0.to_string(),
+            0 => self.format_length(self.length(self.period.get())),
+            1 if self.capture_ratio.get() > 0.5 => format!("{:.0}% of period", self.capture_len.get_raw() * 100.0),
+            1 => self.format_length(self.length(self.capture_len.get())),
+            2 if self.rate_display.get() > 0.5 => format_interval(self.playback_rate.get()),
             2 => format!("{:.2}x", self.playback_rate.get()),
+            3 => format!("{:.0}%", self.pump_depth.get() * 100.0),
+            4 => format!("{:.2}", self.pump_shape.get()),
+            5 => format!("{:.0}%", self.rotate_depth.get() * 100.0),
+            6 => format!("{:.2} Hz", self.rotate_rate.get()),
+            7 => LoopMode::from_param(self.loop_mode.get()).name().to_string(),
+            8 => if self.freeze.get() > 0.5 { "On" } else { "Off" }.to_string(),
+            9 => format!("{:+.1} st", self.freeze_transpose.get()),
+            10 => format!("{:.0} ms", self.freeze_glide.get()),
+            11 => SidechainMode::from_param(self.sidechain.get()).name().to_string(),
+            12 => format!("{:.0}%", self.feedback.get() * 100.0),
+            FEEDBACK_LIMITER_PARAM => match self.feedback_limit.get() {
+                gain if gain < 1.0 => format!("{:.1} dB", 20.0 * gain.log10()),
+                _ => "Off".to_string(),
+            },
+            14 => if self.overdub.get() > 0.5 { "On" } else { "Off" }.to_string(),
+            15 => if self.clear.get() > 0.5 { "On" } else { "Off" }.to_string(),
+            16 => format_db(self.input_trim.get()),
+            17 => format_db(self.output_gain.get()),
+            18 => if self.sync.get() > 0.5 { "On" } else { "Off" }.to_string(),
+            19 => format!("{:.0}", self.sync_numerator.get() + 1.0),
+            20 => format!("{:.0}", self.sync_denominator.get() + 1.0),
+            21 => if self.bypass.get() > 0.5 { "On" } else { "Off" }.to_string(),
+            22 => if self.midi_trigger.get() > 0.5 { "On" } else { "Off" }.to_string(),
+            23 => if self.latch_random.get() > 0.5 { "On" } else { "Off" }.to_string(),
+            24 => format!("{:.0}", self.seed.get()),
+            25 => if self.capture_enable.get() > 0.5 { "On" } else { "Off" }.to_string(),
+            26 => if self.playback_enable.get() > 0.5 { "On" } else { "Off" }.to_string(),
+            27 => format!("{:.0}%", self.mix.get() * 100.0),
+            28 => format!("{:.0}%", self.duck.get() * 100.0),
+            29 => Engine::from_param(self.engine.get()).name().to_string(),
+            30 => format!("{:.0} ms", self.grain_size.get()),
+            31 => format!("{:.1} /s", self.grain_density.get()),
+            32 => format!("{:.0}%", self.position_spray.get() * 100.0),
+            33 => format!("{:.1} st", self.pitch_spray.get()),
+            SAFE_MODE_PARAM => if self.safe_mode.load(Ordering::Acquire) { "On" } else { "Off" }.to_string(),
+            35 => match self.repeat_division.get() as u32 {
+                0 => "Off".to_string(),
+                division => format!("1/{}", 1 << division),
+            },
+            36 => Quantize::from_param(self.quantize.get()).name().to_string(),
+            37 => format!("{:.0}%", self.division_swing.get() * 100.0),
+            38 => format_ms(self.edge_fade.get()),
+            39 => format!("{:.0}%", self.repeat_probability.get() * 100.0),
+            40 => CapturePosition::from_param(self.capture_position.get()).name().to_string(),
+            41 => format!("{:.0}%", self.capture_offset.get() * 100.0),
+            42 => format!("{:.0}%", self.reverse_probability.get() * 100.0),
+            43 => format!("{:.0}", self.mosaic_segments.get() + 2.0),
+            44 => format!("{:.0}%", self.mosaic_probability.get() * 100.0),
+            45 => if self.mosaic_random.get() > 0.5 { "Random" } else { "In turn" }.to_string(),
+            46 => match self.slice_length.get() as u32 {
+                0 => "Off".to_string(),
+                length => format!("1/{}", 1 << length),
+            },
+            47 => format!("{:.0}%", self.slice_random.get() * 100.0),
+            48 => match self.silence_hold.get() {
+                hold if hold > 0.0 => format!("{:.0} ms", hold),
+                _ => "Off".to_string(),
+            },
+            49 => format!("{:.2}x", self.rate_start.get()),
+            50 => format!("{:.2}x", self.rate_end.get()),
+            51 => match self.start_fade.get() {
+                fade if fade > 0.0 => format!("{:.0} ms", fade),
+                _ => "Off".to_string(),
+            },
+            52 => if self.zero_crossing.get() > 0.5 { "On" } else { "Off" }.to_string(),
+            LOCK_SYNC_PARAM => if self.lock_sync.get() > 0.5 { "On" } else { "Off" }.to_string(),
+            LOCK_LEVELS_PARAM => if self.lock_levels.get() > 0.5 { "On" } else { "Off" }.to_string(),
+            55 => match self.wet_highpass.get() {
+                hz if hz > 20.0 => format!("{:.0} Hz", hz),
+                _ => "Off".to_string(),
+            },
+            56 => match self.wet_lowpass.get() {
+                hz if hz < 20000.0 => format!("{:.0} Hz", hz),
+                _ => "Off".to_string(),
+            },
+            57 => match self.crush_bits.get() {
+                bits if bits < 16.0 => format!("{:.1} bits", bits),
+                _ => "Off".to_string(),
+            },
+            58 => match self.downsample.get() {
+                factor if factor > 1.0 => format!("1/{:.1}", factor),
+                _ => "Off".to_string(),
+            },
+            59 => match self.spread.get() {
+                spread if spread > 0.0 => format!("{:.0}%", spread * 100.0),
+                _ => "Off".to_string(),
+            },
+            60 => match self.manual_bpm.get() {
+                bpm if bpm > 0.0 => format!("{:.1} BPM", bpm),
+                _ => "Off".to_string(),
+            },
+            61 => format_ms(self.tail_crossfade.get()),
+            62 => if self.capture_ratio.get() > 0.5 { "On" } else { "Off" }.to_string(),
+            63 => match self.period_jitter.get() {
+                jitter if jitter > 0.0 => format!("{:.1}%", jitter),
+                _ => "Off".to_string(),
+            },
+            64 => format!("{:.0} ms", self.attack.get()),
+            65 => match self.decay.get() {
+                decay if decay > 0.0 => format!("{:.0} ms", decay),
+                _ => "Off".to_string(),
+            },
+            66 => FadeCurve::from_param(self.fade_curve.get()).name().to_string(),
+            67 => if self.rate_display.get() > 0.5 { "Semitones" } else { "Multiplier" }.to_string(),
+            68 => if self.mono_capture.get() > 0.5 { "On" } else { "Off" }.to_string(),
+            69 => Normalize::from_param(self.normalize.get()).name().to_string(),
+            70 => format_db(self.normalize_target.get()),
+            71 => if self.dc_filter.get() > 0.5 { "On" } else { "Off" }.to_string(),
+            72 => OutputClip::from_param(self.output_clip.get()).name().to_string(),
+            73 => format_db(self.clip_ceiling.get()),
+            74 => format!("{:.0}%", self.loop_start.get() * 100.0),
+            75 => format!("{:.0}%", self.loop_length.get() * 100.0),
+            76 => if self.scrub.get() > 0.5 { "On" } else { "Off" }.to_string(),
+            77 => format!("{:.1}%", self.position.get() * 100.0),
+            78 => if self.reset_on_start.get() > 0.5 { "On" } else { "Off" }.to_string(),
+            79 => WhenStopped::from_param(self.when_stopped.get()).name().to_string(),
+            80 => match self.taps.get() as usize {
+                0 => "Off".to_string(),
+                taps => taps.to_string(),
+            },
+            81 => format!("{:.2}x", self.tap_1_rate.get()),
+            82 => format_db(self.tap_1_level.get()),
+            83 => format!("{:.2}x", self.tap_2_rate.get()),
+            84 => format_db(self.tap_2_level.get()),
+            85 => format!("{:.2}x", self.tap_3_rate.get()),
+            86 => format_db(self.tap_3_level.get()),
+            87 => format!("{:.2}x", self.tap_4_rate.get()),
+            88 => format_db(self.tap_4_level.get()),
+            89 => format_db(self.octave_down_level.get()),
+            90 => format_db(self.octave_up_level.get()),
+            91 => format_db(self.fifth_level.get()),
+            92 => if self.time_stretch.get() > 0.5 { "On" } else { "Off" }.to_string(),
+            93 => match self.capture_threshold.get() {
+                db if db == f32::NEG_INFINITY => "Off".to_string(),
+                db => format_db(db),
+            },
+            94 => if self.skip_silence.get() > 0.5 { "On" } else { "Off" }.to_string(),
+            95 => if self.auto_gain.get() > 0.5 { "On" } else { "Off" }.to_string(),
+            96 => format!("{:.2} Hz", self.lfo_1_rate.get()),
+            97 => format!("{:.2} Hz", self.lfo_2_rate.get()),
+            98 => format!("CC {}", self.mod_controller.get()),
+            99 => Source::from_param(self.route_1_source.get()).name().to_string(),
+            100 => Destination::from_param(self.route_1_destination.get()).name().to_string(),
+            101 => format!("{:+.0}%", self.route_1_depth.get() * 100.0),
+            102 => Source::from_param(self.route_2_source.get()).name().to_string(),
+            103 => Destination::from_param(self.route_2_destination.get()).name().to_string(),
+            104 => format!("{:+.0}%", self.route_2_depth.get() * 100.0),
+            105 => Source::from_param(self.route_3_source.get()).name().to_string(),
+            106 => Destination::from_param(self.route_3_destination.get()).name().to_string(),
+            107 => format!("{:+.0}%", self.route_3_depth.get() * 100.0),
+            108 => Source::from_param(self.route_4_source.get()).name().to_string(),
+            109 => Destination::from_param(self.route_4_destination.get()).name().to_string(),
+            110 => format!("{:+.0}%", self.route_4_depth.get() * 100.0),
+            111 => if self.note_values.get() > 0.5 { "On" } else { "Off" }.to_string(),
+            SCENE_PARAM => if self.scene.get() > 0.5 { "B" } else { "A" }.to_string(),
+            COPY_SCENE_PARAM => if self.copy_scene.get() > 0.5 { "On" } else { "Off" }.to_string(),
+            114 => match self.history.get() as usize {
+                0 => "Latest".to_string(),
+                back => format!("{} back", back),
+            },
+            115 => LoopPoints::from_param(self.loop_points.get()).name().to_string(),
+            116 => format!("{:.0}%", self.phase_offset.get() * 100.0),
+            117 => match self.channel_delay.get() {
+                ms if ms > 0.0 => format!("R {:.1} ms", ms),
+                ms if ms < 0.0 => format!("L {:.1} ms", -ms),
+                _ => "Off".to_string(),
+            },
+            118 => format!("{:.2} Hz", self.vibrato_rate.get()),
+            119 => match self.vibrato_depth.get() {
+                cents if cents > 0.0 => format!("{:.0} cents", cents),
+                _ => "Off".to_string(),
+            },
+            120 => match self.tremolo_depth.get() {
+                depth if depth > 0.0 => format!("{:.0}%", depth * 100.0),
+                _ => "Off".to_string(),
+            },
+            121 if self.picks_note_value(121) => NOTE_VALUES[note_value(self.tremolo_rate.get_raw())].0.to_string(),
+            121 => format!("{:.2} Hz", self.tremolo_rate.get()),
+            122 => TremoloShape::from_param(self.tremolo_shape.get()).name().to_string(),
+            123 => if self.tremolo_sync.get() > 0.5 { "On" } else { "Off" }.to_string(),
+            124..=139 => if self.gate_steps[index as usize - 124].get() > 0.5 { "On" } else { "Off" }.to_string(),
             _ => "".to_string(),
         }
     }
@@ -247,10 +1761,987 @@ impl PluginParameters for Params {
             0 => "Period",
             1 => "Capture length",
             2 => "Playback rate",
+            3 => "Pump depth",
+            4 => "Pump shape",
+            5 => "Rotate depth",
+            6 => "Rotate rate",
+            7 => "Loop mode",
+            8 => "Freeze",
+            9 => "Freeze transpose",
+            10 => "Freeze glide",
+            11 => "Sidechain",
+            12 => "Feedback",
+            FEEDBACK_LIMITER_PARAM => "Feedback limiter",
+            14 => "Overdub",
+            15 => "Clear",
+            16 => "Input trim",
+            17 => "Output gain",
+            18 => "Sync",
+            19 => "Sync numerator",
+            20 => "Sync denominator",
+            21 => "Bypass",
+            22 => "MIDI trigger",
+            23 => "Latch random",
+            24 => "Seed",
+            25 => "Capture enable",
+            26 => "Playback enable",
+            27 => "Mix",
+            28 => "Duck",
+            29 => "Engine",
+            30 => "Grain size",
+            31 => "Grain density",
+            32 => "Position spray",
+            33 => "Pitch spray",
+            SAFE_MODE_PARAM => "Safe mode",
+            35 => "Repeat division",
+            36 => "Quantize",
+            37 => "Division swing",
+            38 => "Edge fade",
+            39 => "Repeat probability",
+            40 => "Capture position",
+            41 => "Capture offset",
+            42 => "Reverse probability",
+            43 => "Mosaic segments",
+            44 => "Mosaic probability",
+            45 => "Mosaic order",
+            46 => "Slice length",
+            47 => "Slice random",
+            48 => "Silence hold",
+            49 => "Rate start",
+            50 => "Rate end",
+            51 => "Start fade",
+            52 => "Zero crossing start",
+            LOCK_SYNC_PARAM => "Lock sync",
+            LOCK_LEVELS_PARAM => "Lock levels",
+            55 => "Wet high-pass",
+            56 => "Wet low-pass",
+            57 => "Bit depth",
+            58 => "Downsample",
+            59 => "Mono spread",
+            60 => "Manual tempo",
+            61 => "Tail crossfade",
+            62 => "Relative capture",
+            63 => "Period jitter",
+            64 => "Attack",
+            65 => "Decay",
+            66 => "Fade curve",
+            67 => "Rate display",
+            68 => "Mono capture",
+            69 => "Normalize",
+            70 => "Normalize target",
+            71 => "DC filter",
+            72 => "Output clip",
+            73 => "Clip ceiling",
+            74 => "Loop start",
+            75 => "Loop length",
+            76 => "Scrub",
+            77 => "Position",
+            78 => "Reset on start",
+            79 => "When stopped",
+            80 => "Taps",
+            81 => "Tap 1 rate",
+            82 => "Tap 1 level",
+            83 => "Tap 2 rate",
+            84 => "Tap 2 level",
+            85 => "Tap 3 rate",
+            86 => "Tap 3 level",
+            87 => "Tap 4 rate",
+            88 => "Tap 4 level",
+            89 => "Octave down",
+            90 => "Octave up",
+            91 => "Fifth",
+            92 => "Time stretch",
+            93 => "Capture threshold",
+            94 => "Skip silence",
+            95 => "Auto gain",
+            96 => "LFO 1 rate",
+            97 => "LFO 2 rate",
+            98 => "Mod controller",
+            99 => "Route 1 source",
+            100 => "Route 1 destination",
+            101 => "Route 1 depth",
+            102 => "Route 2 source",
+            103 => "Route 2 destination",
+            104 => "Route 2 depth",
+            105 => "Route 3 source",
+            106 => "Route 3 destination",
+            107 => "Route 3 depth",
+            108 => "Route 4 source",
+            109 => "Route 4 destination",
+            110 => "Route 4 depth",
+            111 => "Note values",
+            SCENE_PARAM => "Scene",
+            COPY_SCENE_PARAM => "Copy scene",
+            114 => "History",
+            115 => "Loop points",
+            116 => "Phase offset",
+            117 => "Channel delay",
+            118 => "Vibrato rate",
+            119 => "Vibrato depth",
+            120 => "Tremolo depth",
+            121 => "Tremolo rate",
+            122 => "Tremolo shape",
+            123 => "Tremolo sync",
+            124..=139 => return format!("Gate step {}", index - 123),
             _ => "",
         }
         .to_string()
     }
+
+    fn string_to_parameter(&self, index: i32, text: String) -> bool {
+        let whole = |text: &str| text.trim().parse::<u8>().ok().filter(|val| (1..=16).contains(val));
+        let steps = |val: u8| (val - 1) as f32 / 15.0;
+        match index {
+            // a whole ratio like `5:4` typed into either of them sets both
+            19 | 20 if text.contains(':') => {
+                let colon = text.find(':').unwrap();
+                match (whole(&text[..colon]), whole(&text[colon + 1..])) {
+                    (Some(numerator), Some(denominator)) => {
+                        self.sync_numerator.set_raw(steps(numerator));
+                        self.sync_denominator.set_raw(steps(denominator));
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            // a note value while the period or the tremolo is picking one
+            0 | 121 if self.picks_note_value(index) => {
+                match NOTE_VALUES.iter().position(|(label, _, _)| label.eq_ignore_ascii_case(text.trim())) {
+                    Some(note) => {
+                        let param = if index == 0 { &self.period } else { &self.tremolo_rate };
+                        param.set_raw(note as f32 / (NOTE_VALUES.len() - 1) as f32);
+                        true
+                    }
+                    None => false,
+                }
+            }
+            2 => match parse_rate(&text) {
+                Some(rate) if rate > 0.0 => {
+                    self.playback_rate.set_raw(self.playback_rate.normalize(rate));
+                    true
+                }
+                _ => false,
+            },
+            38 | 61 => match (self.time_parameter(index), parse_ms(&text)) {
+                (Some(param), Some(ms)) if ms >= 0.0 => {
+                    param.set_raw(param.normalize(ms));
+                    true
+                }
+                _ => false,
+            },
+            19 | 20 => match whole(&text) {
+                Some(val) => {
+                    self.set_parameter(index, steps(val));
+                    true
+                }
+                None => false,
+            },
+            _ => false,
+        }
+    }
+
+    fn can_be_automated(&self, index: i32) -> bool {
+        // the indicators are only there to be looked at
+        index != FEEDBACK_LIMITER_PARAM && index != SAFE_MODE_PARAM
+    }
+
+    fn get_preset_data(&self) -> Vec<u8> {
+        self.serialize()
+    }
+
+    fn get_bank_data(&self) -> Vec<u8> {
+        self.serialize()
+    }
+
+    // there are no programs, so there are no program changes to ignore either
+    fn load_preset_data(&self, data: &[u8]) {
+        self.deserialize(data, true)
+    }
+
+    fn load_bank_data(&self, data: &[u8]) {
+        self.deserialize(data, false)
+    }
 }
 
 plugin_main!(SnapshotRepeatPlugin);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use dsp::Snapshot;
+    use vst::api::AEffect;
+    use vst::host::HostBuffer;
+
+    // a plugin repeating every `period` samples from the first block, the
+    // edges left hard so the repeats come back unchanged
+    fn test_plugin(period: f32, (inputs, outputs): (usize, usize)) -> (SnapshotRepeatPlugin, HostBuffer<f32>) {
+        let plugin = SnapshotRepeatPlugin::default();
+        plugin.params.period.set(period);
+        plugin.params.capture_len.set(period);
+        plugin.params.edge_fade.set(0.0);
+        (plugin, HostBuffer::new(inputs, outputs))
+    }
+
+    // the playback position is normalized so it accumulates some rounding error
+    fn assert_close(a: &[f32], b: &[f32]) {
+        assert_eq!(a.len(), b.len());
+        for (a, b) in a.iter().zip(b) {
+            assert!((a - b).abs() < 1e-3, "{} != {}", a, b);
+        }
+    }
+
+    #[test]
+    fn state_stores_parameters_by_id() {
+        let params = Params::default();
+        params.mix.set(0.25);
+        let state = String::from_utf8(params.serialize()).unwrap();
+        assert!(state.lines().any(|line| line == "mix=0.25"));
+        let restored = Params::default();
+        restored.deserialize(state.as_bytes(), false);
+        assert_eq!(restored.mix.get(), 0.25);
+
+        // older states refer to parameters by index
+        restored.deserialize(b"27=0.5", false);
+        assert_eq!(restored.mix.get(), 0.5);
+    }
+
+    #[test]
+    fn fade_times_keep_their_length_across_scales() {
+        let params = Params::default();
+        assert_eq!(params.get_parameter_text(38), "2.0 ms");
+        // saved with the old linear scale up to 5 and 50 ms
+        params.deserialize(b"edge_fade=0.8\ntail_crossfade=0.5", false);
+        assert!((params.edge_fade.get() - 4.0).abs() < 1e-3);
+        assert!((params.tail_crossfade.get() - 25.0).abs() < 1e-3);
+
+        assert!(params.string_to_parameter(61, "1.5 s".to_string()));
+        assert_eq!(params.get_parameter_text(61), "1.50 s");
+        assert!(params.string_to_parameter(38, "0.5ms".to_string()));
+        assert!((params.edge_fade.get() - 0.5).abs() < 1e-3);
+        assert!(!params.string_to_parameter(38, "long".to_string()));
+    }
+
+    #[test]
+    fn presets_keep_locked_groups() {
+        let preset = Params::default();
+        preset.sync.set(1.0);
+        preset.mix.set(0.5);
+        preset.lock_levels.set(1.0);
+        preset.groove.set(&[0.0, 0.25]);
+        let preset = preset.serialize();
+
+        let params = Params::default();
+        params.lock_sync.set(1.0);
+        params.load_preset_data(&preset);
+        assert_eq!(params.sync.get(), 0.0);
+        assert!(params.groove.offsets().is_empty());
+        assert_eq!(params.mix.get(), 0.5);
+        assert_eq!(params.lock_levels.get(), 0.0);
+
+        // the project state restores everything
+        params.load_bank_data(&preset);
+        assert_eq!(params.sync.get(), 1.0);
+        assert_eq!(params.groove.offsets(), vec![0.0, 0.25]);
+        assert_eq!(params.lock_sync.get(), 0.0);
+    }
+
+    #[test]
+    fn repeated_resumes() {
+        let (mut plugin, mut host_buffer) = test_plugin(128.0, (2, 2));
+        plugin.params.mix.set(1.0);
+        plugin.params.start_fade.set(1.0);
+        let inp = vec![vec![0.25; 128]; 2];
+        let mut out = vec![vec![0.0; 128]; 2];
+        plugin.process(&mut host_buffer.bind(&inp, &mut out));
+
+        // the snapshots stay, the repeats fade back in over the start fade each time
+        for _ in 0..4 {
+            plugin.resume();
+            plugin.resume();
+            plugin.process(&mut host_buffer.bind(&inp, &mut out));
+            plugin.channel_states.iter().for_each(ChannelState::assert_invariants);
+            assert_eq!(out[0], out[1]);
+            assert!(out[0][0] < 0.01);
+            assert!(out[0].windows(2).all(|pair| pair[1] >= pair[0]));
+            assert_close(&out[0][64..], &[0.25; 64]);
+            assert_eq!(plugin.channel_states[0].snapshot(), &[0.25; 128][..]);
+        }
+    }
+
+    #[test]
+    fn spreads_mono_input() {
+        let (mut plugin, mut host_buffer) = test_plugin(64.0, (1, 2));
+        plugin.params.spread.set(1.0);
+        let inp = (0..64).map(|i| (i as f32 / 64.0).sin()).collect::<Vec<_>>();
+        let mut out = vec![vec![0.0; 64]; 2];
+        plugin.process(&mut host_buffer.bind(&[inp], &mut out));
+        plugin.process(&mut host_buffer.bind(&[vec![0.0; 64]], &mut out));
+        // both channels repeat the one input, each its own way
+        assert!(out.iter().all(|chan| chan.iter().any(|&s| s != 0.0)));
+        assert_ne!(out[0], out[1]);
+        assert_eq!(plugin.channel_states[1].snapshot(), plugin.channel_states[0].snapshot());
+    }
+
+    #[test]
+    fn spread_keeps_stereo_input() {
+        let (mut plugin, mut host_buffer) = test_plugin(64.0, (2, 2));
+        plugin.params.spread.set(1.0);
+        let mut out = vec![vec![0.0; 64]; 2];
+        plugin.process(&mut host_buffer.bind(&[vec![0.5; 64], vec![-0.25; 64]], &mut out));
+        plugin.process(&mut host_buffer.bind(&[vec![0.0; 64], vec![0.0; 64]], &mut out));
+        assert_close(&out[0], &[0.5; 64]);
+        assert_close(&out[1], &[-0.25; 64]);
+        // switching it off keeps the snapshots
+        plugin.params.spread.set(0.0);
+        plugin.params.capture_enable.set(0.0);
+        plugin.process(&mut host_buffer.bind(&[vec![0.0; 64], vec![0.0; 64]], &mut out));
+        assert_close(&out[1], &[-0.25; 64]);
+    }
+
+    #[test]
+    fn mono_input_fills_all_outputs() {
+        let (mut plugin, mut host_buffer) = test_plugin(64.0, (1, 2));
+        let mut out = vec![vec![1.0; 64]; 2];
+        plugin.process(&mut host_buffer.bind(&[vec![0.5; 64]], &mut out));
+        assert_close(&out[1], &out[0]);
+        plugin.process(&mut host_buffer.bind(&[vec![0.0; 64]], &mut out));
+        assert_close(&out[0], &[0.5; 64]);
+        assert_close(&out[1], &[0.5; 64]);
+    }
+
+    #[test]
+    fn right_channel_plays_ahead() {
+        let (mut plugin, mut host_buffer) = test_plugin(64.0, (2, 2));
+        plugin.params.phase_offset.set(0.5);
+        let inp = (0..64).map(|i| i as f32 / 64.0).collect::<Vec<_>>();
+        let mut out = vec![vec![0.0; 64]; 2];
+        plugin.process(&mut host_buffer.bind(&[inp.clone(), inp], &mut out));
+        plugin.process(&mut host_buffer.bind(&[vec![0.0; 64], vec![0.0; 64]], &mut out));
+        // the same snapshot, half of it apart
+        let mut ahead = out[0].clone();
+        ahead.rotate_left(32);
+        assert_close(&out[1], &ahead);
+    }
+
+    #[test]
+    fn mono_capture_repeats_the_sum_on_all_channels() {
+        let (mut plugin, mut host_buffer) = test_plugin(64.0, (2, 2));
+        plugin.params.mono_capture.set(1.0);
+        let mut out = vec![vec![0.0; 64]; 2];
+        plugin.process(&mut host_buffer.bind(&[vec![0.5; 64], vec![-0.25; 64]], &mut out));
+        plugin.process(&mut host_buffer.bind(&[vec![0.0; 64], vec![0.0; 64]], &mut out));
+        assert_close(&out[0], &[0.125; 64]);
+        assert_eq!(out[0], out[1]);
+    }
+
+    #[test]
+    fn lengths_shown_in_beats_with_tempo() {
+        let params = Params::default();
+        params.period.set(22_050.0);
+        assert_eq!(params.get_parameter_text(0), "0.500 s");
+
+        // 120 BPM
+        params.display_ppq_per_sample.set(2.0 / 44_100.0);
+        assert_eq!(params.get_parameter_text(0), "1/4");
+        params.period.set(16_537.5);
+        assert_eq!(params.get_parameter_text(0), "3/16");
+        params.period.set(88_200.0);
+        assert_eq!(params.get_parameter_text(0), "1.00 bar");
+        params.display_ppq_per_bar.set(3.0);
+        assert_eq!(params.get_parameter_text(0), "1.33 bars");
+        params.period.set(20_000.0);
+        assert_eq!(params.get_parameter_text(0), "0.91 beats");
+    }
+
+    #[test]
+    fn lengths_keep_their_time_at_any_rate() {
+        let mut plugin = SnapshotRepeatPlugin::default();
+        plugin.set_sample_rate(96_000.0);
+        let params = &plugin.params;
+        params.period.set_raw(1.0);
+        assert_eq!(params.get_parameter_text(0), "10.000 s");
+        assert_eq!(params.length(params.period.get()), 960_000.0);
+        params.capture_len.set(22_050.0);
+        assert_eq!(params.get_parameter_text(1), "0.500 s");
+    }
+
+    #[test]
+    fn rate_shown_and_entered_as_interval() {
+        let params = Params::default();
+        params.rate_display.set(1.0);
+        params.playback_rate.set(1.5);
+        assert_eq!(params.get_parameter_text(2), "+7 st, +2 ct");
+        params.playback_rate.set(0.5);
+        assert_eq!(params.get_parameter_text(2), "-12 st");
+
+        assert!(params.string_to_parameter(2, "+7 st, -12 ct".to_string()));
+        assert!((params.playback_rate.get() - semitones_to_ratio(6.88)).abs() < 1e-3);
+        assert!(params.string_to_parameter(2, "-5".to_string()));
+        assert!((params.playback_rate.get() - semitones_to_ratio(-5.0)).abs() < 1e-3);
+        assert!(params.string_to_parameter(2, "2x".to_string()));
+        assert!((params.playback_rate.get() - 2.0).abs() < 1e-3);
+        assert!(!params.string_to_parameter(2, "fast".to_string()));
+    }
+
+    #[test]
+    fn all_parameters_described() {
+        assert!(PARAMETER_DESCRIPTIONS.iter().chain(&[GATE_STEP_DESCRIPTION]).all(|description| description.ends_with('.')));
+        assert_eq!(SnapshotRepeatPlugin::parameter_description(0), Some(PARAMETER_DESCRIPTIONS[0]));
+        assert_eq!(SnapshotRepeatPlugin::parameter_description(PARAMETERS - 1), Some(GATE_STEP_DESCRIPTION));
+        assert_eq!(SnapshotRepeatPlugin::parameter_description(-1), None);
+        assert_eq!(SnapshotRepeatPlugin::parameter_description(PARAMETERS), None);
+    }
+
+    #[test]
+    fn clear_parameter_drops_snapshot() {
+        let mut plugin = SnapshotRepeatPlugin::default();
+        let mut host_buffer = HostBuffer::new(1, 1);
+        let mut out = vec![vec![0.0; 64]; 1];
+        plugin.process(&mut host_buffer.bind(&[vec![0.5; 64]], &mut out));
+        plugin.channel_states[0].load_snapshot(&mut Snapshot::prepare(&[0.5; 64], 64), 128);
+
+        plugin.params.clear.set(1.0);
+        plugin.process(&mut host_buffer.bind(&[vec![0.5; 64]], &mut out));
+        assert!(out[0].iter().all(|&s| s == 0.0));
+        assert_eq!(plugin.channel_states[0].current_len(), 0);
+    }
+
+    #[test]
+    fn output_stays_under_clip_ceiling() {
+        for mode in [1.0, 2.0] {
+            let (mut plugin, mut host_buffer) = test_plugin(64.0, (1, 1));
+            plugin.params.output_gain.set(12.0);
+            plugin.params.output_clip.set(mode);
+            plugin.params.clip_ceiling.set(-6.0);
+            let mut out = vec![vec![0.0; 64]; 1];
+            plugin.process(&mut host_buffer.bind(&[vec![0.5; 64]], &mut out));
+            plugin.process(&mut host_buffer.bind(&[vec![0.0; 64]], &mut out));
+            let peak = out[0].iter().fold(0.0, |peak: f32, sample| peak.max(sample.abs()));
+            assert!(peak <= db_to_gain(-6.0) && peak > 0.45, "{}", peak);
+        }
+    }
+
+    #[test]
+    fn auto_gain_matches_input_level() {
+        let (mut plugin, mut host_buffer) = test_plugin(64.0, (1, 1));
+        plugin.params.mix.set(1.0);
+        plugin.params.output_gain.set(-12.0);
+        plugin.params.auto_gain.set(1.0);
+        let mut out = vec![vec![0.0; 64]; 1];
+        // a couple of seconds for the levels to settle
+        for _ in 0..1500 {
+            plugin.process(&mut host_buffer.bind(&[vec![0.5; 64]], &mut out));
+        }
+        assert!(out[0].iter().all(|sample| (sample - 0.5).abs() < 0.01), "{:?}", out[0]);
+    }
+
+    #[test]
+    fn routes_modulate_around_host_value() {
+        let mut plugin = SnapshotRepeatPlugin::default();
+        plugin.params.mix.set(0.25);
+        plugin.params.route_2_source.set(5.0);
+        plugin.params.route_2_destination.set(3.0);
+        plugin.params.route_2_depth.set(0.5);
+        let mut host_buffer = HostBuffer::new(1, 1);
+        let mut out = vec![vec![0.0; 64]; 1];
+        plugin.trigger(0, Trigger::ControlChange(1, 127));
+        plugin.process(&mut host_buffer.bind(&[vec![0.0; 64]], &mut out));
+        // the controller lands within the block, the modulation follows from its slice on
+        assert_eq!(plugin.params.mix.modulated(), 0.75);
+        assert_eq!(plugin.params.mix.get(), 0.25);
+    }
+
+    #[test]
+    fn modulation_moves_within_the_block() {
+        let mut plugin = SnapshotRepeatPlugin::default();
+        plugin.params.edge_fade.set(0.0);
+        plugin.params.route_1_source.set(5.0);
+        plugin.params.route_1_depth.set(1.0);
+        let mut host_buffer = HostBuffer::new(1, 1);
+        let mut out = vec![vec![0.0; 128]; 1];
+        let ramp = (0..128).map(|i| i as f32 / 128.0).collect::<Vec<_>>();
+        plugin.process(&mut host_buffer.bind(&[ramp], &mut out));
+
+        // the rate picks up from the slice the controller moves in, not the next block
+        plugin.trigger(0, Trigger::Swap);
+        plugin.trigger(modulation::SLICE, Trigger::ControlChange(1, 127));
+        plugin.process(&mut host_buffer.bind(&[vec![0.0; 128]], &mut out));
+        let step = |i: usize| out[0][i + 1] - out[0][i];
+        assert!((step(modulation::SLICE / 2) - 1.0 / 128.0).abs() < 1e-4);
+        assert!(step(modulation::SLICE + 8) > 1.2 / 128.0);
+    }
+
+    #[test]
+    fn publishes_effective_feedback() {
+        let mut plugin = SnapshotRepeatPlugin::default();
+        plugin.params.feedback.set(1.2);
+        let mut host_buffer = HostBuffer::new(2, 2);
+        let mut out = vec![vec![0.0; 64]; 2];
+        plugin.process(&mut host_buffer.bind(&[vec![0.0; 64], vec![0.0; 64]], &mut out));
+
+        let visualization = plugin.visualization();
+        let feedback = visualization.modulation(FEEDBACK_PARAM).unwrap();
+        // bent below unity loop gain by the knee
+        assert!(feedback < plugin.params.feedback.normalize(1.0));
+        let rate = visualization.modulation(PLAYBACK_RATE_PARAM);
+        assert_eq!(rate, Some(plugin.params.playback_rate.get_raw()));
+        assert_eq!(visualization.modulation(0), None);
+    }
+
+    #[test]
+    fn publishes_snapshot_overview() {
+        let (mut plugin, mut host_buffer) = test_plugin(64.0, (1, 1));
+        let mut out = vec![vec![0.0; 32]; 1];
+        for _ in 0..3 {
+            plugin.process(&mut host_buffer.bind(&[vec![0.5; 32]], &mut out));
+        }
+
+        let overview = plugin.visualization().snapshot();
+        assert_eq!(overview.len, 64);
+        // halfway through the second period
+        assert!((overview.playhead - 0.5).abs() < 1e-6);
+        assert_eq!(overview.peaks(1), &[(0.5, 0.5)]);
+        assert_eq!(overview.peaks(64), &[(0.5, 0.5); 64][..]);
+    }
+
+    #[test]
+    fn repeats_duck_dry_signal() {
+        let dry_level = |duck: f32| {
+            let mut plugin = SnapshotRepeatPlugin::default();
+            plugin.params.mix.set(0.5);
+            plugin.params.duck.set(duck);
+            let mut host_buffer = HostBuffer::new(1, 1);
+            let mut out = vec![vec![0.0; 512]; 1];
+            plugin.process(&mut host_buffer.bind(&[vec![0.0; 512]], &mut out));
+            plugin.channel_states[0].load_snapshot(&mut Snapshot::prepare(&[0.5; 512], 512), 1024);
+            plugin.process(&mut host_buffer.bind(&[vec![1.0; 512]], &mut out));
+            // minus the repeats at half the level
+            out[0][511] - 0.25
+        };
+        assert!((dry_level(0.0) - 0.5).abs() < 1e-3);
+        assert!(dry_level(1.0) < 0.01);
+    }
+
+    #[test]
+    fn bypass_crossfades_to_input() {
+        let mut plugin = SnapshotRepeatPlugin::default();
+        plugin.params.bypass.set(1.0);
+        let mut host_buffer = HostBuffer::new(1, 1);
+        let inp = vec![(0..512).map(|i| (i as f32 * 0.1).sin()).collect::<Vec<_>>()];
+        let mut out = vec![vec![0.0; 512]; 1];
+        plugin.process(&mut host_buffer.bind(&inp, &mut out));
+        // no jump at the start of the fade
+        assert!(out[0][1].abs() < 0.01);
+        // dry once the fade is done
+        assert_close(&out[0][450..], &inp[0][450..]);
+        assert_eq!(plugin.bypass_mix, 1.0);
+    }
+
+    #[test]
+    fn bypass_keeps_double_precision() {
+        let mut plugin = SnapshotRepeatPlugin::default();
+        plugin.params.bypass.set(1.0);
+        let mut host_buffer = HostBuffer::new(2, 2);
+        let inp = vec![(0..512).map(|i| (i as f64 * 0.1).sin() + 1e-12).collect::<Vec<_>>(); 2];
+        let mut out = vec![vec![0.0; 512]; 2];
+        for _ in 0..2 {
+            plugin.process_f64(&mut host_buffer.bind(&inp, &mut out));
+        }
+        // none of it went through `f32` on the way
+        assert_eq!(out, inp);
+    }
+
+    #[test]
+    fn limiter_lookahead_holds_back_the_dry_signal() {
+        let mut plugin = SnapshotRepeatPlugin::default();
+        plugin.params.output_clip.set(2.0);
+        plugin.params.bypass.set(1.0);
+        plugin.resume();
+        // 1.5 ms at 44.1 kHz
+        assert_eq!(plugin.get_info().initial_delay, 66);
+        let mut host_buffer = HostBuffer::new(1, 1);
+        let inp = vec![(0..512).map(|i| i as f32 / 512.0).collect::<Vec<_>>()];
+        let mut out = vec![vec![0.0; 512]; 1];
+        plugin.process(&mut host_buffer.bind(&inp, &mut out));
+        assert!(out[0][..66].iter().all(|&s| s == 0.0));
+        assert_eq!(out[0][66..], inp[0][..446]);
+    }
+
+    // counts how often the host was told the setup changed
+    static IO_CHANGES: AtomicUsize = AtomicUsize::new(0);
+
+    extern "C" fn test_host(_: *mut AEffect, opcode: i32, _: i32, _: isize, _: *mut c_void, _: f32) -> isize {
+        match opcode {
+            // the VST version
+            1 => 2400,
+            13 => {
+                IO_CHANGES.fetch_add(1, Ordering::Relaxed);
+                1
+            }
+            _ => 0,
+        }
+    }
+
+    #[test]
+    fn latency_follows_the_settings() {
+        unsafe {
+            let effect = vst::main::<SnapshotRepeatPlugin>(test_host);
+            let dispatch = |opcode, value| ((*effect).dispatcher)(effect, opcode, 0, value, ptr::null_mut(), 0.0);
+            let latency = || ((*effect).initialDelay, (*effect).get_plugin().get_info().initial_delay);
+            dispatch(0, 0);
+            dispatch(12, 1);
+            assert_eq!(latency(), (0, 0));
+
+            // switching the limiter on only changes the latency once the host turns the plugin on again
+            ((*effect).setParameter)(effect, 72, 1.0);
+            assert_eq!(latency(), (0, 0));
+            dispatch(12, 0);
+            dispatch(12, 1);
+            assert_eq!(latency(), (66, 66));
+            assert_eq!(IO_CHANGES.load(Ordering::Relaxed), 1);
+
+            ((*effect).setParameter)(effect, 72, 0.0);
+            dispatch(12, 0);
+            dispatch(12, 1);
+            assert_eq!(latency(), (0, 0));
+            assert_eq!(IO_CHANGES.load(Ordering::Relaxed), 2);
+            dispatch(1, 0);
+        }
+    }
+
+    #[test]
+    fn skipped_periods_pass_input() {
+        let (mut plugin, mut host_buffer) = test_plugin(64.0, (1, 1));
+        plugin.params.repeat_probability.set(0.0);
+        let inp = vec![vec![0.5; 64]];
+        let mut out = vec![vec![0.0; 64]; 1];
+        for _ in 0..8 {
+            plugin.process(&mut host_buffer.bind(&inp, &mut out));
+        }
+        assert_eq!(out[0], inp[0]);
+
+        // the repeats come back at the next period
+        plugin.params.repeat_probability.set(1.0);
+        plugin.params.input_trim.set(-6.0);
+        for _ in 0..8 {
+            plugin.process(&mut host_buffer.bind(&inp, &mut out));
+        }
+        assert_close(&out[0], &[0.5 * db_to_gain(-6.0); 64]);
+    }
+
+    #[test]
+    fn silence_holds_snapshot() {
+        let (mut plugin, mut host_buffer) = test_plugin(64.0, (1, 1));
+        // shorter than a period, no silent period gets through
+        plugin.params.silence_hold.set(1.0);
+        let mut out = vec![vec![0.0; 64]; 1];
+        let mut process = |plugin: &mut SnapshotRepeatPlugin, level: f32, blocks: usize| {
+            for _ in 0..blocks {
+                plugin.process(&mut host_buffer.bind(&[vec![level; 64]], &mut out));
+            }
+        };
+        process(&mut plugin, 0.5, 8);
+        assert_close(&plugin.channel_states[0].snapshot(), &[0.5; 64]);
+
+        process(&mut plugin, 0.0, 32);
+        assert_close(&plugin.channel_states[0].snapshot(), &[0.5; 64]);
+
+        process(&mut plugin, 0.25, 8);
+        assert_close(&plugin.channel_states[0].snapshot(), &[0.25; 64]);
+    }
+
+    #[test]
+    fn manual_tempo_without_host() {
+        let mut plugin = SnapshotRepeatPlugin::default();
+        assert_eq!(plugin.transport().ppq_pos, None);
+
+        plugin.params.manual_bpm.set(120.0);
+        let transport = plugin.transport();
+        assert_eq!(transport.ppq_pos, Some(0.0));
+        assert_eq!(transport.ppq_per_sample, 2.0 / plugin.sample_rate as f64);
+
+        // the clock keeps running from block to block
+        let mut host_buffer = HostBuffer::new(1, 1);
+        let mut out = vec![vec![0.0; 64]; 1];
+        plugin.process(&mut host_buffer.bind(&[vec![0.0; 64]], &mut out));
+        assert_eq!(plugin.transport().ppq_pos, Some(64.0 * transport.ppq_per_sample));
+    }
+
+    #[test]
+    fn repeats_fade_in_after_transport_start() {
+        let mut plugin = SnapshotRepeatPlugin::default();
+        plugin.params.start_fade.set(10.0);
+        let mut host_buffer = HostBuffer::new(1, 1);
+        let mut out = vec![vec![0.0; 512]; 1];
+        plugin.process(&mut host_buffer.bind(&[vec![0.0; 512]], &mut out));
+        plugin.channel_states[0].load_snapshot(&mut Snapshot::prepare(&[0.5; 512], 512), 1024);
+
+        plugin.follow_transport(&Transport { playing: true, ..Default::default() });
+        plugin.process(&mut host_buffer.bind(&[vec![0.0; 512]], &mut out));
+        assert!(out[0][0] < 0.01);
+        assert!(out[0].windows(2).all(|pair| pair[1] >= pair[0]));
+        assert_close(&out[0][450..], &[0.5; 62]);
+    }
+
+    #[test]
+    fn transport_start_resets_when_asked() {
+        let mut plugin = SnapshotRepeatPlugin::default();
+        let mut host_buffer = HostBuffer::new(1, 1);
+        let mut out = vec![vec![0.0; 512]; 1];
+        plugin.process(&mut host_buffer.bind(&[vec![0.0; 512]], &mut out));
+        plugin.channel_states[0].load_snapshot(&mut Snapshot::prepare(&[0.5; 512], 512), 1024);
+
+        plugin.follow_transport(&Transport { playing: true, ..Default::default() });
+        assert_eq!(plugin.channel_states[0].current_len(), 512);
+        plugin.follow_transport(&Transport::default());
+        plugin.params.reset_on_start.set(1.0);
+        plugin.follow_transport(&Transport { playing: true, ..Default::default() });
+        assert_eq!(plugin.channel_states[0].current_len(), 0);
+        assert_eq!(plugin.channel_states[0].cycle(), (0, 0));
+    }
+
+    #[test]
+    fn suspend_drops_snapshots_unless_frozen() {
+        let mut plugin = SnapshotRepeatPlugin::default();
+        let mut host_buffer = HostBuffer::new(1, 1);
+        let mut out = vec![vec![0.0; 512]; 1];
+        plugin.process(&mut host_buffer.bind(&[vec![0.0; 512]], &mut out));
+        plugin.channel_states[0].load_snapshot(&mut Snapshot::prepare(&[0.5; 512], 512), 1024);
+
+        plugin.frozen = true;
+        plugin.suspend();
+        plugin.resume();
+        assert_eq!(plugin.channel_states[0].current_len(), 512);
+        // fading in again
+        assert_eq!(plugin.start_gain, 0.0);
+
+        plugin.frozen = false;
+        plugin.suspend();
+        plugin.resume();
+        assert_eq!(plugin.channel_states[0].current_len(), 0);
+    }
+
+    #[test]
+    fn stopped_transport_holds_when_asked() {
+        let plugin = SnapshotRepeatPlugin::default();
+        let stopped = Transport { stopped: true, ..Default::default() };
+        assert!(plugin.held(&stopped).is_none());
+        plugin.params.when_stopped.set(2.0);
+        assert!(plugin.held(&stopped) == Some(WhenStopped::Silence));
+        // without a host reporting it, the transport is never stopped
+        assert!(plugin.held(&Transport::default()).is_none());
+        assert!(plugin.held(&Transport { playing: true, ..Default::default() }).is_none());
+    }
+
+    #[test]
+    fn holding_fades_and_keeps_bypass() {
+        let mut plugin = SnapshotRepeatPlugin::default();
+        plugin.params.edge_fade.set(0.0);
+        plugin.params.when_stopped.set(2.0);
+        let mut host_buffer = HostBuffer::new(1, 1);
+        let mut out = vec![vec![0.0; 512]; 1];
+        plugin.process(&mut host_buffer.bind(&[vec![0.5; 512]], &mut out));
+        plugin.trigger(0, Trigger::Swap);
+        plugin.process(&mut host_buffer.bind(&[vec![0.0; 512]], &mut out));
+        assert_close(&out[0][500..], &[0.5; 12]);
+
+        // the repeats fade out rather than stopping dead
+        plugin.test_transport = Some(Transport { stopped: true, ..Default::default() });
+        plugin.process(&mut host_buffer.bind(&[vec![0.0; 512]], &mut out));
+        assert!(out[0][0] > 0.45);
+        assert!(out[0][1..].windows(2).all(|pair| pair[1] <= pair[0]));
+        assert_eq!(out[0][511], 0.0);
+
+        // bypassed while held the input still comes through
+        plugin.params.bypass.set(1.0);
+        plugin.process(&mut host_buffer.bind(&[vec![0.25; 512]], &mut out));
+        assert_close(&out[0][450..], &[0.25; 62]);
+
+        // and the repeats fade back in when the transport starts again
+        plugin.params.bypass.set(0.0);
+        plugin.bypass_mix = 0.0;
+        plugin.test_transport = None;
+        plugin.process(&mut host_buffer.bind(&[vec![0.0; 512]], &mut out));
+        assert!(out[0][0] < 0.05);
+        assert_close(&out[0][500..], &[0.5; 12]);
+    }
+
+    #[test]
+    fn seed_repeats_random_decisions() {
+        let skips = |plugin: &mut SnapshotRepeatPlugin| {
+            let mut host_buffer = HostBuffer::new(1, 1);
+            let mut out = vec![vec![0.0; 64]; 1];
+            (0..32).map(|_| {
+                plugin.process(&mut host_buffer.bind(&[vec![0.5; 64]], &mut out));
+                plugin.channel_states[0].skipped
+            }).collect::<Vec<_>>()
+        };
+        let plugin = |seed: f32| {
+            let (plugin, _) = test_plugin(64.0, (1, 1));
+            plugin.params.repeat_probability.set(0.5);
+            plugin.params.seed.set(seed);
+            plugin
+        };
+        let mut first = plugin(3.0);
+        let take = skips(&mut first);
+        assert_eq!(take, skips(&mut plugin(3.0)));
+        assert_ne!(take, skips(&mut plugin(4.0)));
+        // starting the transport again starts the same sequence over
+        first.params.reset_on_start.set(1.0);
+        first.follow_transport(&Transport { playing: true, ..Default::default() });
+        assert_eq!(take, skips(&mut first));
+    }
+
+    #[test]
+    fn other_scene_stored_with_state() {
+        let params = Params::default();
+        params.set_parameter(27, 0.25);
+        params.set_parameter(SCENE_PARAM, 1.0);
+        params.set_parameter(27, 0.75);
+        let state = params.serialize();
+
+        // back in B, with A waiting behind it
+        let restored = Params::default();
+        restored.deserialize(&state, false);
+        assert_eq!(restored.get_parameter_text(SCENE_PARAM), "B");
+        assert_eq!(restored.mix.get_raw(), 0.75);
+        restored.set_parameter(SCENE_PARAM, 0.0);
+        assert_eq!(restored.mix.get_raw(), 0.25);
+
+        // a state without scenes has the same in both
+        restored.deserialize(b"mix=0.5\nscene=0", false);
+        restored.set_parameter(SCENE_PARAM, 1.0);
+        assert_eq!(restored.mix.get_raw(), 0.5);
+    }
+
+    #[test]
+    fn notes_latch_variation_per_seed() {
+        let variations = |seed: f32| {
+            let mut plugin = SnapshotRepeatPlugin::default();
+            plugin.params.midi_trigger.set(1.0);
+            plugin.params.latch_random.set(1.0);
+            plugin.params.seed.set(seed);
+            let mut host_buffer = HostBuffer::new(1, 1);
+            let mut out = vec![vec![0.0; 64]; 1];
+            let mut variations = Vec::new();
+            for note in 60..64 {
+                plugin.trigger(0, Trigger::NoteOn(note));
+                plugin.process(&mut host_buffer.bind(&[vec![0.5; 64]], &mut out));
+                let chan_state = &plugin.channel_states[0];
+                variations.push((chan_state.rate_variation, chan_state.start_offset));
+                plugin.trigger(0, Trigger::NoteOff(note));
+                plugin.process(&mut host_buffer.bind(&[vec![0.5; 64]], &mut out));
+                // back to normal once the note ends
+                assert_eq!(plugin.channel_states[0].rate_variation, 1.0);
+            }
+            variations
+        };
+        assert_eq!(variations(7.0), variations(7.0));
+        assert_ne!(variations(7.0), variations(8.0));
+    }
+
+    #[test]
+    fn controllers_drive_learned_parameters() {
+        let mut plugin = SnapshotRepeatPlugin::default();
+        let mut host_buffer = HostBuffer::new(1, 1);
+        let mut out = vec![vec![0.0; 64]; 1];
+        plugin.midi_learn().learn(27);
+        plugin.trigger(0, Trigger::ControlChange(74, 0));
+        plugin.process(&mut host_buffer.bind(&[vec![0.5; 64]], &mut out));
+        assert_eq!(plugin.params.mix.get(), 0.0);
+        plugin.trigger(0, Trigger::ControlChange(74, 127));
+        plugin.process(&mut host_buffer.bind(&[vec![0.5; 64]], &mut out));
+        assert_eq!(plugin.params.mix.get(), 1.0);
+
+        // the mappings are stored with the state, presets keep the current ones
+        let state = plugin.params.serialize();
+        assert!(String::from_utf8_lossy(&state).lines().any(|line| line == "midi_cc=74:mix"));
+        let restored = Params::default();
+        restored.deserialize(&state, false);
+        assert_eq!(restored.midi_learn.mappings(), vec![(74, 27)]);
+        restored.deserialize(b"mix=0.5", true);
+        assert_eq!(restored.midi_learn.mappings(), vec![(74, 27)]);
+        restored.deserialize(b"mix=0.5", false);
+        assert_eq!(restored.midi_learn.mappings(), vec![]);
+    }
+
+    #[test]
+    fn swap_trigger_is_sample_accurate() {
+        let mut plugin = SnapshotRepeatPlugin::default();
+        // so the snapshot plays back unchanged
+        plugin.params.edge_fade.set(0.0);
+        let mut host_buffer = HostBuffer::new(1, 1);
+        let mut out = vec![vec![0.0; 64]; 1];
+        plugin.process(&mut host_buffer.bind(&[vec![0.5; 64]], &mut out));
+
+        assert!(plugin.trigger(32, Trigger::Swap));
+        plugin.process(&mut host_buffer.bind(&[vec![0.0; 64]], &mut out));
+        assert!(out[0][..32].iter().all(|&s| s == 0.0));
+        assert_close(&out[0][32..], &[0.5; 32]);
+        // the snapshot is what was captured up to the trigger
+        assert_eq!(plugin.channel_states[0].current_len(), 96);
+    }
+
+    #[test]
+    fn queued_parameter_changes_land_on_their_sample() {
+        let mut plugin = SnapshotRepeatPlugin::default();
+        plugin.params.edge_fade.set(0.0);
+        let mut host_buffer = HostBuffer::new(1, 1);
+        let mut out = vec![vec![0.0; 64]; 1];
+        plugin.process(&mut host_buffer.bind(&[vec![0.5; 64]], &mut out));
+
+        // clearing through the parameter drops the snapshot right there
+        assert!(plugin.trigger(0, Trigger::Swap));
+        assert!(plugin.trigger(32, Trigger::Parameter(15, 1.0)));
+        plugin.process(&mut host_buffer.bind(&[vec![0.0; 64]], &mut out));
+        assert_close(&out[0][..32], &[0.5; 32]);
+        assert!(out[0][32..].iter().all(|&s| s == 0.0));
+        assert_eq!(plugin.params.clear.get(), 1.0);
+    }
+
+    #[test]
+    fn scene_trigger_switches_on_its_sample() {
+        let mut plugin = SnapshotRepeatPlugin::default();
+        plugin.params.edge_fade.set(0.0);
+        // B keeps the defaults, A turns the repeats down
+        plugin.params.copy_scene.set(1.0);
+        plugin.params.scenes.update(&plugin.params);
+        plugin.params.output_gain.set(-6.0);
+        let mut host_buffer = HostBuffer::new(1, 1);
+        let mut out = vec![vec![0.0; 64]; 1];
+        plugin.process(&mut host_buffer.bind(&[vec![0.5; 64]], &mut out));
+
+        assert!(plugin.trigger(0, Trigger::Swap));
+        assert!(plugin.trigger(32, Trigger::Scene(1)));
+        plugin.process(&mut host_buffer.bind(&[vec![0.0; 64]], &mut out));
+        assert_close(&out[0][..32], &[0.5 * db_to_gain(-6.0); 32]);
+        // from there the gain moves to the one of B
+        assert_close(&out[0][63..], &[0.5]);
+        assert_eq!(plugin.params.scene.get(), 1.0);
+        assert_eq!(plugin.params.output_gain.get(), 0.0);
+    }
+
+    #[test]
+    fn broken_output_falls_back_to_safe_mode() {
+        let mut plugin = SnapshotRepeatPlugin::default();
+        let mut host_buffer = HostBuffer::new(1, 1);
+        let inp = vec![vec![0.5; 64]];
+        let mut out = vec![vec![0.0; 64]; 1];
+        plugin.process(&mut host_buffer.bind(&inp, &mut out));
+        for _ in 0..SAFE_MODE_ANOMALIES {
+            assert_eq!(plugin.params.get_parameter(SAFE_MODE_PARAM), 0.0);
+            plugin.channel_states[0].load_snapshot(&mut Snapshot::prepare(&[f32::NAN; 64], 64), 64);
+            plugin.process(&mut host_buffer.bind(&inp, &mut out));
+            assert!(out[0].iter().all(|sample| sample.is_finite()));
+        }
+        assert_eq!(plugin.params.get_parameter(SAFE_MODE_PARAM), 1.0);
+        plugin.process(&mut host_buffer.bind(&inp, &mut out));
+        assert_eq!(out[0], inp[0]);
+
+        // clearing starts over
+        plugin.params.clear.set(1.0);
+        plugin.process(&mut host_buffer.bind(&inp, &mut out));
+        assert_eq!(plugin.params.get_parameter(SAFE_MODE_PARAM), 0.0);
+    }
+}
@@ -0,0 +1,76 @@
+//! Turning the MIDI events sent by the host into triggers.
+//!
+//! The host hands over the events of a block before calling `process`, each
+//! one becomes a trigger queued at its sample offset into that block.
+
+use vst::api::Events;
+use vst::event::Event;
+
+use crate::events::{EventQueue, Trigger};
+
+/// the MIDI messages the plugin reacts to, on any channel
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum MidiMessage {
+    NoteOn(u8),
+    NoteOff(u8),
+    /// controller number and value
+    ControlChange(u8, u8),
+}
+
+impl MidiMessage {
+    pub(crate) fn parse(data: [u8; 3]) -> Option<Self> {
+        match (data[0] & 0xf0, data[2]) {
+            // note on with zero velocity is a note off
+            (0x90, 0) | (0x80, _) => Some(MidiMessage::NoteOff(data[1])),
+            (0x90, _) => Some(MidiMessage::NoteOn(data[1])),
+            (0xb0, value) => Some(MidiMessage::ControlChange(data[1], value)),
+            _ => None,
+        }
+    }
+
+    fn trigger(self) -> Trigger {
+        match self {
+            MidiMessage::NoteOn(note) => Trigger::NoteOn(note),
+            MidiMessage::NoteOff(note) => Trigger::NoteOff(note),
+            MidiMessage::ControlChange(cc, value) => Trigger::ControlChange(cc, value),
+        }
+    }
+}
+
+/// Queues the triggers for all the events of a `process_events` call.
+pub(crate) fn queue_events(events: &Events, queue: &mut EventQueue) {
+    for event in events.events() {
+        if let Event::Midi(midi) = event {
+            if let Some(message) = MidiMessage::parse(midi.data) {
+                // a full queue drops the event, the next block gets a fresh start anyway
+                queue.push(midi.delta_frames.max(0) as usize, message.trigger());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_notes_on_any_channel() {
+        assert_eq!(MidiMessage::parse([0x90, 60, 100]), Some(MidiMessage::NoteOn(60)));
+        assert_eq!(MidiMessage::parse([0x9f, 61, 1]), Some(MidiMessage::NoteOn(61)));
+        assert_eq!(MidiMessage::parse([0x83, 62, 64]), Some(MidiMessage::NoteOff(62)));
+        assert_eq!(MidiMessage::parse([0x90, 63, 0]), Some(MidiMessage::NoteOff(63)));
+    }
+
+    #[test]
+    fn parses_controllers() {
+        assert_eq!(MidiMessage::parse([0xb0, 1, 127]), Some(MidiMessage::ControlChange(1, 127)));
+        assert_eq!(MidiMessage::parse([0xb5, 74, 0]), Some(MidiMessage::ControlChange(74, 0)));
+    }
+
+    #[test]
+    fn ignores_other_messages() {
+        // pitch bend, aftertouch
+        assert_eq!(MidiMessage::parse([0xe0, 0, 64]), None);
+        assert_eq!(MidiMessage::parse([0xd0, 10, 0]), None);
+    }
+}
@@ -0,0 +1,227 @@
+//! Modulation matrix, routing a few moving sources to the parameters that can be modulated.
+//!
+//! Each of the `ROUTES` routes takes a source, scaled by its depth, to a
+//! destination. The routes to the same destination add up in its normalized
+//! range, around the value the host set, and the sum is clamped to that range.
+//! The sources are worked out for each slice of `SLICE` samples while a route
+//! is active, the LFOs and random values swing both ways (`-1 ..= 1`), the
+//! envelope and controller only go up (`0 ..= 1`). The pump envelope and the
+//! stereo rotation LFO live here as well, their depths are destinations.
+
+use std::f32::consts::TAU;
+
+use crate::random::Random;
+
+pub(crate) const ROUTES: usize = 4;
+pub(crate) const DESTINATIONS: usize = 7;
+// samples the sources hold for while a route is active
+pub(crate) const SLICE: usize = 32;
+
+// how fast the envelope follower rises and falls
+const ENVELOPE_ATTACK_MS: f32 = 10.0;
+const ENVELOPE_RELEASE_MS: f32 = 200.0;
+
+/// what a route modulates with
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Source {
+    Off,
+    Lfo1,
+    Lfo2,
+    /// the level of the input
+    Envelope,
+    /// a new random value for each period
+    Random,
+    /// the MIDI controller chosen for modulation
+    Controller,
+}
+
+impl Source {
+    pub(crate) fn from_param(val: f32) -> Self {
+        match val as usize {
+            1 => Source::Lfo1,
+            2 => Source::Lfo2,
+            3 => Source::Envelope,
+            4 => Source::Random,
+            5 => Source::Controller,
+            _ => Source::Off,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Source::Off => "Off",
+            Source::Lfo1 => "LFO 1",
+            Source::Lfo2 => "LFO 2",
+            Source::Envelope => "Envelope",
+            Source::Random => "Random",
+            Source::Controller => "Controller",
+        }
+    }
+}
+
+/// what a route modulates
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Destination {
+    Rate,
+    Period,
+    CaptureLength,
+    Mix,
+    LoopStart,
+    PumpDepth,
+    RotateDepth,
+}
+
+impl Destination {
+    pub(crate) fn from_param(val: f32) -> Self {
+        match val as usize {
+            1 => Destination::Period,
+            2 => Destination::CaptureLength,
+            3 => Destination::Mix,
+            4 => Destination::LoopStart,
+            5 => Destination::PumpDepth,
+            6 => Destination::RotateDepth,
+            _ => Destination::Rate,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Destination::Rate => "Rate",
+            Destination::Period => "Period",
+            Destination::CaptureLength => "Capture length",
+            Destination::Mix => "Mix",
+            Destination::LoopStart => "Loop start",
+            Destination::PumpDepth => "Pump depth",
+            Destination::RotateDepth => "Rotate depth",
+        }
+    }
+}
+
+/// a route as set by the parameters, (source, destination, depth)
+pub(crate) type Route = (Source, Destination, f32);
+
+pub(crate) struct Modulation {
+    // normalized (0.0 .. 1.0) phases of the two LFOs
+    lfo_phases: [f32; 2],
+    // and of the one rotating the stereo field
+    rotate_phase: f32,
+    envelope: f32,
+    random: f32,
+    controller: f32,
+    // draws the random values, seeded like the rest of the random decisions
+    generator: Random,
+}
+
+impl Default for Modulation {
+    fn default() -> Self {
+        Self {
+            lfo_phases: [0.0; 2],
+            rotate_phase: 0.0,
+            envelope: 0.0,
+            random: 0.0,
+            controller: 0.0,
+            generator: Random::new(0),
+        }
+    }
+}
+
+impl Modulation {
+    pub(crate) fn reseed(&mut self, seed: u32) {
+        self.generator = Random::new(seed);
+    }
+
+    /// Moves the LFOs on by `samples`, `rates` are in hertz.
+    pub(crate) fn advance(&mut self, rates: [f32; 2], samples: usize, sample_rate: f32) {
+        for (phase, rate) in self.lfo_phases.iter_mut().zip(rates) {
+            *phase = (*phase + rate * samples as f32 / sample_rate).fract();
+        }
+    }
+
+    /// The stereo rotation LFO (`-1 ..= 1`), then moves it on by `increment` of a cycle.
+    pub(crate) fn next_rotation(&mut self, increment: f32) -> f32 {
+        let value = self.rotation();
+        self.rotate_phase = (self.rotate_phase + increment) % 1.0;
+        value
+    }
+
+    /// Where the stereo rotation LFO is (`-1 ..= 1`).
+    pub(crate) fn rotation(&self) -> f32 {
+        (self.rotate_phase * TAU).sin()
+    }
+
+    /// Follows the level of the input, one level for each sample.
+    pub(crate) fn follow(&mut self, levels: impl Iterator<Item = f32>, sample_rate: f32) {
+        let attack = 1.0 - (-1000.0 / (ENVELOPE_ATTACK_MS * sample_rate)).exp();
+        let release = 1.0 - (-1000.0 / (ENVELOPE_RELEASE_MS * sample_rate)).exp();
+        for level in levels {
+            let coefficient = if level > self.envelope { attack } else { release };
+            self.envelope += (level - self.envelope) * coefficient;
+        }
+    }
+
+    /// Draws the random value for a new period.
+    pub(crate) fn next_period(&mut self) {
+        self.random = self.generator.next_f32() * 2.0 - 1.0;
+    }
+
+    /// Sets the value of the controller source, `0 ..= 127`.
+    pub(crate) fn set_controller(&mut self, value: u8) {
+        self.controller = value.min(127) as f32 / 127.0;
+    }
+
+    fn source(&self, source: Source) -> f32 {
+        match source {
+            Source::Off => 0.0,
+            Source::Lfo1 => (self.lfo_phases[0] * TAU).sin(),
+            Source::Lfo2 => (self.lfo_phases[1] * TAU).sin(),
+            Source::Envelope => self.envelope.min(1.0),
+            Source::Random => self.random,
+            Source::Controller => self.controller,
+        }
+    }
+
+    /// How far each destination (in `Destination` order) is moved in its normalized range.
+    pub(crate) fn offsets(&self, routes: &[Route]) -> [f32; DESTINATIONS] {
+        let mut offsets = [0.0; DESTINATIONS];
+        for &(source, destination, depth) in routes {
+            offsets[destination as usize] += self.source(source) * depth;
+        }
+        offsets
+    }
+}
+
+/// Whether any of `routes` moves its destination.
+pub(crate) fn active(routes: &[Route]) -> bool {
+    routes.iter().any(|&(source, _, depth)| source != Source::Off && depth != 0.0)
+}
+
+/// Classic sidechain pump gain, ducked by `depth` at the start of the cycle
+/// (`phase` 0.0) and recovering towards its end.
+pub(crate) fn pump_gain(phase: f32, depth: f32, shape: f32) -> f32 {
+    1.0 - depth * (1.0 - phase).powf(shape)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes_add_up_per_destination() {
+        let mut modulation = Modulation::default();
+        modulation.set_controller(127);
+        // a quarter of the way through, the LFO is at its top
+        modulation.advance([1.0, 0.0], 11_025, 44_100.0);
+        let routes = [
+            (Source::Controller, Destination::Mix, 0.5),
+            (Source::Lfo1, Destination::Mix, -0.25),
+            (Source::Lfo2, Destination::Rate, 1.0),
+            (Source::Off, Destination::Period, 1.0),
+        ];
+        let offsets = modulation.offsets(&routes);
+        assert!((offsets[Destination::Mix as usize] - 0.25).abs() < 1e-6);
+        assert_eq!(offsets[Destination::Rate as usize], 0.0);
+        assert_eq!(offsets[Destination::Period as usize], 0.0);
+        assert!(active(&routes));
+        assert!(!active(&[(Source::Off, Destination::Mix, 1.0), (Source::Lfo1, Destination::Mix, 0.0)]));
+    }
+}
@@ -0,0 +1,251 @@
+//! Parameter properties for generic host panels and control surfaces.
+//!
+//! `vst` doesn't answer the host asking for them, so once the plugin is open
+//! its `AEffect` gets a dispatcher answering that request and passing all the
+//! others on to the one `vst` installed. The parameters are grouped into
+//! categories, the switches and choices are marked as such and the rest can
+//! ramp. The host's own bypass switch isn't passed on by `vst` either, it goes
+//! on to the plugin as a vendor specific request `vst` does pass on, so it
+//! reaches the instance the `AEffect` belongs to.
+
+use std::ffi::c_void;
+use std::sync::OnceLock;
+
+use vst::api::{AEffect, DispatcherProc};
+
+use crate::dsp::{Params, FEEDBACK_LIMITER_PARAM, LOCK_LEVELS_PARAM, LOCK_SYNC_PARAM, SAFE_MODE_PARAM};
+use crate::PARAMETERS;
+
+// `effSetBypass`, `effVendorSpecific` and `effGetParameterProperties` of the VST 2.4 SDK
+const SET_BYPASS: i32 = 44;
+const VENDOR_SPECIFIC: i32 = 50;
+const GET_PARAMETER_PROPERTIES: i32 = 56;
+
+/// the vendor specific index the host's bypass switch reaches the plugin under
+pub(crate) const HOST_BYPASS: i32 = i32::from_be_bytes(*b"SRby");
+
+// `VstParameterFlags`
+const IS_SWITCH: i32 = 1 << 0;
+const USES_INTEGER_MIN_MAX: i32 = 1 << 1;
+const USES_FLOAT_STEP: i32 = 1 << 2;
+const USES_INT_STEP: i32 = 1 << 3;
+const SUPPORTS_DISPLAY_INDEX: i32 = 1 << 4;
+const SUPPORTS_DISPLAY_CATEGORY: i32 = 1 << 5;
+const CAN_RAMP: i32 = 1 << 6;
+
+// the dispatcher `vst` installed, the same function for every instance
+static VST_DISPATCHER: OnceLock<DispatcherProc> = OnceLock::new();
+// properties of every parameter, the same for every instance as well
+static PROPERTIES: OnceLock<Vec<ParameterProperties>> = OnceLock::new();
+
+/// the category a parameter is shown in, in the order they are shown
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+enum Category {
+    /// when and how much is captured
+    Timing,
+    /// how the snapshot is played
+    Playback,
+    /// levels and the processing of the output
+    Mix,
+}
+
+impl Category {
+    fn of(index: i32) -> Self {
+        match index {
+            0 | 1 | 7 | 15 | 18..=20 | 25 | 35..=37 | 40 | 41 | 48 | 52 | LOCK_SYNC_PARAM | 60 | 62 | 63 | 68 | 78
+            | 79 | 93 | 94 | 111 | 115 => Category::Timing,
+            3..=6 | 11 | 12 | FEEDBACK_LIMITER_PARAM | 14 | 16 | 17 | 21 | 27 | 28 | SAFE_MODE_PARAM
+            | LOCK_LEVELS_PARAM | 55..=59 | 69..=73 | 82 | 84 | 86 | 88..=91 | 95 | 117 => Category::Mix,
+            _ => Category::Playback,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Category::Timing => "Timing",
+            Category::Playback => "Playback",
+            Category::Mix => "Mix",
+        }
+    }
+}
+
+/// `VstParameterProperties` as the host reads it
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(crate) struct ParameterProperties {
+    step_float: f32,
+    small_step_float: f32,
+    large_step_float: f32,
+    label: [u8; 64],
+    flags: i32,
+    min_integer: i32,
+    max_integer: i32,
+    step_integer: i32,
+    large_step_integer: i32,
+    short_label: [u8; 8],
+    display_index: i16,
+    // starting from one, zero is none
+    category: i16,
+    parameters_in_category: i16,
+    reserved: i16,
+    category_label: [u8; 24],
+    future: [u8; 16],
+}
+
+impl Default for ParameterProperties {
+    fn default() -> Self {
+        Self {
+            step_float: 0.0,
+            small_step_float: 0.0,
+            large_step_float: 0.0,
+            label: [0; 64],
+            flags: 0,
+            min_integer: 0,
+            max_integer: 0,
+            step_integer: 0,
+            large_step_integer: 0,
+            short_label: [0; 8],
+            display_index: 0,
+            category: 0,
+            parameters_in_category: 0,
+            reserved: 0,
+            category_label: [0; 24],
+            future: [0; 16],
+        }
+    }
+}
+
+fn properties(params: &Params, index: i32) -> ParameterProperties {
+    let category = Category::of(index);
+    let in_category = (0..PARAMETERS).filter(|&other| Category::of(other) == category);
+    // the categories one after another, each in the order of the indices
+    let display_index = (0..PARAMETERS)
+        .filter(|&other| Category::of(other) < category || (Category::of(other) == category && other < index))
+        .count();
+    let mut properties = ParameterProperties {
+        flags: SUPPORTS_DISPLAY_INDEX | SUPPORTS_DISPLAY_CATEGORY,
+        display_index: display_index as i16,
+        category: category as i16 + 1,
+        parameters_in_category: in_category.count() as i16,
+        ..Default::default()
+    };
+    let name = category.name().as_bytes();
+    properties.category_label[..name.len()].copy_from_slice(name);
+
+    match params.parameter(index).and_then(|param| param.steps()) {
+        Some(2) => properties.flags |= IS_SWITCH,
+        Some(steps) => {
+            properties.flags |= USES_INTEGER_MIN_MAX | USES_INT_STEP;
+            properties.max_integer = steps as i32 - 1;
+            properties.step_integer = 1;
+            properties.large_step_integer = (steps as i32 / 8).max(1);
+        }
+        // the read-only ones are left alone
+        None if params.parameter(index).is_none() => {}
+        None => {
+            properties.flags |= USES_FLOAT_STEP | CAN_RAMP;
+            properties.step_float = 0.01;
+            properties.small_step_float = 0.001;
+            properties.large_step_float = 0.1;
+        }
+    }
+    properties
+}
+
+/// Puts `dispatch` in front of the dispatcher `vst` installed in `effect`.
+///
+/// # Safety
+///
+/// `effect` has to point to the plugin's `AEffect` filled in by `vst`.
+pub(crate) unsafe fn install(effect: *mut AEffect, params: &Params) {
+    PROPERTIES.get_or_init(|| (0..PARAMETERS).map(|index| properties(params, index)).collect());
+    let installed = (*effect).dispatcher;
+    if installed as usize != dispatch as DispatcherProc as usize {
+        VST_DISPATCHER.get_or_init(|| installed);
+        (*effect).dispatcher = dispatch;
+    }
+}
+
+// the bypass is only an atomic store in the plugin, hosts switch it from the audio thread
+extern "C" fn dispatch(effect: *mut AEffect, opcode: i32, index: i32, value: isize, ptr: *mut c_void, opt: f32) -> isize {
+    // only ever installed after setting the one it goes in front of
+    let vst_dispatch = match VST_DISPATCHER.get() {
+        Some(vst_dispatch) => vst_dispatch,
+        None => return 0,
+    };
+    match opcode {
+        GET_PARAMETER_PROPERTIES => {
+            // negative indices wrap around past the end
+            match PROPERTIES.get().and_then(|table| table.get(index as usize)) {
+                Some(properties) if !ptr.is_null() => {
+                    unsafe { *(ptr as *mut ParameterProperties) = *properties };
+                    1
+                }
+                _ => 0,
+            }
+        }
+        SET_BYPASS => vst_dispatch(effect, VENDOR_SPECIFIC, HOST_BYPASS, value, ptr, opt),
+        _ => vst_dispatch(effect, opcode, index, value, ptr, opt),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr;
+    use vst::host::HostBuffer;
+    use crate::SnapshotRepeatPlugin;
+
+    #[test]
+    fn parameters_grouped_with_hints() {
+        let params = Params::default();
+        let table = (0..PARAMETERS).map(|index| properties(&params, index)).collect::<Vec<_>>();
+
+        // every parameter has its own place, the categories one after another
+        let mut order = (0..PARAMETERS as usize).collect::<Vec<_>>();
+        order.sort_by_key(|&index| table[index].display_index);
+        assert!(order.iter().enumerate().all(|(place, &index)| table[index].display_index == place as i16));
+        assert!(order.windows(2).all(|pair| table[pair[0]].category <= table[pair[1]].category));
+        let timing = table.iter().filter(|properties| properties.category == 1).count();
+        assert_eq!(table[0].parameters_in_category as usize, timing);
+        assert_eq!(&table[27].category_label[..4], b"Mix\0");
+
+        // freeze is a switch, the loop mode a choice and the period ramps
+        assert_eq!(table[8].flags & IS_SWITCH, IS_SWITCH);
+        assert_eq!(table[7].flags & USES_INTEGER_MIN_MAX, USES_INTEGER_MIN_MAX);
+        assert_eq!((table[7].min_integer, table[7].max_integer), (0, 2));
+        assert_eq!(table[0].flags & (CAN_RAMP | IS_SWITCH), CAN_RAMP);
+        assert_eq!(table[FEEDBACK_LIMITER_PARAM as usize].flags & CAN_RAMP, 0);
+    }
+
+    extern "C" fn host(_: *mut AEffect, opcode: i32, _: i32, _: isize, _: *mut c_void, _: f32) -> isize {
+        // only the VST version is asked for
+        if opcode == 1 { 2400 } else { 0 }
+    }
+
+    #[test]
+    fn host_requests_reach_the_instance() {
+        unsafe {
+            let effect = vst::main::<SnapshotRepeatPlugin>(host);
+            let dispatch = |opcode, index, value, ptr| ((*effect).dispatcher)(effect, opcode, index, value, ptr, 0.0);
+            dispatch(0, 0, 0, ptr::null_mut());
+
+            // the properties as the host asks for them
+            let table = properties(&Params::default(), 8);
+            let mut asked = ParameterProperties::default();
+            let asked_ptr = &mut asked as *mut ParameterProperties as *mut c_void;
+            assert_eq!(dispatch(GET_PARAMETER_PROPERTIES, 8, 0, asked_ptr), 1);
+            assert_eq!((asked.flags, asked.display_index), (table.flags, table.display_index));
+            assert_eq!(dispatch(GET_PARAMETER_PROPERTIES, PARAMETERS, 0, asked_ptr), 0);
+
+            // bypassed by the host the input comes through once faded over
+            assert_eq!(dispatch(SET_BYPASS, 0, 1, ptr::null_mut()), 1);
+            let mut host_buffer = HostBuffer::new(1, 1);
+            let inp = vec![vec![0.5; 1024]];
+            let mut out = vec![vec![0.0; 1024]];
+            (*effect).get_plugin().process(&mut host_buffer.bind(&inp, &mut out));
+            assert_eq!(out[0][1000..], inp[0][1000..]);
+            dispatch(1, 0, 0, ptr::null_mut());
+        }
+    }
+}
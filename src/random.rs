@@ -0,0 +1,25 @@
+//! Small deterministic random number generator, the same seed always gives the
+//! same sequence so randomized variations can be reproduced.
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Random {
+    state: u32,
+}
+
+impl Random {
+    pub(crate) fn new(seed: u32) -> Self {
+        // spread nearby seeds apart, xorshift also gets stuck on zero
+        Self {
+            state: seed.wrapping_mul(0x9e37_79b9) | 1,
+        }
+    }
+
+    /// uniformly distributed in `0.0 .. 1.0`
+    pub(crate) fn next_f32(&mut self) -> f32 {
+        // xorshift32
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        (self.state >> 8) as f32 / (1 << 24) as f32
+    }
+}
@@ -0,0 +1,166 @@
+//! A/B scenes, two sets of settings to switch between while tweaking.
+//!
+//! The parameters always hold the scene being edited, the other one waits
+//! here. Switching scenes swaps the two, copying puts the values of the one
+//! being edited in place of the other. Until something has been stored in it,
+//! the other scene is the same as the one being edited. The switches are
+//! picked up from whichever thread sees them first, the audio thread never
+//! waits for the other. The audio thread tells the host about the new values
+//! after each switch.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::dsp::{Params, COPY_SCENE_PARAM, LOCK_LEVELS_PARAM, LOCK_SYNC_PARAM, SCENE_PARAM};
+use crate::PARAMETERS;
+
+// index of the clear parameter, an action rather than a setting
+const CLEAR_PARAM: i32 = 15;
+
+#[derive(Default)]
+pub(crate) struct Scenes {
+    state: Mutex<State>,
+    // set when a switch changed the parameters, until the host has been told
+    changed: AtomicBool,
+}
+
+pub(crate) struct State {
+    // the scene the parameters hold, 0 for A and 1 for B
+    shown: usize,
+    // normalized values of the other scene by parameter index, swapped in
+    // place so the audio thread never allocates for it
+    other: [f32; PARAMETERS as usize],
+    // false while the other scene is the same as the one being edited
+    stored: bool,
+    // last seen value of the copy parameter, switching it on copies
+    copying: bool,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            shown: 0,
+            other: [0.0; PARAMETERS as usize],
+            stored: false,
+            copying: false,
+        }
+    }
+}
+
+/// Whether the parameter at `index` is part of a scene, the scene switches,
+/// the actions and the locks aren't.
+pub(crate) fn in_scene(params: &Params, index: i32) -> bool {
+    params.parameter(index).is_some()
+        && ![SCENE_PARAM, COPY_SCENE_PARAM, CLEAR_PARAM, LOCK_SYNC_PARAM, LOCK_LEVELS_PARAM].contains(&index)
+}
+
+impl Scenes {
+    /// Swaps or copies the scenes after a change of the scene parameters, the
+    /// change is left to the next call if another thread is busy with them.
+    pub(crate) fn update(&self, params: &Params) {
+        if let Ok(mut state) = self.state.try_lock() {
+            if state.update(params) {
+                self.changed.store(true, Ordering::Release);
+            }
+        }
+    }
+
+    /// Whether a switch changed the parameters since the last call.
+    pub(crate) fn take_changed(&self) -> bool {
+        self.changed.swap(false, Ordering::AcqRel)
+    }
+
+    /// Holds off switches while a state is being loaded, see `State::loaded`.
+    pub(crate) fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+}
+
+impl State {
+    // returns whether a switch swapped in values of the other scene
+    fn update(&mut self, params: &Params) -> bool {
+        let copying = params.copy_scene.get() > 0.5;
+        if copying && !self.copying {
+            self.store(params);
+        }
+        self.copying = copying;
+
+        let wanted = (params.scene.get() > 0.5) as usize;
+        if wanted == self.shown {
+            return false
+        }
+        self.shown = wanted;
+        if !self.stored {
+            self.store(params);
+            return false
+        }
+        for index in (0..PARAMETERS).filter(|&index| in_scene(params, index)) {
+            let editing = params.get_normalized(index);
+            params.set_normalized(index, self.other[index as usize]);
+            self.other[index as usize] = editing;
+        }
+        true
+    }
+
+    // the values of the scene being edited become the other one
+    fn store(&mut self, params: &Params) {
+        for (index, other) in self.other.iter_mut().enumerate() {
+            *other = params.get_normalized(index as i32);
+        }
+        self.stored = true;
+    }
+
+    /// Normalized values of the other scene by index, `None` while it's the same.
+    pub(crate) fn other(&self) -> Option<&[f32]> {
+        self.stored.then(|| &self.other[..])
+    }
+
+    /// Takes the loaded parameters as the scene they say they are, and the
+    /// `(index, normalized value)` pairs of the other one in their place.
+    pub(crate) fn loaded(&mut self, params: &Params, other: &[(i32, f32)]) {
+        self.shown = (params.scene.get() > 0.5) as usize;
+        self.copying = params.copy_scene.get() > 0.5;
+        self.stored = false;
+        if !other.is_empty() {
+            self.store(params);
+            for &(index, value) in other.iter().filter(|&&(index, _)| in_scene(params, index)) {
+                self.other[index as usize] = value;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn switching_swaps_and_copying_copies() {
+        let params = Params::default();
+        params.mix.set_raw(0.25);
+        // the other scene starts out the same
+        params.scene.set_raw(1.0);
+        params.scenes.update(&params);
+        assert_eq!(params.mix.get_raw(), 0.25);
+        assert!(!params.scenes.take_changed());
+
+        params.mix.set_raw(0.75);
+        params.scene.set_raw(0.0);
+        params.scenes.update(&params);
+        assert_eq!(params.mix.get_raw(), 0.25);
+        // the host is told once
+        assert!(params.scenes.take_changed());
+        assert!(!params.scenes.take_changed());
+        params.scene.set_raw(1.0);
+        params.scenes.update(&params);
+        assert_eq!(params.mix.get_raw(), 0.75);
+
+        // B copied over to A
+        params.copy_scene.set_raw(1.0);
+        params.scenes.update(&params);
+        params.scene.set_raw(0.0);
+        params.scenes.update(&params);
+        assert_eq!(params.mix.get_raw(), 0.75);
+        assert_eq!(params.scene.get_raw(), 0.0);
+    }
+}
@@ -0,0 +1,228 @@
+//! Deterministic runs of the DSP for tests.
+//!
+//! A `Simulation` feeds a synthetic signal through `process_channel` block by
+//! block the way the plugin does, applies scripted parameter changes at the
+//! start of the block they fall into and keeps the whole input and output, so
+//! tests can check properties of the result (energy, continuity, where the
+//! repeats land) rather than exact samples.
+
+use std::f32::consts::TAU;
+use std::slice;
+
+use crate::dsp::{process_channel, ChannelSettings, ChannelState, Params, Transport};
+
+pub(crate) const SAMPLE_RATE: f32 = 44_100.0;
+
+/// synthetic test signals, at full scale unless they have a level
+#[derive(Clone, Copy)]
+pub(crate) enum Signal {
+    Silence,
+    /// a constant offset
+    Dc { level: f32 },
+    /// single samples of 1.0, the first one at `offset`
+    Impulses { interval: usize, offset: usize },
+    Sine { hz: f32 },
+    /// a sine gliding exponentially from one frequency to the other over `samples`
+    Sweep { from: f32, to: f32, samples: usize },
+}
+
+impl Signal {
+    pub(crate) fn sample(&self, n: usize) -> f32 {
+        match *self {
+            Signal::Silence => 0.0,
+            Signal::Dc { level } => level,
+            Signal::Impulses { interval, offset } => {
+                if n >= offset && (n - offset) % interval.max(1) == 0 { 1.0 } else { 0.0 }
+            }
+            Signal::Sine { hz } => (TAU * hz * n as f32 / SAMPLE_RATE).sin(),
+            Signal::Sweep { from, to, samples } => {
+                // the phase is the integral of the exponentially moving frequency
+                let n = n.min(samples) as f32;
+                let ratio = (to / from).ln();
+                let phase = from * samples as f32 / ratio * ((ratio * n / samples as f32).exp() - 1.0);
+                (TAU * phase / SAMPLE_RATE).sin()
+            }
+        }
+    }
+}
+
+type Change = Box<dyn Fn(&Params)>;
+
+pub(crate) struct Simulation {
+    pub(crate) params: Params,
+    pub(crate) state: ChannelState,
+    block_size: usize,
+    // ordered by the sample they're due at
+    changes: Vec<(usize, Change)>,
+    pub(crate) input: Vec<f32>,
+    pub(crate) output: Vec<f32>,
+    // where each period started
+    period_starts: Vec<usize>,
+}
+
+impl Simulation {
+    pub(crate) fn new(params: Params, block_size: usize) -> Self {
+        Self {
+            params,
+            state: ChannelState::default(),
+            block_size: block_size.max(1),
+            changes: Vec::new(),
+            input: Vec::new(),
+            output: Vec::new(),
+            period_starts: Vec::new(),
+        }
+    }
+
+    /// Changes the parameters at the start of the block containing `sample`.
+    pub(crate) fn at(mut self, sample: usize, change: impl Fn(&Params) + 'static) -> Self {
+        let pos = self.changes.partition_point(|&(time, _)| time <= sample);
+        self.changes.insert(pos, (sample, Box::new(change)));
+        self
+    }
+
+    /// Processes `samples` more of `signal`, which is timed from the start of the simulation.
+    pub(crate) fn run(&mut self, signal: Signal, samples: usize) -> &mut Self {
+        let end = self.input.len() + samples;
+        while self.input.len() < end {
+            let start = self.input.len();
+            let len = usize::min(self.block_size, end - start);
+            let due = self.changes.iter().take_while(|&&(time, _)| time < start + len).count();
+            for (_, change) in self.changes.drain(..due) {
+                change(&self.params);
+            }
+
+            let settings = ChannelSettings::new(&self.params, SAMPLE_RATE, slice::from_ref(&self.state));
+            self.state.apply(&settings, self.params.freeze.get() > 0.5, 0, 1);
+            self.input.extend((start..start + len).map(|n| signal.sample(n)));
+            self.output.resize(start + len, 0.0);
+            process_channel(&self.params, &Transport::default(), &mut self.state,
+                &self.input[start..], &mut self.output[start..]);
+            self.state.assert_invariants();
+            // the last period started within the block, blocks longer than the
+            // periods can hide the ones before it
+            let (into_period, _) = self.state.cycle();
+            if into_period <= len {
+                self.period_starts.push(start + len - into_period);
+            }
+        }
+        self
+    }
+
+    /// Lengths of the periods finished so far.
+    pub(crate) fn periods(&self) -> Vec<usize> {
+        self.period_starts.windows(2).map(|pair| pair[1] - pair[0]).collect()
+    }
+}
+
+/// mean square of the samples
+pub(crate) fn energy(samples: &[f32]) -> f32 {
+    samples.iter().map(|sample| sample * sample).sum::<f32>() / samples.len().max(1) as f32
+}
+
+/// largest step between neighbouring samples, clicks show up as outliers
+pub(crate) fn max_jump(samples: &[f32]) -> f32 {
+    samples.windows(2).map(|pair| (pair[1] - pair[0]).abs()).fold(0.0, f32::max)
+}
+
+/// where the samples are louder than `threshold`
+pub(crate) fn peaks(samples: &[f32], threshold: f32) -> Vec<usize> {
+    samples.iter().enumerate().filter(|(_, sample)| sample.abs() > threshold).map(|(n, _)| n).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(period: f32) -> Params {
+        let params = Params::default();
+        params.period.set(period);
+        params.capture_len.set(period);
+        params
+    }
+
+    #[test]
+    fn repeats_lag_one_period() {
+        let params = params(100.0);
+        params.edge_fade.set(0.0);
+        let mut sim = Simulation::new(params, 50)
+            .at(1000, |params| params.period.set(150.0));
+        sim.run(Signal::Impulses { interval: 300, offset: 10 }, 2000);
+
+        // without feedback each impulse comes back once, a period later
+        let repeats = peaks(&sim.output, 0.5);
+        assert_eq!(&repeats[..4], &[110, 410, 710, 1010]);
+        // the longer period starts at the boundary after the change, the short
+        // snapshot captured before it loops within it, then the lag follows
+        assert_eq!(&repeats[4..], &[1110, 1360, 1660, 1960]);
+        assert_eq!(sim.periods(), [vec![100; 10], vec![150; 6]].concat());
+    }
+
+    #[test]
+    fn blocks_longer_than_period_swap_on_time() {
+        let run = |block_size| {
+            let params = params(100.0);
+            params.edge_fade.set(0.0);
+            let mut sim = Simulation::new(params, block_size);
+            sim.run(Signal::Impulses { interval: 300, offset: 10 }, 2000);
+            peaks(&sim.output, 0.5)
+        };
+        // several periods to a block, each one swaps on its own sample
+        assert_eq!(run(512), run(50));
+        assert_eq!(&run(512)[..3], &[110, 410, 710]);
+    }
+
+    #[test]
+    fn fades_keep_repeats_continuous() {
+        let sweep = Signal::Sweep { from: 100.0, to: 2000.0, samples: 20_000 };
+        // snapshots shorter than the period loop within it, the seam cuts the sweep mid-cycle
+        let params = || {
+            let params = params(1280.0);
+            params.capture_len.set(1000.0);
+            params
+        };
+        // the swap cuts the outgoing snapshot mid-way, the tail covers that
+        let mut faded = Simulation::new(params(), 256)
+            .at(0, |params| params.tail_crossfade.set(5.0));
+        faded.run(sweep, 20_000);
+        let mut hard = Simulation::new(params(), 256)
+            .at(0, |params| params.edge_fade.set(0.0));
+        hard.run(sweep, 20_000);
+
+        let limit = max_jump(&faded.input) * 1.1;
+        assert!(max_jump(&faded.output) < limit, "{} >= {}", max_jump(&faded.output), limit);
+        assert!(max_jump(&hard.output) > limit);
+    }
+
+    #[test]
+    fn feedback_decays_after_input_stops() {
+        let params = params(1000.0);
+        params.feedback.set(0.5);
+        let mut sim = Simulation::new(params, 128);
+        sim.run(Signal::Sine { hz: 441.0 }, 1000).run(Signal::Silence, 5000);
+
+        let energies = sim.output.chunks(1000).skip(1).map(energy).collect::<Vec<_>>();
+        assert!(energies[0] > 0.4, "{:?}", energies);
+        for pair in energies.windows(2) {
+            // the level halves each time around
+            let ratio = pair[1] / pair[0];
+            assert!(ratio > 0.15 && ratio < 0.3, "{:?}", energies);
+        }
+    }
+
+    #[test]
+    fn output_independent_of_block_size() {
+        let run = |block_size| {
+            let params = params(1024.0);
+            params.feedback.set(0.7);
+            params.playback_rate.set(1.5);
+            let mut sim = Simulation::new(params, block_size)
+                .at(3000, |params| params.playback_rate.set(0.75));
+            sim.run(Signal::Sweep { from: 50.0, to: 5000.0, samples: 8000 }, 8000);
+            sim.output
+        };
+        let small = run(32);
+        let large = run(1024);
+        // the change lands at the start of a different block in each, compare up to the earlier one
+        assert_eq!(&small[..2048], &large[..2048]);
+    }
+}
@@ -0,0 +1,131 @@
+//! Time stretched playback of the snapshot, the speed changes without the pitch.
+//!
+//! Windows of the snapshot are overlap-added half a window apart (WSOLA). The
+//! play head moves at the playback rate, each window starts near it, wherever
+//! within a small range the snapshot looks the most like the continuation of
+//! the window before, so the overlapping windows stay in phase instead of
+//! cancelling out.
+
+use std::f32::consts::TAU;
+
+use crate::granular::interpolate;
+
+/// length of the windows, long enough for low notes and short enough not to smear attacks
+pub(crate) const STRETCH_WINDOW_MS: f32 = 40.0;
+// how far from the play head a window can start, as a share of the window
+const SEARCH_RANGE: f32 = 0.25;
+// the search tries starts this many samples apart and compares every this many samples
+const SEARCH_STEP: usize = 4;
+
+#[derive(Clone, Copy)]
+struct Window {
+    // samples into the snapshot
+    position: f32,
+    age: f32,
+}
+
+pub(crate) struct Stretcher {
+    windows: [Window; 2],
+    len: f32,
+    // samples until the next window starts
+    until_next: f32,
+    // the window started next, the other one is halfway through
+    next: usize,
+}
+
+impl Default for Stretcher {
+    fn default() -> Self {
+        Self {
+            windows: [Window { position: 0.0, age: f32::INFINITY }; 2],
+            len: 2.0,
+            until_next: 0.0,
+            next: 0,
+        }
+    }
+}
+
+impl Stretcher {
+    /// Sets the length of the windows in samples.
+    pub(crate) fn set_window(&mut self, len: f32) {
+        self.len = len.max(2.0);
+    }
+
+    /// The next output sample reading `buffer`, new windows start around `head`
+    /// (normalized) and each one reads at `pitch` (a playback rate ratio).
+    pub(crate) fn next(&mut self, buffer: &[f32], head: f32, pitch: f32) -> f32 {
+        let buffer_len = buffer.len() as f32;
+        self.until_next -= 1.0;
+        if self.until_next <= 0.0 {
+            self.until_next += self.len / 2.0;
+            let target = head * buffer_len;
+            let previous = self.windows[1 - self.next];
+            let position = if previous.age < self.len {
+                // where the previous window goes on from, the new one fades in over that
+                let natural = previous.position + previous.age * pitch;
+                best_match(buffer, natural, target, self.len, pitch)
+            } else {
+                target
+            };
+            self.windows[self.next] = Window { position, age: 0.0 };
+            self.next = 1 - self.next;
+        }
+
+        let len = self.len;
+        let mut sum = 0.0;
+        for window in self.windows.iter_mut().filter(|window| window.age < len) {
+            // hann windows half a window apart add up to one
+            let gain = 0.5 - 0.5 * (TAU * window.age / len).cos();
+            sum += interpolate(buffer, (window.position + window.age * pitch).rem_euclid(buffer_len)) * gain;
+            window.age += 1.0;
+        }
+        sum
+    }
+}
+
+// the start around `target` where the overlap with the window going on from
+// `natural` correlates best
+fn best_match(buffer: &[f32], natural: f32, target: f32, window: f32, pitch: f32) -> f32 {
+    let len = buffer.len() as f32;
+    let at = |position: f32| buffer[usize::min(position.rem_euclid(len) as usize, buffer.len() - 1)];
+    let overlap = (window / 2.0) as usize;
+    let range = (window * SEARCH_RANGE) as isize;
+    let (_, start) = (-range..=range)
+        .step_by(SEARCH_STEP)
+        .map(|shift| {
+            let start = target + shift as f32;
+            let correlation = (0..overlap)
+                .step_by(SEARCH_STEP)
+                .map(|i| at(start + i as f32 * pitch) * at(natural + i as f32 * pitch))
+                .sum::<f32>();
+            (correlation, start)
+        })
+        .fold((f32::NEG_INFINITY, target), |best, candidate| if candidate.0 > best.0 { candidate } else { best });
+    start.rem_euclid(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::energy;
+
+    #[test]
+    fn slowing_down_keeps_pitch() {
+        // a hundred whole cycles so the snapshot loops seamlessly
+        let buffer = (0..10_000).map(|i| (TAU * i as f32 / 100.0).sin()).collect::<Vec<_>>();
+        let mut stretcher = Stretcher::default();
+        stretcher.set_window(1000.0);
+        let out = (0..20_000)
+            .map(|i| stretcher.next(&buffer, i as f32 * 0.5 / 10_000.0, 1.0))
+            .skip(1000)
+            .collect::<Vec<_>>();
+
+        // half the speed, still two crossings every hundred samples
+        let crossings = out.windows(2).filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0)).count();
+        let expected = out.len() / 50;
+        assert!(crossings.abs_diff(expected) < expected / 50, "{} crossings", crossings);
+        // the windows stay in phase, no dips in level
+        for chunk in out.chunks(500) {
+            assert!((energy(chunk) - 0.5).abs() < 0.05, "{}", energy(chunk));
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! Handing values from the audio thread to any other thread without waiting.
+//!
+//! Of the three slots, the writer owns one, the reader owns one and the third
+//! holds the latest published value. Publishing swaps the written slot with the
+//! middle one, reading swaps the middle one in if it's newer than what the
+//! reader has. Neither side ever waits for the other or allocates, the reader
+//! just keeps seeing the previous value until a newer one is published.
+
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+// set alongside the index of the middle slot while the reader hasn't taken it
+const FRESH: usize = 4;
+
+pub(crate) struct TripleBuffer<T> {
+    slots: [UnsafeCell<T>; 3],
+    // index of the middle slot, with `FRESH`
+    middle: AtomicUsize,
+    // the slots owned by each side, the locks only keep several writers (or
+    // readers) apart, the two sides never contend for the same one
+    writer: Mutex<usize>,
+    reader: Mutex<usize>,
+}
+
+// each slot is only ever accessed by whoever owns its index
+unsafe impl<T: Send> Sync for TripleBuffer<T> {}
+
+impl<T> TripleBuffer<T> {
+    pub(crate) fn new(init: impl Fn() -> T) -> Self {
+        Self {
+            slots: [UnsafeCell::new(init()), UnsafeCell::new(init()), UnsafeCell::new(init())],
+            middle: AtomicUsize::new(1),
+            writer: Mutex::new(0),
+            reader: Mutex::new(2),
+        }
+    }
+
+    // called on the audio thread, `write` fills in the slot that gets published,
+    // it holds whatever was published two times before
+    pub(crate) fn publish(&self, write: impl FnOnce(&mut T)) {
+        if let Ok(mut idx) = self.writer.try_lock() {
+            // the writer owns this slot until it's swapped into the middle
+            write(unsafe { &mut *self.slots[*idx].get() });
+            let middle = self.middle.swap(*idx | FRESH, Ordering::AcqRel);
+            *idx = middle & !FRESH;
+        }
+    }
+
+    /// Reads the latest published value.
+    pub(crate) fn read<R>(&self, read: impl FnOnce(&T) -> R) -> R {
+        let mut idx = self.reader.lock().unwrap();
+        if self.middle.load(Ordering::Acquire) & FRESH != 0 {
+            *idx = self.middle.swap(*idx, Ordering::AcqRel) & !FRESH;
+        }
+        // the reader owns this slot until it's swapped back into the middle
+        read(unsafe { &*self.slots[*idx].get() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn reader_sees_latest_complete_value() {
+        let buffer = TripleBuffer::new(|| [0; 64]);
+        assert_eq!(buffer.read(|value| value[0]), 0);
+        buffer.publish(|value| *value = [1; 64]);
+        buffer.publish(|value| *value = [2; 64]);
+        assert_eq!(buffer.read(|value| value[0]), 2);
+        // nothing new, the same value again
+        assert_eq!(buffer.read(|value| value[0]), 2);
+
+        // values are never seen half written
+        let buffer = Arc::new(TripleBuffer::new(|| [0; 64]));
+        let writer = Arc::clone(&buffer);
+        let handle = thread::spawn(move || {
+            for n in 0..10_000 {
+                writer.publish(|value| *value = [n; 64]);
+            }
+        });
+        let mut last = 0;
+        while !handle.is_finished() {
+            let (first, whole) = buffer.read(|value| (value[0], value.iter().all(|&n| n == value[0])));
+            assert!(whole);
+            assert!(first >= last);
+            last = first;
+        }
+        handle.join().unwrap();
+        assert_eq!(buffer.read(|value| value[0]), 9_999);
+    }
+}
@@ -0,0 +1,149 @@
+//! Values published by the audio thread for editors and monitoring tools.
+//!
+//! Single values are atomics, the snapshot overview goes through a triple
+//! buffer so readers always get a complete one without the audio thread ever
+//! waiting for them.
+
+use vst::util::AtomicFloat;
+
+use crate::triple_buffer::TripleBuffer;
+use crate::PARAMETERS;
+
+/// number of parts the finest level of the snapshot overview splits the snapshot into
+pub const OVERVIEW_LEN: usize = 1024;
+/// levels of the snapshot overview, down to the one with a single part
+pub const OVERVIEW_LEVELS: usize = 11;
+
+/// The playing snapshot as an editor draws it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SnapshotOverview {
+    /// lowest and highest sample (at full scale) of equal parts of the snapshot,
+    /// the first level has at most `OVERVIEW_LEN` parts and each next one merges
+    /// pairs of the one before, all of them are empty while there's no snapshot
+    pub levels: Vec<Vec<(f32, f32)>>,
+    /// length of the snapshot in samples, zero while there's none
+    pub len: usize,
+    /// where playback is in the snapshot, `0 ..= 1`
+    pub playhead: f32,
+}
+
+impl SnapshotOverview {
+    // with room for the largest overview, so copying one in never allocates
+    pub(crate) fn new() -> Self {
+        Self {
+            levels: (0..OVERVIEW_LEVELS).map(|level| Vec::with_capacity(OVERVIEW_LEN >> level)).collect(),
+            len: 0,
+            playhead: 0.0,
+        }
+    }
+
+    /// The coarsest level with at least `parts` parts (or the finest one), to
+    /// draw the snapshot `parts` pixels wide.
+    pub fn peaks(&self, parts: usize) -> &[(f32, f32)] {
+        self.levels.iter().rev()
+            .find(|level| level.len() >= parts)
+            .or_else(|| self.levels.first())
+            .map_or(&[], Vec::as_slice)
+    }
+}
+
+pub struct Visualization {
+    // effective value of each parameter, normalized like the parameter itself,
+    // NaN for the ones nothing modulates
+    modulation: Vec<AtomicFloat>,
+    snapshot: TripleBuffer<SnapshotOverview>,
+}
+
+impl Default for Visualization {
+    fn default() -> Self {
+        Self {
+            modulation: (0..PARAMETERS).map(|_| AtomicFloat::new(f32::NAN)).collect(),
+            // publishing never allocates
+            snapshot: TripleBuffer::new(SnapshotOverview::new),
+        }
+    }
+}
+
+impl Visualization {
+    /// Where the parameter at `index` effectively is after all modulation, in the
+    /// same `0 ..= 1` range as the parameter. `None` if nothing modulates it.
+    pub fn modulation(&self, index: i32) -> Option<f32> {
+        let value = self.modulation.get(index as usize)?.get();
+        if value.is_nan() { None } else { Some(value) }
+    }
+
+    pub(crate) fn set_modulation(&self, index: i32, value: f32) {
+        if let Some(modulation) = self.modulation.get(index as usize) {
+            modulation.set(value.clamp(0.0, 1.0));
+        }
+    }
+
+    /// The latest overview of the playing snapshot.
+    pub fn snapshot(&self) -> SnapshotOverview {
+        self.snapshot.read(SnapshotOverview::clone)
+    }
+
+    // called on the audio thread, `levels` as made by `overview_levels`
+    pub(crate) fn publish_snapshot(&self, levels: &[Vec<(f32, f32)>], len: usize, playhead: f32) {
+        self.snapshot.publish(|overview| {
+            for (level, peaks) in overview.levels.iter_mut().zip(levels) {
+                level.clear();
+                level.extend_from_slice(&peaks[..peaks.len().min(level.capacity())]);
+            }
+            overview.len = len;
+            overview.playhead = playhead;
+        });
+    }
+}
+
+// the overview levels of `buffer` times `gain` into `levels`, which keep their
+// capacity as long as they start out like `SnapshotOverview::new` made them
+pub(crate) fn overview_levels(buffer: &[f32], gain: f32, levels: &mut Vec<Vec<(f32, f32)>>) {
+    levels.resize_with(OVERVIEW_LEVELS, Vec::new);
+    let parts = buffer.len().min(OVERVIEW_LEN);
+    let finest = &mut levels[0];
+    finest.clear();
+    for part in 0..parts {
+        let part = &buffer[part * buffer.len() / parts..(part + 1) * buffer.len() / parts];
+        let (low, high) = part.iter().fold((f32::INFINITY, f32::NEG_INFINITY), |(low, high), &sample| {
+            (low.min(sample), high.max(sample))
+        });
+        finest.push((low * gain, high * gain));
+    }
+    for level in 1..OVERVIEW_LEVELS {
+        let (finer, coarser) = levels.split_at_mut(level);
+        let coarser = &mut coarser[0];
+        coarser.clear();
+        coarser.extend(finer[level - 1].chunks(2).map(|pair| {
+            pair.iter().fold((f32::INFINITY, f32::NEG_INFINITY), |(low, high), &(part_low, part_high)| {
+                (low.min(part_low), high.max(part_high))
+            })
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overview_levels_keep_peaks_of_each_part() {
+        let mut buffer = vec![0.0; 4096];
+        buffer[1] = 0.25;
+        buffer[4000] = -0.125;
+        let mut levels = SnapshotOverview::new().levels;
+        overview_levels(&buffer, 4.0, &mut levels);
+        assert_eq!(levels.iter().map(Vec::len).collect::<Vec<_>>(), [1024, 512, 256, 128, 64, 32, 16, 8, 4, 2, 1]);
+        assert_eq!(levels[0][0], (0.0, 1.0));
+        assert_eq!(levels[0][1000], (-0.5, 0.0));
+        assert_eq!(levels[10][0], (-0.5, 1.0));
+
+        let visualization = Visualization::default();
+        assert_eq!(visualization.snapshot().peaks(100), &[]);
+        visualization.publish_snapshot(&levels, buffer.len(), 0.5);
+        let overview = visualization.snapshot();
+        assert_eq!((overview.len, overview.playhead), (4096, 0.5));
+        assert_eq!(overview.peaks(100).len(), 128);
+        assert_eq!(overview.peaks(4096).len(), 1024);
+    }
+}
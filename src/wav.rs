@@ -0,0 +1,154 @@
+//! Minimal RIFF/WAVE reading and writing, just enough to get audio in and out
+//! of the plugin without pulling in a decoding library.
+
+use std::io::{self, Read, Write};
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_FLOAT: u16 = 3;
+const FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+/// decoded audio, one buffer of samples per channel
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Wav {
+    pub sample_rate: u32,
+    pub channels: Vec<Vec<f32>>,
+}
+
+impl Wav {
+    /// number of samples in each channel
+    pub fn len(&self) -> usize {
+        self.channels.first().map_or(0, |chan| chan.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// a chunk other than the format and the audio, (id, body)
+pub type Chunk = ([u8; 4], Vec<u8>);
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_u16(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Decodes integer PCM (8, 16, 24 or 32 bit) or float (32 or 64 bit) audio.
+pub fn read(reader: impl Read) -> io::Result<Wav> {
+    read_with_chunks(reader).map(|(wav, _)| wav)
+}
+
+/// Like `read`, also returning the other chunks.
+pub fn read_with_chunks(mut reader: impl Read) -> io::Result<(Wav, Vec<Chunk>)> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(invalid("not a RIFF/WAVE file"));
+    }
+
+    // (format, channels, sample rate, bits per sample)
+    let mut format = None;
+    let mut data = None;
+    let mut chunks = Vec::new();
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let id = &bytes[pos..pos + 4];
+        let size = read_u32(&bytes[pos + 4..]) as usize;
+        let body = &bytes[pos + 8..usize::min(pos + 8 + size, bytes.len())];
+        match id {
+            b"fmt " if body.len() >= 16 => {
+                let mut tag = read_u16(body);
+                if tag == FORMAT_EXTENSIBLE && body.len() >= 26 {
+                    // the sub-format GUID starts with the actual format tag
+                    tag = read_u16(&body[24..]);
+                }
+                format = Some((tag, read_u16(&body[2..]), read_u32(&body[4..]), read_u16(&body[14..])));
+            }
+            b"data" => data = Some(body),
+            _ => chunks.push(([id[0], id[1], id[2], id[3]], body.to_vec())),
+        }
+        // chunks are padded to an even size
+        pos += 8 + size + (size & 1);
+    }
+
+    let (tag, channel_count, sample_rate, bits) = format.ok_or_else(|| invalid("missing fmt chunk"))?;
+    let data = data.ok_or_else(|| invalid("missing data chunk"))?;
+    if channel_count == 0 {
+        return Err(invalid("no channels"));
+    }
+
+    let decode: fn(&[u8]) -> f32 = match (tag, bits) {
+        (FORMAT_PCM, 8) => |b| (b[0] as f32 - 128.0) / 128.0,
+        (FORMAT_PCM, 16) => |b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32_768.0,
+        (FORMAT_PCM, 24) => |b| (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8_388_608.0,
+        (FORMAT_PCM, 32) => |b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2_147_483_648.0,
+        (FORMAT_FLOAT, 32) => |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+        (FORMAT_FLOAT, 64) => |b| f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]) as f32,
+        _ => return Err(invalid("unsupported sample format")),
+    };
+
+    let sample_size = bits as usize / 8;
+    let frame_size = sample_size * channel_count as usize;
+    let mut channels = vec![Vec::with_capacity(data.len() / frame_size); channel_count as usize];
+    for frame in data.chunks_exact(frame_size) {
+        frame.chunks_exact(sample_size)
+            .zip(&mut channels)
+            .for_each(|(sample, chan)| chan.push(decode(sample)));
+    }
+
+    Ok((Wav { sample_rate, channels }, chunks))
+}
+
+/// Encodes the audio as 32 bit float samples.
+pub fn write(writer: impl Write, wav: &Wav) -> io::Result<()> {
+    write_with_chunks(writer, wav, &[])
+}
+
+/// Like `write`, with the given chunks after the audio.
+pub fn write_with_chunks(mut writer: impl Write, wav: &Wav, chunks: &[Chunk]) -> io::Result<()> {
+    let channel_count = wav.channels.len() as u16;
+    let block_align = channel_count * 4;
+    let data_size = (wav.len() * block_align as usize) as u32;
+    let chunks_size = chunks.iter().map(|(_, body)| 8 + body.len() + (body.len() & 1)).sum::<usize>() as u32;
+
+    let mut bytes = Vec::with_capacity(44 + data_size as usize + chunks_size as usize);
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_size + chunks_size).to_le_bytes());
+    bytes.extend_from_slice(b"WAVE");
+
+    bytes.extend_from_slice(b"fmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    bytes.extend_from_slice(&FORMAT_FLOAT.to_le_bytes());
+    bytes.extend_from_slice(&channel_count.to_le_bytes());
+    bytes.extend_from_slice(&wav.sample_rate.to_le_bytes());
+    bytes.extend_from_slice(&(wav.sample_rate * block_align as u32).to_le_bytes());
+    bytes.extend_from_slice(&block_align.to_le_bytes());
+    bytes.extend_from_slice(&32u16.to_le_bytes());
+
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_size.to_le_bytes());
+    for frame in 0..wav.len() {
+        for chan in &wav.channels {
+            bytes.extend_from_slice(&chan[frame].to_le_bytes());
+        }
+    }
+    for (id, body) in chunks {
+        bytes.extend_from_slice(id);
+        bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(body);
+        // padded to an even size
+        if body.len() & 1 == 1 {
+            bytes.push(0);
+        }
+    }
+
+    writer.write_all(&bytes)
+}